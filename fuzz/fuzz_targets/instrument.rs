@@ -0,0 +1,13 @@
+//! `cargo fuzz run instrument`: instruments modules generated by `wasm-smith`
+//! and runs them next to the original, see `tests/common/generated.rs`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    common::generated::check(data);
+});
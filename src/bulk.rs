@@ -0,0 +1,371 @@
+//! Recording of the instructions that write whole ranges of a memory or a
+//! table: `memory.fill`, `memory.copy`, `memory.init`, `table.fill`,
+//! `table.copy`, `table.init` and `table.grow`.
+//!
+//! The instruction is followed by a call of a helper, added for every
+//! memory and table the module writes this way, that records the written
+//! range after the fact: the bytes of a memory, the null flags of a table.
+//! Like syscall records they are neither sampled nor watched. With a ring
+//! buffer, a range too long for the ring is recorded without its contents
+//! and the state reconstructed from the trace is incomplete.
+
+use std::collections::HashMap;
+
+use walrus::{
+    ir::{BinaryOp, Instr, InstrLocId, MemArg, StoreKind, Visitor},
+    FunctionBuilder, FunctionId, GlobalId, InstrSeqBuilder, LocalId, MemoryId, Module, TableId,
+    ValType,
+};
+
+use crate::runtime::Runtime;
+
+/// Record code of a memory range write.
+const MEMORY_CODE: i32 = 0x1A;
+/// Code, opcode, address, length and the recorded flag.
+const MEMORY_HEADER_LEN: i32 = 11;
+/// Record code of a table range write.
+const TABLE_CODE: i32 = 0x1C;
+/// Code, opcode, table idx, element index, count and the recorded flag.
+const TABLE_HEADER_LEN: i32 = 15;
+
+/// Opcodes of the instructions behind the `0xFC` prefix.
+pub(crate) const MEMORY_INIT: u8 = 8;
+pub(crate) const MEMORY_COPY: u8 = 10;
+pub(crate) const MEMORY_FILL: u8 = 11;
+pub(crate) const TABLE_INIT: u8 = 12;
+pub(crate) const TABLE_COPY: u8 = 14;
+pub(crate) const TABLE_GROW: u8 = 15;
+pub(crate) const TABLE_FILL: u8 = 17;
+
+/// The helpers and the scratch locals the generator saves the operands to.
+#[derive(Debug, Clone)]
+pub(crate) struct Bulk {
+    /// `(opcode, addr, len)` helper of each written memory.
+    pub(crate) memories: HashMap<MemoryId, FunctionId>,
+    /// `(opcode, index, count)` helper of each written table.
+    pub(crate) tables: HashMap<TableId, FunctionId>,
+    /// The destination, the length and the result of `table.grow`.
+    pub(crate) locals: [LocalId; 3],
+}
+
+/// Adds a helper for every memory and table written by a bulk instruction
+/// of the original functions `funcs`.
+pub(crate) fn add(
+    module: &mut Module,
+    funcs: &[FunctionId],
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    runtime: &Runtime,
+) -> Bulk {
+    let mut written = Written::default();
+    for (_, func) in module
+        .funcs
+        .iter_local()
+        .filter(|(id, _)| funcs.contains(id))
+    {
+        walrus::ir::dfs_in_order(&mut written, func, func.entry_block());
+    }
+    let locals = [(); 3].map(|_| module.locals.add(ValType::I32));
+    let helper = Helper {
+        trace_mem_id,
+        mem_pointer,
+        opcode: module.locals.add(ValType::I32),
+        start: module.locals.add(ValType::I32),
+        len: module.locals.add(ValType::I32),
+        recorded: module.locals.add(ValType::I32),
+        total: module.locals.add(ValType::I32),
+        i: module.locals.add(ValType::I32),
+    };
+    let memories = written
+        .memories
+        .into_iter()
+        .map(|memory| (memory, helper.memory(module, memory, runtime)))
+        .collect();
+    let tables = written
+        .tables
+        .into_iter()
+        .map(|table| (table, helper.table(module, table, runtime)))
+        .collect();
+    Bulk {
+        memories,
+        tables,
+        locals,
+    }
+}
+
+/// The memories and tables written by bulk instructions, in the order they
+/// are first written, so the helpers are added in a stable order.
+#[derive(Default)]
+struct Written {
+    memories: Vec<MemoryId>,
+    tables: Vec<TableId>,
+}
+
+impl Visitor<'_> for Written {
+    fn visit_instr(&mut self, instr: &Instr, _: &InstrLocId) {
+        let (memory, table) = match instr {
+            Instr::MemoryFill(fill) => (Some(fill.memory), None),
+            Instr::MemoryCopy(copy) => (Some(copy.dst), None),
+            Instr::MemoryInit(init) => (Some(init.memory), None),
+            Instr::TableFill(fill) => (None, Some(fill.table)),
+            Instr::TableCopy(copy) => (None, Some(copy.dst)),
+            Instr::TableInit(init) => (None, Some(init.table)),
+            Instr::TableGrow(grow) => (None, Some(grow.table)),
+            _ => (None, None),
+        };
+        if let Some(memory) = memory.filter(|m| !self.memories.contains(m)) {
+            self.memories.push(memory);
+        }
+        if let Some(table) = table.filter(|t| !self.tables.contains(t)) {
+            self.tables.push(table);
+        }
+    }
+}
+
+/// Params and locals of the helpers.
+struct Helper {
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    opcode: LocalId,
+    /// Address or first element index.
+    start: LocalId,
+    /// Length in bytes or number of elements.
+    len: LocalId,
+    recorded: LocalId,
+    total: LocalId,
+    i: LocalId,
+}
+
+impl Helper {
+    /// ```wasm
+    /// ;; with a ring buffer: only record the bytes if they fit into it
+    /// local.get $len
+    /// i32.const ;; ring size - 2 - 11
+    /// i32.le_u
+    /// local.set $recorded
+    /// i32.const 11
+    /// local.get $len
+    /// i32.const 0
+    /// local.get $recorded
+    /// select
+    /// i32.add
+    /// local.set $total
+    /// ;; with a ring buffer: wrap early if the record does not fit
+    /// ;; code, opcode, address, length and $recorded at $mem_pointer
+    /// local.get $recorded
+    /// if
+    ///     global.get $mem_pointer
+    ///     i32.const 11
+    ///     i32.add
+    ///     local.get $addr
+    ///     local.get $len
+    ///     memory.copy $trace_mem $memory
+    /// end
+    /// local.get $total
+    /// call $advance ;; or the inline mem_pointer increment
+    /// ```
+    fn memory(&self, module: &mut Module, memory: MemoryId, runtime: &Runtime) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32; 3], &[]);
+        let mut body = builder.func_body();
+        self.reserve(&mut body, runtime, MEMORY_HEADER_LEN, 1);
+        body.global_get(self.mem_pointer)
+            .i32_const(MEMORY_CODE)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(0),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.opcode)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(1),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.start)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                byte_arg(2),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.len)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                byte_arg(6),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.recorded)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(10),
+            );
+        body.local_get(self.recorded).if_else(
+            None,
+            |then| {
+                then.global_get(self.mem_pointer)
+                    .i32_const(MEMORY_HEADER_LEN)
+                    .binop(BinaryOp::I32Add)
+                    .local_get(self.start)
+                    .local_get(self.len)
+                    .memory_copy(memory, self.trace_mem_id);
+            },
+            |_| {},
+        );
+        body.local_get(self.total);
+        runtime.commit(&mut body, self.mem_pointer);
+        builder.finish(vec![self.opcode, self.start, self.len], &mut module.funcs)
+    }
+
+    /// ```wasm
+    /// ;; $recorded and $total like for a memory, with 4 bytes per element
+    /// ;; code, opcode, table idx, index, count and $recorded at $mem_pointer
+    /// local.get $recorded
+    /// if
+    ///     ;; for $i below $count
+    ///         global.get $mem_pointer
+    ///         local.get $i
+    ///         i32.const 4
+    ///         i32.mul
+    ///         i32.add
+    ///         local.get $index
+    ///         local.get $i
+    ///         i32.add
+    ///         table.get $table
+    ///         ref.is_null
+    ///         i32.store $trace_mem offset=15
+    /// end
+    /// local.get $total
+    /// call $advance ;; or the inline mem_pointer increment
+    /// ```
+    fn table(&self, module: &mut Module, table: TableId, runtime: &Runtime) -> FunctionId {
+        let table_index = module
+            .tables
+            .iter()
+            .position(|t| t.id() == table)
+            .expect("table of the module") as i32;
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32; 3], &[]);
+        let mut body = builder.func_body();
+        self.reserve(&mut body, runtime, TABLE_HEADER_LEN, 4);
+        body.global_get(self.mem_pointer)
+            .i32_const(TABLE_CODE)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(0),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.opcode)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(1),
+            )
+            .global_get(self.mem_pointer)
+            .i32_const(table_index)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                byte_arg(2),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.start)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                byte_arg(6),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.len)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                byte_arg(10),
+            )
+            .global_get(self.mem_pointer)
+            .local_get(self.recorded)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(14),
+            );
+        body.local_get(self.recorded).if_else(
+            None,
+            |then| {
+                then.i32_const(0).local_set(self.i);
+                then.block(None, |done| {
+                    let done_id = done.id();
+                    done.loop_(None, |next| {
+                        let next_id = next.id();
+                        next.local_get(self.i)
+                            .local_get(self.len)
+                            .binop(BinaryOp::I32GeU)
+                            .br_if(done_id)
+                            .global_get(self.mem_pointer)
+                            .local_get(self.i)
+                            .i32_const(4)
+                            .binop(BinaryOp::I32Mul)
+                            .binop(BinaryOp::I32Add)
+                            .local_get(self.start)
+                            .local_get(self.i)
+                            .binop(BinaryOp::I32Add)
+                            .table_get(table)
+                            .ref_is_null()
+                            .store(
+                                self.trace_mem_id,
+                                StoreKind::I32 { atomic: false },
+                                MemArg {
+                                    align: 4,
+                                    offset: TABLE_HEADER_LEN as u32,
+                                },
+                            )
+                            .local_get(self.i)
+                            .i32_const(1)
+                            .binop(BinaryOp::I32Add)
+                            .local_set(self.i)
+                            .br(next_id);
+                    });
+                });
+            },
+            |_| {},
+        );
+        body.local_get(self.total);
+        runtime.commit(&mut body, self.mem_pointer);
+        builder.finish(vec![self.opcode, self.start, self.len], &mut module.funcs)
+    }
+
+    /// Sets `$recorded` and the record length in `$total`, then makes room
+    /// for the record in a ring buffer. Without one the range is always
+    /// recorded.
+    fn reserve(&self, body: &mut InstrSeqBuilder, runtime: &Runtime, header_len: i32, scale: i32) {
+        match runtime.ring_size() {
+            Some(size) => {
+                let max_len = (size as i32 - 2 - header_len).max(0) / scale;
+                body.local_get(self.len)
+                    .i32_const(max_len)
+                    .binop(BinaryOp::I32LeU)
+                    .local_set(self.recorded);
+            }
+            None => {
+                body.i32_const(1).local_set(self.recorded);
+            }
+        }
+        body.i32_const(header_len)
+            .local_get(self.len)
+            .i32_const(scale)
+            .binop(BinaryOp::I32Mul)
+            .i32_const(0)
+            .local_get(self.recorded)
+            .select(None)
+            .binop(BinaryOp::I32Add)
+            .local_set(self.total);
+        runtime.reserve(body, self.mem_pointer, self.total);
+    }
+}
+
+fn byte_arg(offset: u32) -> MemArg {
+    MemArg { align: 1, offset }
+}
@@ -0,0 +1,356 @@
+//! Reconstruction of the call tree from the call, entry and return events of
+//! a decoded trace.
+//!
+//! Imports that call back into exports nest host frames and Wasm frames, and
+//! a trap leaves frames without their return. The tree makes both explicit:
+//! every frame knows the events that opened and closed it, and everything
+//! that does not add up is reported as an [`Issue`].
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use crate::decode::Event;
+
+/// What a frame of the call tree stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// The host called a function export (Wasm frame).
+    Export { export: u32 },
+    /// A function of the module runs (Wasm frame).
+    Function { func: u32 },
+    /// A direct call, the callee is entered inside it.
+    Call { func: u32 },
+    /// An indirect call, the callee is entered inside it unless it is an
+    /// import.
+    CallIndirect { type_index: u32, elem_index: u32 },
+    /// A call of an imported function: the host runs and may call exports
+    /// (host frame).
+    Import { func: u32 },
+}
+
+impl FrameKind {
+    /// Whether the host runs in this frame.
+    pub fn is_host(&self) -> bool {
+        matches!(self, FrameKind::Import { .. })
+    }
+}
+
+impl Display for FrameKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameKind::Export { export } => write!(f, "export {}", export),
+            FrameKind::Function { func } => write!(f, "function {}", func),
+            FrameKind::Call { func } => write!(f, "call {}", func),
+            FrameKind::CallIndirect {
+                type_index,
+                elem_index,
+            } => write!(f, "call_indirect (type {}) [{}]", type_index, elem_index),
+            FrameKind::Import { func } => write!(f, "host import {}", func),
+        }
+    }
+}
+
+/// A node of the call tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Index of the event that opened the frame.
+    pub start: usize,
+    /// Index of the event that closed the frame, `None` if it never closed.
+    pub end: Option<usize>,
+    pub children: Vec<Frame>,
+}
+
+/// Something in the trace that does not fit a well nested call tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The frame was left without its closing event, because of a trap or
+    /// because the trace ends inside it. `at` is the event that unwound it,
+    /// `None` at the end of the trace.
+    Unterminated {
+        kind: FrameKind,
+        start: usize,
+        at: Option<usize>,
+    },
+    /// A return or call end without an open frame it could close, e.g. at
+    /// the start of a ring buffer.
+    Unmatched { event: usize },
+    /// The function entered after a direct call is not the called one.
+    WrongCallee {
+        event: usize,
+        called: u32,
+        entered: u32,
+    },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &|kind| kind.to_string())
+    }
+}
+
+impl Issue {
+    /// Writes the issue, naming frame kinds through `kind_name`.
+    pub fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        kind_name: &dyn Fn(&FrameKind) -> String,
+    ) -> fmt::Result {
+        match self {
+            Issue::Unterminated {
+                kind,
+                start,
+                at: Some(at),
+            } => write!(
+                f,
+                "{} entered at event {} was left at event {} without returning",
+                kind_name(kind),
+                start,
+                at
+            ),
+            Issue::Unterminated {
+                kind,
+                start,
+                at: None,
+            } => write!(
+                f,
+                "{} entered at event {} has not returned when the trace ends",
+                kind_name(kind),
+                start
+            ),
+            Issue::Unmatched { event } => {
+                write!(f, "event {} closes a frame that was never opened", event)
+            }
+            Issue::WrongCallee {
+                event,
+                called,
+                entered,
+            } => write!(
+                f,
+                "event {} enters function {} but function {} was called",
+                event, entered, called
+            ),
+        }
+    }
+}
+
+/// The call tree of a trace, see [`CallTree::new`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallTree {
+    /// Frames entered from the host at the top level.
+    pub roots: Vec<Frame>,
+    pub issues: Vec<Issue>,
+}
+
+impl CallTree {
+    /// Builds the tree from the events of a trace.
+    ///
+    /// A trap event, or an export call while a Wasm frame is on top, means
+    /// the previous call from the host trapped: the open frames up to the
+    /// innermost host frame are unwound. Closing events unwind the frames
+    /// above the one they close.
+    pub fn new(events: &[Event]) -> Self {
+        let mut builder = Builder::default();
+        for (i, event) in events.iter().enumerate() {
+            match event {
+                Event::ExportCall { export, .. } => {
+                    builder.unwind_to_host(i);
+                    builder.open(FrameKind::Export { export: *export }, i);
+                }
+                Event::ExportReturn { export, .. } => {
+                    builder.close(i, |k| *k == FrameKind::Export { export: *export })
+                }
+                Event::FunctionEntry { func, .. } => {
+                    if let Some(FrameKind::Call { func: called }) = builder.top() {
+                        if called != *func {
+                            builder.tree.issues.push(Issue::WrongCallee {
+                                event: i,
+                                called,
+                                entered: *func,
+                            });
+                        }
+                    }
+                    builder.open(FrameKind::Function { func: *func }, i);
+                }
+                Event::Return { func, .. } => {
+                    builder.close(i, |k| *k == FrameKind::Function { func: *func })
+                }
+                Event::Call { func, .. } => builder.open(FrameKind::Call { func: *func }, i),
+                Event::CallIndirect {
+                    type_index,
+                    elem_index,
+                    ..
+                } => builder.open(
+                    FrameKind::CallIndirect {
+                        type_index: *type_index,
+                        elem_index: *elem_index,
+                    },
+                    i,
+                ),
+                Event::ImportCall { func, .. } => {
+                    builder.open(FrameKind::Import { func: *func }, i)
+                }
+                Event::CallEnd { .. } => builder.close(i, |k| {
+                    matches!(
+                        k,
+                        FrameKind::Call { .. }
+                            | FrameKind::CallIndirect { .. }
+                            | FrameKind::Import { .. }
+                    )
+                }),
+                // Recorded by the host after it caught a trap
+                Event::Trap { .. } => builder.unwind_to_host(i),
+                _ => {}
+            }
+        }
+        while !builder.stack.is_empty() {
+            builder.pop(None, None);
+        }
+        builder.tree
+    }
+
+    /// The frames open at event `index`, outermost first. A frame is open
+    /// from the event that opened it up to the one that closed or unwound it,
+    /// both included, so a trap event still sees the frames it unwinds. An
+    /// export call that unwinds frames only sees the frame it opens.
+    pub fn stack_at(&self, index: usize) -> Vec<&Frame> {
+        let left = self.left_at();
+        let open = |frame: &&Frame| {
+            let end = frame
+                .end
+                .or_else(|| left.get(&frame.start).copied().flatten());
+            frame.start <= index && end.is_none_or(|end| index <= end)
+        };
+        let mut stack = Vec::new();
+        let mut frames = &self.roots;
+        while let Some(frame) = frames.iter().rev().find(open) {
+            stack.push(frame);
+            frames = &frame.children;
+        }
+        stack
+    }
+
+    /// The events that unwound the unterminated frames, by the event that
+    /// opened them.
+    pub(crate) fn left_at(&self) -> HashMap<usize, Option<usize>> {
+        self.issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::Unterminated { start, at, .. } => Some((*start, *at)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// All frames in pre-order with their depth.
+    pub fn frames(&self) -> Vec<(usize, &Frame)> {
+        fn walk<'a>(frames: &'a [Frame], depth: usize, out: &mut Vec<(usize, &'a Frame)>) {
+            for frame in frames {
+                out.push((depth, frame));
+                walk(&frame.children, depth + 1, out);
+            }
+        }
+        let mut out = Vec::new();
+        walk(&self.roots, 0, &mut out);
+        out
+    }
+}
+
+/// One frame per line, indented by depth, followed by the issues.
+impl Display for CallTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &|kind| kind.to_string())
+    }
+}
+
+impl CallTree {
+    /// Writes the tree like its `Display`, naming frame kinds through
+    /// `kind_name`.
+    pub fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        kind_name: &dyn Fn(&FrameKind) -> String,
+    ) -> fmt::Result {
+        for (depth, frame) in self.frames() {
+            write!(
+                f,
+                "{:indent$}{} @{}",
+                "",
+                kind_name(&frame.kind),
+                frame.start,
+                indent = 2 * depth
+            )?;
+            match frame.end {
+                Some(end) => writeln!(f, "..{}", end)?,
+                None => writeln!(f, " (unterminated)")?,
+            }
+        }
+        for issue in &self.issues {
+            write!(f, "issue: ")?;
+            issue.fmt_with(f, kind_name)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    tree: CallTree,
+    stack: Vec<Frame>,
+}
+
+impl Builder {
+    fn top(&self) -> Option<FrameKind> {
+        self.stack.last().map(|f| f.kind)
+    }
+
+    fn open(&mut self, kind: FrameKind, event: usize) {
+        self.stack.push(Frame {
+            kind,
+            start: event,
+            end: None,
+            children: Vec::new(),
+        });
+    }
+
+    /// Pops the top frame into its parent. Without `end` it is reported as
+    /// unterminated, unwound by event `at`.
+    fn pop(&mut self, end: Option<usize>, at: Option<usize>) {
+        let mut frame = self.stack.pop().expect("pop on an empty stack");
+        frame.end = end;
+        if end.is_none() {
+            self.tree.issues.push(Issue::Unterminated {
+                kind: frame.kind,
+                start: frame.start,
+                at,
+            });
+        }
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(frame),
+            None => self.tree.roots.push(frame),
+        }
+    }
+
+    /// Closes the innermost frame matching `matches` with event `event`.
+    fn close(&mut self, event: usize, matches: impl Fn(&FrameKind) -> bool) {
+        match self.stack.iter().rposition(|f| matches(&f.kind)) {
+            Some(pos) => {
+                while self.stack.len() > pos + 1 {
+                    self.pop(None, Some(event));
+                }
+                self.pop(Some(event), None);
+            }
+            None => self.tree.issues.push(Issue::Unmatched { event }),
+        }
+    }
+
+    /// Unwinds the Wasm frames above the innermost host frame.
+    fn unwind_to_host(&mut self, event: usize) {
+        while self.top().is_some_and(|k| !k.is_host()) {
+            self.pop(None, Some(event));
+        }
+    }
+}
@@ -0,0 +1,552 @@
+//! A Debug Adapter Protocol server to step through a decoded trace offline.
+//!
+//! The trace has one thread whose position is an event. Execution stops at
+//! function entries, calls and their ends, loads, stores, syscalls and traps,
+//! and can step forward and backward over them. Stack frames come from the
+//! [`CallTree`], globals, tables and the memory from [`StateAt`], and frames
+//! are mapped to source lines when the module has DWARF.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, Write},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value as Json};
+use walrus::Module;
+
+use crate::{
+    call_tree::{CallTree, FrameKind},
+    decode::Event,
+    dwarf::Location,
+    find_function,
+    state::{last_write, StateAt},
+    symbolize::Symbols,
+    text,
+};
+
+/// The only thread of a trace.
+const THREAD: u64 = 1;
+/// Longest message body read, requests are far shorter.
+const MAX_MESSAGE_LEN: usize = 4 << 20;
+
+/// Variables references of the scopes, the same in every frame.
+const EVENT_SCOPE: u64 = 1;
+const GLOBALS_SCOPE: u64 = 2;
+const TABLES_SCOPE: u64 = 3;
+
+/// A debug session on a trace, see [`Session::serve`].
+pub struct Session {
+    events: Vec<Event>,
+    tree: CallTree,
+    /// Frames around each event, see [`depths`].
+    depths: Vec<usize>,
+    symbols: Symbols,
+    module: Module,
+    breakpoints: HashSet<u32>,
+    position: usize,
+    state: Option<StateAt>,
+    seq: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    In,
+    Over,
+    Out,
+    Back,
+    Continue,
+    ReverseContinue,
+}
+
+impl Session {
+    /// Starts a session on the events of a trace of the original module, in
+    /// the binary or the text format.
+    pub fn new(wasm: &[u8], events: Vec<Event>) -> Result<Self> {
+        let wasm = text::parse(wasm)?;
+        let module = Module::from_buffer(&wasm)?;
+        let symbols = Symbols::new(&wasm)?;
+        let tree = CallTree::new(&events);
+        let depths = depths(&tree, events.len());
+        let position = events.iter().position(is_stop).unwrap_or(0);
+        Ok(Self {
+            events,
+            tree,
+            depths,
+            symbols,
+            module,
+            breakpoints: HashSet::new(),
+            position,
+            state: None,
+            seq: 0,
+        })
+    }
+
+    /// The event the session stopped at.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Answers the requests read from `input` until the client disconnects
+    /// or closes it.
+    pub fn serve(mut self, mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+        while let Some(request) = read_message(&mut input)? {
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let (result, events) = match self.handle(&command, &request["arguments"]) {
+                Ok((body, events)) => (Ok(body), events),
+                Err(e) => (Err(e), Vec::new()),
+            };
+            let mut response = json!({
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": result.is_ok(),
+            });
+            match result {
+                Ok(body) => response["body"] = body,
+                Err(e) => response["message"] = format!("{:#}", e).into(),
+            }
+            self.send(&mut output, response)?;
+            for (event, body) in events {
+                let event = json!({ "type": "event", "event": event, "body": body });
+                self.send(&mut output, event)?;
+            }
+            if matches!(command.as_str(), "disconnect" | "terminate") {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, output: &mut impl Write, mut message: Json) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        let body = message.to_string();
+        write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// The body of the response and the events to send after it.
+    fn handle(&mut self, command: &str, args: &Json) -> Result<(Json, Vec<(&'static str, Json)>)> {
+        let body = match command {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsStepBack": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsTerminateRequest": true,
+                });
+                return Ok((capabilities, vec![("initialized", json!({}))]));
+            }
+            "configurationDone" => {
+                return Ok((json!({}), vec![self.stopped("entry", None)]));
+            }
+            "next" => return Ok(self.step(Step::Over)),
+            "stepIn" => return Ok(self.step(Step::In)),
+            "stepOut" => return Ok(self.step(Step::Out)),
+            "stepBack" => return Ok(self.step(Step::Back)),
+            "continue" => return Ok(self.step(Step::Continue)),
+            "reverseContinue" => return Ok(self.step(Step::ReverseContinue)),
+            "terminate" => return Ok((json!({}), vec![("terminated", json!({}))])),
+            "launch" | "attach" | "disconnect" | "setExceptionBreakpoints" => json!({}),
+            "setBreakpoints" => {
+                let breakpoints: Vec<Json> = args["breakpoints"]
+                    .as_array()
+                    .map_or(&[][..], |b| b)
+                    .iter()
+                    .map(|_| {
+                        json!({
+                            "verified": false,
+                            "message": "only function breakpoints are supported",
+                        })
+                    })
+                    .collect();
+                json!({ "breakpoints": breakpoints })
+            }
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "threads" => json!({ "threads": [{ "id": THREAD, "name": "trace" }] }),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => json!({
+                "scopes": [
+                    { "name": "Event", "variablesReference": EVENT_SCOPE, "expensive": false },
+                    { "name": "Globals", "variablesReference": GLOBALS_SCOPE, "expensive": false },
+                    { "name": "Tables", "variablesReference": TABLES_SCOPE, "expensive": false },
+                ]
+            }),
+            "variables" => self.variables(args["variablesReference"].as_u64().unwrap_or(0)),
+            "readMemory" => self.read_memory(args)?,
+            _ => bail!("unsupported request {}", command),
+        };
+        Ok((body, Vec::new()))
+    }
+
+    fn step(&mut self, step: Step) -> (Json, Vec<(&'static str, Json)>) {
+        let (target, reason) = self.target(step);
+        let body = match step {
+            Step::Continue | Step::ReverseContinue => json!({ "allThreadsContinued": true }),
+            _ => json!({}),
+        };
+        let description = match target {
+            Some(target) => {
+                self.position = target;
+                None
+            }
+            None => {
+                // Stay at the first or last stop of the trace
+                let forward = matches!(step, Step::In | Step::Over | Step::Out | Step::Continue);
+                let edge = if forward {
+                    self.events.iter().rposition(is_stop)
+                } else {
+                    self.events.iter().position(is_stop)
+                };
+                self.position = edge.unwrap_or(self.position);
+                Some(if forward {
+                    "end of the trace"
+                } else {
+                    "start of the trace"
+                })
+            }
+        };
+        self.state = None;
+        (body, vec![self.stopped(reason, description)])
+    }
+
+    /// The next stop for `step` and the reason to stop there, `None` if
+    /// there is none before the end (or the start) of the trace.
+    fn target(&self, step: Step) -> (Option<usize>, &'static str) {
+        let depth = self.depths.get(self.position).copied().unwrap_or(0);
+        let is_stop = |i: &usize| is_stop(&self.events[*i]);
+        let mut after = (self.position + 1..self.events.len()).filter(is_stop);
+        let mut before = (0..self.position.min(self.events.len()))
+            .rev()
+            .filter(is_stop);
+        match step {
+            Step::In => (after.next(), "step"),
+            Step::Over => (after.find(|&i| self.depths[i] <= depth), "step"),
+            Step::Out => (after.find(|&i| self.depths[i] < depth), "step"),
+            Step::Back => (before.find(|&i| self.depths[i] <= depth), "step"),
+            Step::Continue => self.run(after),
+            Step::ReverseContinue => self.run(before),
+        }
+    }
+
+    /// The first breakpoint or trap among `stops`.
+    fn run(&self, mut stops: impl Iterator<Item = usize>) -> (Option<usize>, &'static str) {
+        let is_trap = |i: usize| matches!(self.events[i], Event::Trap { .. });
+        match stops.find(|&i| self.hits_breakpoint(i) || is_trap(i)) {
+            Some(i) if is_trap(i) => (Some(i), "exception"),
+            Some(i) => (Some(i), "function breakpoint"),
+            None => (None, "step"),
+        }
+    }
+
+    fn hits_breakpoint(&self, index: usize) -> bool {
+        match self.events[index] {
+            Event::FunctionEntry { func, .. } | Event::ImportCall { func, .. } => {
+                self.breakpoints.contains(&func)
+            }
+            _ => false,
+        }
+    }
+
+    fn stopped(&self, reason: &str, description: Option<&str>) -> (&'static str, Json) {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = description.into();
+        }
+        if let Some(event) = self.events.get(self.position) {
+            body["text"] = format!("event {}: {}", self.position, self.symbols.event(event)).into();
+        }
+        ("stopped", body)
+    }
+
+    /// Function breakpoints are function names or indices, they stop at the
+    /// entry of the function or at the call of an import.
+    fn set_function_breakpoints(&mut self, args: &Json) -> Json {
+        self.breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().map_or(&[][..], |b| b) {
+            let name = breakpoint["name"].as_str().unwrap_or_default();
+            match self.function_index(name) {
+                Some(func) => {
+                    self.breakpoints.insert(func);
+                    breakpoints.push(json!({ "verified": true }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "message": format!("no function {}", name),
+                })),
+            }
+        }
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn function_index(&self, name: &str) -> Option<u32> {
+        if let Ok(func) = name.trim_start_matches("function ").parse::<u32>() {
+            return (func < self.symbols.function_count()).then_some(func);
+        }
+        let id = find_function(&self.module, name);
+        match self.module.funcs.iter().position(|f| Some(f.id()) == id) {
+            Some(func) => Some(func as u32),
+            None => self.symbols.function_index(name),
+        }
+    }
+
+    /// The function and host frames at the position, innermost first.
+    fn stack_trace(&self) -> Json {
+        let stack = self.tree.stack_at(self.position);
+        let trap_frames = match self.events.get(self.position) {
+            Some(Event::Trap { frames, .. }) => &frames[..],
+            _ => &[],
+        };
+        let mut functions = 0;
+        let frames: Vec<Json> = stack
+            .iter()
+            .rev()
+            .filter_map(|frame| {
+                let (name, location) = match frame.kind {
+                    FrameKind::Function { func } => {
+                        // A trap records the offsets of its frames
+                        let location = match trap_frames.get(functions) {
+                            Some(trap) if trap.func == func && trap.offset != 0 => {
+                                self.symbols.locate(trap.offset)
+                            }
+                            _ => self.symbols.locate_function(func),
+                        };
+                        functions += 1;
+                        (self.symbols.function_name(func), location)
+                    }
+                    FrameKind::Import { func } => {
+                        (format!("host: {}", self.symbols.function_name(func)), None)
+                    }
+                    _ => return None,
+                };
+                let mut json = json!({
+                    "id": frame.start + 1,
+                    "name": name,
+                    "line": location.as_ref().map_or(0, |l| l.line),
+                    "column": location.as_ref().map_or(0, |l| l.column.max(1)),
+                });
+                match location {
+                    Some(location) => json["source"] = source(&location),
+                    None => json["presentationHint"] = "subtle".into(),
+                }
+                Some(json)
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn state(&mut self) -> &StateAt {
+        let (events, position) = (&self.events, self.position);
+        self.state
+            .get_or_insert_with(|| StateAt::at(events, position))
+    }
+
+    fn variables(&mut self, reference: u64) -> Json {
+        let variables = match reference {
+            EVENT_SCOPE => self.event_variables(),
+            GLOBALS_SCOPE => {
+                let globals = self.state().globals.clone();
+                globals
+                    .iter()
+                    .map(|(global, value)| {
+                        let name = match self.symbols.global(*global) {
+                            Some(name) => name.to_string(),
+                            None => format!("global {}", global),
+                        };
+                        variable(&name, value)
+                    })
+                    .collect()
+            }
+            TABLES_SCOPE => self
+                .state()
+                .tables
+                .iter()
+                .enumerate()
+                .map(|(table, elements)| {
+                    let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                    variable(
+                        &format!("table {}", table),
+                        format!("[{}]", elements.join(", ")),
+                    )
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        json!({ "variables": variables })
+    }
+
+    fn event_variables(&mut self) -> Vec<Json> {
+        let position = self.position;
+        let Some(event) = self.events.get(position) else {
+            return Vec::new();
+        };
+        let mut variables = vec![
+            variable("index", position),
+            variable("event", self.symbols.event(event)),
+        ];
+        if let Event::Load { addr, .. } | Event::Store { addr, .. } = *event {
+            let mut address = variable("address", format!("{:#x}", addr));
+            address["memoryReference"] = format!("{:#x}", addr).into();
+            variables.push(address);
+            let writer = match last_write(&self.events, addr, position) {
+                Some(i) => format!("event {}: {}", i, self.symbols.event(&self.events[i])),
+                None => "none in the trace".to_string(),
+            };
+            variables.push(variable("last write", writer));
+        }
+        let len = self.state().memory.len();
+        let mut memory = variable("memory", format!("{} bytes", len));
+        memory["memoryReference"] = "0x0".into();
+        variables.push(memory);
+        variables
+    }
+
+    fn read_memory(&mut self, args: &Json) -> Result<Json> {
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let base = match reference.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => reference.parse(),
+        }
+        .map_err(|_| anyhow!("invalid memory reference {}", reference))?;
+        let start = (base as i64).saturating_add(args["offset"].as_i64().unwrap_or(0));
+        // No more than a 32 bit memory holds
+        let count = args["count"].as_u64().unwrap_or(0).min(u32::MAX as u64) as usize;
+        let memory = &self.state().memory;
+        let bytes = usize::try_from(start)
+            .ok()
+            .and_then(|start| memory.get(start..start.saturating_add(count).min(memory.len())))
+            .unwrap_or_default();
+        Ok(json!({
+            "address": format!("{:#x}", start),
+            "data": base64(bytes),
+            "unreadableBytes": count - bytes.len(),
+        }))
+    }
+}
+
+/// Whether execution stops at the event.
+fn is_stop(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::FunctionEntry { .. }
+            | Event::Call { .. }
+            | Event::ImportCall { .. }
+            | Event::CallIndirect { .. }
+            | Event::CallEnd { .. }
+            | Event::ExportCall { .. }
+            | Event::ExportReturn { .. }
+            | Event::Load { .. }
+            | Event::Store { .. }
+            | Event::BulkMemory { .. }
+            | Event::Syscall { .. }
+            | Event::Trap { .. }
+    )
+}
+
+/// The number of frames around each event. A function frame counts from its
+/// entry to its return, so they are at the depth of its body, the other
+/// frames only between the events that open and close them, so a call and
+/// its end are at the depth of the caller.
+fn depths(tree: &CallTree, len: usize) -> Vec<usize> {
+    let left = tree.left_at();
+    let mut changes = vec![0isize; len + 1];
+    for (_, frame) in tree.frames() {
+        let end = frame
+            .end
+            .or_else(|| left.get(&frame.start).copied().flatten());
+        let (first, last) = match (frame.kind, end) {
+            (FrameKind::Function { .. }, Some(end)) => (frame.start, end),
+            (_, Some(end)) => (frame.start + 1, end - 1),
+            (FrameKind::Function { .. }, None) => (frame.start, len - 1),
+            (_, None) => (frame.start + 1, len - 1),
+        };
+        if first <= last {
+            changes[first] += 1;
+            changes[last + 1] -= 1;
+        }
+    }
+    let mut depth = 0;
+    changes[..len]
+        .iter()
+        .map(|change| {
+            depth += change;
+            depth as usize
+        })
+        .collect()
+}
+
+fn variable(name: &str, value: impl ToString) -> Json {
+    json!({ "name": name, "value": value.to_string(), "variablesReference": 0 })
+}
+
+fn source(location: &Location) -> Json {
+    let name = location.file.rsplit(['/', '\\']).next().unwrap_or_default();
+    json!({ "name": name, "path": location.file })
+}
+
+/// Reads one message, `None` at the end of the input. Fails on headers
+/// without a `Content-Length` and on bodies longer than
+/// [`MAX_MESSAGE_LEN`].
+fn read_message(input: &mut impl BufRead) -> Result<Option<Json>> {
+    let mut headers = false;
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if headers {
+                break;
+            }
+            continue;
+        }
+        headers = true;
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let Some(len) = len else {
+        bail!("message without a Content-Length header");
+    };
+    if len > MAX_MESSAGE_LEN {
+        bail!(
+            "message of {} bytes is longer than the limit of {} bytes",
+            len,
+            MAX_MESSAGE_LEN
+        );
+    }
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,1337 @@
+//! Decoding of the traces written into the exported `trace` memory.
+//!
+//! The records are listed in `transformations.md`, together with the
+//! changes that made older traces undecodable.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    ops::Range,
+};
+
+use anyhow::{bail, Context, Result};
+use walrus::{
+    ir::{self, dfs_in_order, Instr, Visitor},
+    ElementKind, ExportItem, FunctionId, InitExpr, InstrLocId, Module, TableId, ValType,
+};
+
+use crate::{metadata::Metadata, sites::SITE_CODE};
+
+const FOOTER_LEN: u32 = 2;
+const PAGE_SIZE: usize = 64 * 1024;
+/// Pages of a 32-bit memory.
+const MAX_PAGES: u32 = 1 << 16;
+
+/// Values of the globals exported in flight recorder mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingState {
+    /// `trace_byte_length`
+    pub mem_pointer: u32,
+    /// `trace_ring_end`
+    pub ring_end: u32,
+    /// `trace_wrap_count`
+    pub wrap_count: u32,
+    /// `trace_ring_limit`
+    pub limit: u32,
+    /// `trace_ring_size`, the ring buffer size in bytes.
+    pub size: u32,
+    /// `trace_ring_written`
+    pub written: u32,
+}
+
+/// Reassembles the ring buffer of a module instrumented with
+/// [`Options::ring_buffer`](crate::Options::ring_buffer) into a linear trace.
+///
+/// `ring` is the `trace` memory, or at least its first
+/// [`RingState::size`] bytes. The records of the previous lap that were not
+/// yet overwritten come first, followed by the records of the current lap.
+/// Footers are stripped, so the result has the same layout as a trace
+/// recorded without a ring buffer.
+pub fn reassemble_ring(ring: &[u8], state: RingState) -> Result<Vec<u8>> {
+    let Some(ring) = ring.get(..state.size as usize) else {
+        bail!(
+            "the trace of {} bytes is shorter than the {} byte ring",
+            ring.len(),
+            state.size
+        );
+    };
+    let mut records = Vec::new();
+    if state.wrap_count > 0 {
+        // Records dropped while recording is disabled are still written at
+        // `mem_pointer`, so the longest record after it may be garbage, or
+        // up to `written` for the records longer than that
+        let max_record_len = (ring.len() as u32).saturating_sub(state.limit + FOOTER_LEN);
+        let overwritten = state
+            .mem_pointer
+            .saturating_add(max_record_len)
+            .max(state.written);
+        records.append(&mut records_between(
+            ring,
+            overwritten,
+            state.ring_end,
+            false,
+        )?);
+    }
+    records.append(&mut records_between(ring, 0, state.mem_pointer, true)?);
+    Ok(records
+        .into_iter()
+        .flat_map(|r| ring[r.start as usize..r.end as usize].iter().copied())
+        .collect())
+}
+
+/// Walks the footers backwards from `end` and returns the records that
+/// start at or after `start` in order. With `exact` the first record has to
+/// start right at `start`, otherwise the ring is corrupt.
+fn records_between(ring: &[u8], start: u32, end: u32, exact: bool) -> Result<Vec<Range<u32>>> {
+    if end as usize > ring.len() {
+        bail!(
+            "ring end {} lies outside of the {} byte ring",
+            end,
+            ring.len()
+        );
+    }
+    let mut records = Vec::new();
+    let mut pos = end;
+    while pos > start {
+        if pos < start + FOOTER_LEN {
+            break;
+        }
+        let footer = (pos - FOOTER_LEN) as usize;
+        let len = u16::from_le_bytes([ring[footer], ring[footer + 1]]) as u32;
+        let record_start = match (pos - FOOTER_LEN).checked_sub(len) {
+            Some(s) if s >= start => s,
+            _ => break,
+        };
+        records.push(record_start..pos - FOOTER_LEN);
+        pos = record_start;
+    }
+    if exact && pos != start {
+        bail!("corrupt ring buffer: no record boundary at offset {}", pos);
+    }
+    records.reverse();
+    Ok(records)
+}
+
+/// A value recorded in the trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    /// References are opaque, only whether they are null is recorded.
+    Ref {
+        is_null: bool,
+    },
+    /// A non-null `funcref` in a table snapshot, resolved to the function
+    /// the element segments put there. Recorded like any other reference.
+    Func(u32),
+}
+
+impl Value {
+    /// The low `len` bytes of the value as they are stored in memory.
+    /// References have no bytes.
+    pub fn to_le_bytes(&self, len: u32) -> Vec<u8> {
+        let bytes = match self {
+            Value::I32(v) => v.to_le_bytes().to_vec(),
+            Value::I64(v) => v.to_le_bytes().to_vec(),
+            Value::F32(v) => v.to_le_bytes().to_vec(),
+            Value::F64(v) => v.to_le_bytes().to_vec(),
+            Value::Ref { .. } | Value::Func(_) => Vec::new(),
+        };
+        bytes.into_iter().take(len as usize).collect()
+    }
+
+    /// Appends the value as it is recorded, references as a null flag.
+    fn encode(&self, trace: &mut Vec<u8>) {
+        match self {
+            Value::Ref { is_null } => trace.extend((*is_null as u32).to_le_bytes()),
+            Value::Func(_) => trace.extend(0u32.to_le_bytes()),
+            value => trace.extend(value.to_le_bytes(8)),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I32(v) => write!(f, "i32 {}", v),
+            Value::I64(v) => write!(f, "i64 {}", v),
+            Value::F32(v) => write!(f, "f32 {}", v),
+            Value::F64(v) => write!(f, "f64 {}", v),
+            Value::Ref { is_null: true } => write!(f, "ref null"),
+            Value::Ref { is_null: false } => write!(f, "ref"),
+            Value::Func(func) => write!(f, "ref func {}", func),
+        }
+    }
+}
+
+/// One record of the trace. Function, type, global and table indices refer
+/// to the original module.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// `0x02`: a function of the module was entered.
+    FunctionEntry { func: u32, params: Vec<Value> },
+    /// `0x0F`: a function of the module returns.
+    Return { func: u32, results: Vec<Value> },
+    /// `0x10`: written before a direct call of a function of the module.
+    Call { func: u32, args: Vec<Value> },
+    /// `0x13`: written before a direct call of an imported function, see
+    /// [`Metadata::imports`](crate::metadata::Metadata::imports) for its
+    /// name.
+    ImportCall { func: u32, args: Vec<Value> },
+    /// `0x11`: written before an indirect call through `table[elem_index]`.
+    CallIndirect {
+        type_index: u32,
+        table: u32,
+        args: Vec<Value>,
+        elem_index: u32,
+    },
+    /// `0x14`: the host called the function export `export`, counting the
+    /// function exports in order, see
+    /// [`Metadata::exports`](crate::metadata::Metadata::exports).
+    ExportCall { export: u32, args: Vec<Value> },
+    /// `0x15`: the function export returns to the host.
+    ExportReturn { export: u32, results: Vec<Value> },
+    /// `0x0B`: written after a call returned to the caller.
+    CallEnd {
+        type_index: u32,
+        results: Vec<Value>,
+    },
+    /// The opcode of the load instruction, the effective address (the
+    /// operand plus the static offset) and the loaded value.
+    Load { opcode: u8, addr: u32, value: Value },
+    /// The opcode of the store instruction, the effective address and the
+    /// stored value.
+    Store { opcode: u8, addr: u32, value: Value },
+    /// `0x23`
+    GlobalGet { global: u32, value: Value },
+    /// `0x24`
+    GlobalSet { global: u32, value: Value },
+    /// `0x25`
+    TableGet {
+        table: u32,
+        index: u32,
+        value: Value,
+    },
+    /// `0x26`
+    TableSet {
+        table: u32,
+        index: u32,
+        value: Value,
+    },
+    /// `0x12`: written after the call end of a WASI import, with the
+    /// memory the host wrote during the call.
+    Syscall {
+        func: u32,
+        errno: i32,
+        writes: Vec<MemoryWrite>,
+    },
+    /// `0x16`: written by `r3_trap_info` after a trap, with the frames of
+    /// the shadow stack, innermost first. `depth` counts all frames, deep
+    /// stacks only keep the innermost ones.
+    Trap { depth: u32, frames: Vec<TrapFrame> },
+    /// `0x1A`: written after `memory.fill`, `memory.copy` or `memory.init`,
+    /// by their opcode behind the `0xFC` prefix, with the bytes of the range
+    /// they wrote. `bytes` is `None` if the range did not fit into the ring
+    /// buffer.
+    BulkMemory {
+        opcode: u8,
+        addr: u32,
+        len: u32,
+        bytes: Option<Vec<u8>>,
+    },
+    /// `0x1B`: written after `memory.grow`, `result` is the old number of
+    /// pages or -1 if the memory could not grow.
+    MemoryGrow { delta: u32, result: i32 },
+    /// `0x1C`: written after `table.fill`, `table.copy`, `table.init` or
+    /// `table.grow` with the null flags of the elements they wrote, `None`
+    /// if they did not fit into the ring buffer.
+    BulkTable {
+        opcode: u8,
+        table: u32,
+        index: u32,
+        count: u32,
+        values: Option<Vec<Value>>,
+    },
+    /// `0x17`: written before the start function, with the global values by
+    /// global idx (`v128` globals are left out), the null flags of each
+    /// table and, if it was recorded, the first memory. The non-null
+    /// elements of tables the module defines are resolved to the functions
+    /// of the active element segments, see [`Value::Func`].
+    InitialState {
+        globals: Vec<(u32, Value)>,
+        tables: Vec<Vec<Value>>,
+        memory: Option<MemorySnapshot>,
+    },
+    /// `0x18`: written before a flush every now and then, like the initial
+    /// state but with the memory unless it did not fit into the trace
+    /// memory. Replays can start here, see [`checkpoint_before`]. Elements
+    /// are only resolved in tables that neither the module nor the host can
+    /// write.
+    Checkpoint {
+        globals: Vec<(u32, Value)>,
+        tables: Vec<Vec<Value>>,
+        memory: Option<MemorySnapshot>,
+    },
+}
+
+type State = (Vec<(u32, Value)>, Vec<Vec<Value>>, Option<MemorySnapshot>);
+
+/// The non-zero parts of a memory, the rest of its pages are zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemorySnapshot {
+    pub pages: u32,
+    pub runs: Vec<MemoryWrite>,
+}
+
+impl MemorySnapshot {
+    /// The whole memory. The decoder checks that the runs lie inside the
+    /// pages, others extend it.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.pages as usize * PAGE_SIZE];
+        for run in &self.runs {
+            run.apply(&mut bytes);
+        }
+        bytes
+    }
+}
+
+/// A function that was running when the module trapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrapFrame {
+    pub func: u32,
+    /// Byte offset in the input of the last instruction of the function that
+    /// could trap, 0 if there was none since its entry.
+    pub offset: u32,
+}
+
+/// Bytes the host wrote to the memory of the module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub addr: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl MemoryWrite {
+    /// Writes the bytes to `memory`, growing it if they end behind it.
+    pub fn apply(&self, memory: &mut Vec<u8>) {
+        let start = self.addr as usize;
+        let end = start + self.bytes.len();
+        if memory.len() < end {
+            memory.resize(end, 0);
+        }
+        memory[start..end].copy_from_slice(&self.bytes);
+    }
+
+    /// Whether the write covers the byte at `addr`.
+    pub fn covers(&self, addr: u32) -> bool {
+        (self.addr as u64..self.addr as u64 + self.bytes.len() as u64).contains(&(addr as u64))
+    }
+}
+
+impl Event {
+    /// The bytes the event writes to the memory of the module: the stored
+    /// value of a store, the writes of a syscall, the recorded range of a
+    /// bulk memory instruction.
+    pub fn memory_writes(&self) -> Vec<MemoryWrite> {
+        match self {
+            Event::Store {
+                opcode,
+                addr,
+                value,
+            } => vec![MemoryWrite {
+                addr: *addr,
+                bytes: value.to_le_bytes(access_len(*opcode)),
+            }],
+            Event::Syscall { writes, .. } => writes.clone(),
+            Event::BulkMemory {
+                addr,
+                bytes: Some(bytes),
+                ..
+            } => vec![MemoryWrite {
+                addr: *addr,
+                bytes: bytes.clone(),
+            }],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Appends the record of the event to `trace`, as the instrumented
+    /// module writes it, without a site prefix.
+    pub fn encode(&self, trace: &mut Vec<u8>) {
+        fn values(trace: &mut Vec<u8>, values: &[Value]) {
+            for value in values {
+                value.encode(trace);
+            }
+        }
+        fn writes(trace: &mut Vec<u8>, writes: &[MemoryWrite]) {
+            trace.extend((writes.len() as u32).to_le_bytes());
+            for write in writes {
+                trace.extend(write.addr.to_le_bytes());
+                trace.extend((write.bytes.len() as u32).to_le_bytes());
+                trace.extend(&write.bytes);
+            }
+        }
+        let u32 = |trace: &mut Vec<u8>, v: u32| trace.extend(v.to_le_bytes());
+        match self {
+            Event::FunctionEntry { func, params } => {
+                trace.push(0x02);
+                u32(trace, *func);
+                values(trace, params);
+            }
+            Event::Return { func, results } => {
+                trace.push(0x0F);
+                u32(trace, *func);
+                values(trace, results);
+            }
+            Event::Call { func, args } | Event::ImportCall { func, args } => {
+                trace.push(match self {
+                    Event::Call { .. } => 0x10,
+                    _ => 0x13,
+                });
+                u32(trace, *func);
+                values(trace, args);
+            }
+            Event::CallIndirect {
+                type_index,
+                table,
+                args,
+                elem_index,
+            } => {
+                trace.push(0x11);
+                u32(trace, *type_index);
+                u32(trace, *table);
+                values(trace, args);
+                u32(trace, *elem_index);
+            }
+            Event::ExportCall { export, args } => {
+                trace.push(0x14);
+                u32(trace, *export);
+                values(trace, args);
+            }
+            Event::ExportReturn { export, results } => {
+                trace.push(0x15);
+                u32(trace, *export);
+                values(trace, results);
+            }
+            Event::CallEnd {
+                type_index,
+                results,
+            } => {
+                trace.push(0x0B);
+                u32(trace, *type_index);
+                values(trace, results);
+            }
+            Event::Load {
+                opcode,
+                addr,
+                value,
+            }
+            | Event::Store {
+                opcode,
+                addr,
+                value,
+            } => {
+                trace.push(*opcode);
+                u32(trace, *addr);
+                value.encode(trace);
+            }
+            Event::GlobalGet { global, value } | Event::GlobalSet { global, value } => {
+                trace.push(match self {
+                    Event::GlobalGet { .. } => 0x23,
+                    _ => 0x24,
+                });
+                u32(trace, *global);
+                value.encode(trace);
+            }
+            Event::TableGet {
+                table,
+                index,
+                value,
+            }
+            | Event::TableSet {
+                table,
+                index,
+                value,
+            } => {
+                trace.push(match self {
+                    Event::TableGet { .. } => 0x25,
+                    _ => 0x26,
+                });
+                u32(trace, *table);
+                u32(trace, *index);
+                value.encode(trace);
+            }
+            Event::Syscall {
+                func,
+                errno,
+                writes: syscall_writes,
+            } => {
+                trace.push(0x12);
+                u32(trace, *func);
+                u32(trace, *errno as u32);
+                writes(trace, syscall_writes);
+            }
+            Event::BulkMemory {
+                opcode,
+                addr,
+                len,
+                bytes,
+            } => {
+                trace.push(0x1A);
+                trace.push(*opcode);
+                u32(trace, *addr);
+                u32(trace, *len);
+                trace.push(bytes.is_some() as u8);
+                trace.extend(bytes.iter().flatten());
+            }
+            Event::MemoryGrow { delta, result } => {
+                trace.push(0x1B);
+                u32(trace, *delta);
+                u32(trace, *result as u32);
+            }
+            Event::BulkTable {
+                opcode,
+                table,
+                index,
+                count,
+                values: table_values,
+            } => {
+                trace.push(0x1C);
+                trace.push(*opcode);
+                u32(trace, *table);
+                u32(trace, *index);
+                u32(trace, *count);
+                trace.push(table_values.is_some() as u8);
+                values(trace, table_values.as_deref().unwrap_or_default());
+            }
+            Event::Trap { depth, frames } => {
+                trace.push(0x16);
+                u32(trace, *depth);
+                u32(trace, frames.len() as u32);
+                for frame in frames {
+                    u32(trace, frame.func);
+                    u32(trace, frame.offset);
+                }
+            }
+            Event::InitialState {
+                globals,
+                tables,
+                memory,
+            }
+            | Event::Checkpoint {
+                globals,
+                tables,
+                memory,
+            } => {
+                trace.push(match self {
+                    Event::InitialState { .. } => 0x17,
+                    _ => 0x18,
+                });
+                for (_, value) in globals {
+                    value.encode(trace);
+                }
+                for table in tables {
+                    u32(trace, table.len() as u32);
+                    values(trace, table);
+                }
+                match memory {
+                    Some(memory) => {
+                        trace.push(1);
+                        u32(trace, memory.pages);
+                        writes(trace, &memory.runs);
+                    }
+                    None => trace.push(0),
+                }
+            }
+        }
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::FunctionEntry { func, params } => {
+                write!(f, "enter {} ", func)?;
+                write_values(f, params)
+            }
+            Event::Return { func, results } => {
+                write!(f, "return {} ", func)?;
+                write_values(f, results)
+            }
+            Event::Call { func, args } => {
+                write!(f, "call {} ", func)?;
+                write_values(f, args)
+            }
+            Event::ImportCall { func, args } => {
+                write!(f, "call import {} ", func)?;
+                write_values(f, args)
+            }
+            Event::CallIndirect {
+                type_index,
+                table,
+                args,
+                elem_index,
+            } => {
+                write!(
+                    f,
+                    "call_indirect (type {}) table {}[{}] ",
+                    type_index, table, elem_index
+                )?;
+                write_values(f, args)
+            }
+            Event::ExportCall { export, args } => {
+                write!(f, "export call {} ", export)?;
+                write_values(f, args)
+            }
+            Event::ExportReturn { export, results } => {
+                write!(f, "export return {} ", export)?;
+                write_values(f, results)
+            }
+            Event::CallEnd {
+                type_index,
+                results,
+            } => {
+                write!(f, "call_end (type {}) ", type_index)?;
+                write_values(f, results)
+            }
+            Event::Load {
+                opcode,
+                addr,
+                value,
+            } => write!(f, "{} {:#x} -> {}", opcode_name(*opcode), addr, value),
+            Event::Store {
+                opcode,
+                addr,
+                value,
+            } => write!(f, "{} {:#x} <- {}", opcode_name(*opcode), addr, value),
+            Event::GlobalGet { global, value } => write!(f, "global.get {} -> {}", global, value),
+            Event::GlobalSet { global, value } => write!(f, "global.set {} <- {}", global, value),
+            Event::TableGet {
+                table,
+                index,
+                value,
+            } => write!(f, "table.get {}[{}] -> {}", table, index, value),
+            Event::TableSet {
+                table,
+                index,
+                value,
+            } => write!(f, "table.set {}[{}] <- {}", table, index, value),
+            Event::Syscall {
+                func,
+                errno,
+                writes,
+            } => {
+                write!(f, "syscall {} -> errno {} ", func, errno)?;
+                write_writes(f, writes)
+            }
+            Event::BulkMemory {
+                opcode,
+                addr,
+                len,
+                bytes,
+            } => {
+                write!(f, "{} {:#x} <- {} bytes", bulk_name(*opcode), addr, len)?;
+                if bytes.is_none() {
+                    write!(f, " (not recorded)")?;
+                }
+                Ok(())
+            }
+            Event::MemoryGrow { delta, result } => {
+                write!(f, "memory.grow {} -> {}", delta, result)
+            }
+            Event::BulkTable {
+                opcode,
+                table,
+                index,
+                count,
+                values,
+            } => {
+                write!(f, "{} {}[{}] <- ", bulk_name(*opcode), table, index)?;
+                match values {
+                    Some(values) => write_values(f, values),
+                    None => write!(f, "{} elements (not recorded)", count),
+                }
+            }
+            Event::Trap { depth, frames } => {
+                write!(f, "trap depth {} [", depth)?;
+                for (i, frame) in frames.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} @{:#x}", frame.func, frame.offset)?;
+                }
+                write!(f, "]")
+            }
+            Event::InitialState {
+                globals,
+                tables,
+                memory,
+            } => {
+                write!(f, "initial state ")?;
+                write_state(f, globals, tables, memory.as_ref(), &write_index)
+            }
+            Event::Checkpoint {
+                globals,
+                tables,
+                memory,
+            } => {
+                write!(f, "checkpoint ")?;
+                write_state(f, globals, tables, memory.as_ref(), &write_index)
+            }
+        }
+    }
+}
+
+fn write_index(f: &mut fmt::Formatter<'_>, index: u32) -> fmt::Result {
+    write!(f, "{}", index)
+}
+
+/// Writes the state of an initial state or checkpoint, the globals through
+/// `global`.
+pub(crate) fn write_state(
+    f: &mut fmt::Formatter<'_>,
+    globals: &[(u32, Value)],
+    tables: &[Vec<Value>],
+    memory: Option<&MemorySnapshot>,
+    global: &dyn Fn(&mut fmt::Formatter<'_>, u32) -> fmt::Result,
+) -> fmt::Result {
+    write!(f, "globals [")?;
+    for (i, (index, value)) in globals.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        global(f, *index)?;
+        write!(f, ": {}", value)?;
+    }
+    write!(f, "] tables [")?;
+    for (i, table) in tables.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{} elements", table.len())?;
+    }
+    write!(f, "]")?;
+    if let Some(memory) = memory {
+        write!(f, " memory {} pages [", memory.pages)?;
+        for (i, run) in memory.runs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:#x} <- {} bytes", run.addr, run.bytes.len())?;
+        }
+        write!(f, "]")?;
+    }
+    Ok(())
+}
+
+/// Writes the addresses and lengths of the writes of a syscall.
+pub(crate) fn write_writes(f: &mut fmt::Formatter<'_>, writes: &[MemoryWrite]) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, write) in writes.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{:#x} <- {} bytes", write.addr, write.bytes.len())?;
+    }
+    write!(f, "]")
+}
+
+fn write_values(f: &mut fmt::Formatter<'_>, values: &[Value]) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    write!(f, "]")
+}
+
+/// Index of the last initial state or checkpoint at or before event `index`
+/// that has the memory, where a replay up to `index` can start.
+pub fn checkpoint_before(events: &[Event], index: usize) -> Option<usize> {
+    events
+        .get(..=index.min(events.len().checked_sub(1)?))?
+        .iter()
+        .rposition(|e| {
+            matches!(
+                e,
+                Event::InitialState {
+                    memory: Some(_),
+                    ..
+                } | Event::Checkpoint {
+                    memory: Some(_),
+                    ..
+                }
+            )
+        })
+}
+
+/// Name of a load or store opcode. Sign extending loads are recorded with
+/// the opcode of the unsigned variant.
+pub fn opcode_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x28 => "i32.load",
+        0x29 => "i64.load",
+        0x2A => "f32.load",
+        0x2B => "f64.load",
+        0x2C => "i32.load8",
+        0x2E => "i32.load16",
+        0x30 => "i64.load8",
+        0x32 => "i64.load16",
+        0x34 => "i64.load32",
+        0x36 => "i32.store",
+        0x37 => "i64.store",
+        0x38 => "f32.store",
+        0x39 => "f64.store",
+        0x3A => "i32.store8",
+        0x3B => "i32.store16",
+        0x3C => "i64.store8",
+        0x3D => "i64.store16",
+        0x3E => "i64.store32",
+        _ => "unknown",
+    }
+}
+
+/// Name of a bulk memory or table opcode, behind the `0xFC` prefix.
+pub fn bulk_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x08 => "memory.init",
+        0x0A => "memory.copy",
+        0x0B => "memory.fill",
+        0x0C => "table.init",
+        0x0E => "table.copy",
+        0x0F => "table.grow",
+        0x11 => "table.fill",
+        _ => "unknown",
+    }
+}
+
+/// Number of bytes of memory a load or store opcode accesses.
+pub fn access_len(opcode: u8) -> u32 {
+    match opcode {
+        0x2C | 0x30 | 0x3A | 0x3C => 1,
+        0x2E | 0x32 | 0x3B | 0x3D => 2,
+        0x29 | 0x2B | 0x37 | 0x39 => 8,
+        _ => 4,
+    }
+}
+
+/// Type of the value a load or store opcode records.
+fn access_type(opcode: u8) -> Option<ValType> {
+    match opcode {
+        0x28 | 0x2C | 0x2E | 0x36 | 0x3A | 0x3B => Some(ValType::I32),
+        0x29 | 0x30 | 0x32 | 0x34 | 0x37 | 0x3C | 0x3D | 0x3E => Some(ValType::I64),
+        0x2A | 0x38 => Some(ValType::F32),
+        0x2B | 0x39 => Some(ValType::F64),
+        _ => None,
+    }
+}
+
+/// Decodes the records of a linear trace, using the types of the original
+/// (not instrumented) module to know the length of each record.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    func_types: Vec<u32>,
+    /// Function index of each function export.
+    exports: Vec<u32>,
+    types: Vec<(Vec<ValType>, Vec<ValType>)>,
+    globals: Vec<ValType>,
+    tables: Vec<ValType>,
+    /// The elements of each table after instantiation, by function index,
+    /// see [`Elements`].
+    elements: Vec<Elements>,
+}
+
+/// The elements the active segments put into a table.
+#[derive(Debug, Clone, Default)]
+struct Elements {
+    /// Function index of each element, `None` for a null one. Empty if the
+    /// table is imported or a segment offset is not known statically.
+    funcs: Vec<Option<u32>>,
+    /// Whether no instruction writes the table and it is not exported, so
+    /// it keeps these elements.
+    fixed: bool,
+}
+
+impl Decoder {
+    pub fn new(module: &Module) -> Self {
+        let type_indices: HashMap<_, _> = module
+            .types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.id(), i as u32))
+            .collect();
+        Self {
+            func_types: module.funcs.iter().map(|f| type_indices[&f.ty()]).collect(),
+            exports: Metadata::new(module)
+                .exports
+                .iter()
+                .map(|e| e.func)
+                .collect(),
+            types: module
+                .types
+                .iter()
+                .map(|t| (t.params().to_vec(), t.results().to_vec()))
+                .collect(),
+            globals: module.globals.iter().map(|g| g.ty).collect(),
+            tables: module.tables.iter().map(|t| t.element_ty).collect(),
+            elements: elements(module),
+        }
+    }
+
+    /// Parses the original module, in the binary or the text format.
+    pub fn from_buffer(wasm: &[u8]) -> Result<Self> {
+        Ok(Self::new(&Module::from_buffer(&crate::text::parse(wasm)?)?))
+    }
+
+    pub fn decode(&self, trace: &[u8]) -> Result<Vec<Event>> {
+        Ok(self
+            .decode_with_sites(trace)?
+            .into_iter()
+            .map(|(event, _)| event)
+            .collect())
+    }
+
+    /// Decodes the events with the site ids written before them by a module
+    /// instrumented with [`Options::sites`](crate::Options::sites), see
+    /// [`Sites`](crate::sites::Sites).
+    pub fn decode_with_sites(&self, trace: &[u8]) -> Result<Vec<(Event, Option<u32>)>> {
+        let mut reader = Reader { trace, pos: 0 };
+        let mut events = Vec::new();
+        while reader.pos < trace.len() {
+            let start = reader.pos;
+            let event = self
+                .read_site(&mut reader)
+                .and_then(|site| Ok((self.read_event(&mut reader)?, site)))
+                .with_context(|| format!("invalid record at offset {:#x}", start))?;
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    /// Reads the site prefix of a record if it has one.
+    fn read_site(&self, reader: &mut Reader) -> Result<Option<u32>> {
+        if reader.trace.get(reader.pos) != Some(&SITE_CODE) {
+            return Ok(None);
+        }
+        reader.u8()?;
+        Ok(Some(reader.u32()?))
+    }
+
+    fn read_event(&self, reader: &mut Reader) -> Result<Event> {
+        let code = reader.u8()?;
+        Ok(match code {
+            0x02 => {
+                let func = reader.u32()?;
+                let (params, _) = self.func_type(func)?;
+                Event::FunctionEntry {
+                    func,
+                    params: reader.values(params)?,
+                }
+            }
+            0x0F => {
+                let func = reader.u32()?;
+                let (_, results) = self.func_type(func)?;
+                Event::Return {
+                    func,
+                    results: reader.values(results)?,
+                }
+            }
+            0x10 | 0x13 => {
+                let func = reader.u32()?;
+                let (params, _) = self.func_type(func)?;
+                let args = reader.values(params)?;
+                match code {
+                    0x10 => Event::Call { func, args },
+                    _ => Event::ImportCall { func, args },
+                }
+            }
+            0x11 => {
+                let type_index = reader.u32()?;
+                let table = reader.u32()?;
+                let (params, _) = self.type_at(type_index)?;
+                Event::CallIndirect {
+                    type_index,
+                    table,
+                    args: reader.values(params)?,
+                    elem_index: reader.u32()?,
+                }
+            }
+            0x14 | 0x15 => {
+                let export = reader.u32()?;
+                let (params, results) = match self.exports.get(export as usize) {
+                    Some(func) => self.func_type(*func)?,
+                    None => bail!("unknown export {}", export),
+                };
+                match code {
+                    0x14 => Event::ExportCall {
+                        export,
+                        args: reader.values(params)?,
+                    },
+                    _ => Event::ExportReturn {
+                        export,
+                        results: reader.values(results)?,
+                    },
+                }
+            }
+            0x0B => {
+                let type_index = reader.u32()?;
+                let (_, results) = self.type_at(type_index)?;
+                Event::CallEnd {
+                    type_index,
+                    results: reader.values(results)?,
+                }
+            }
+            0x23 | 0x24 => {
+                let global = reader.u32()?;
+                let typ = match self.globals.get(global as usize) {
+                    Some(typ) => *typ,
+                    None => bail!("unknown global {}", global),
+                };
+                let value = reader.value(typ)?;
+                match code {
+                    0x23 => Event::GlobalGet { global, value },
+                    _ => Event::GlobalSet { global, value },
+                }
+            }
+            0x25 | 0x26 => {
+                let table = reader.u32()?;
+                let typ = match self.tables.get(table as usize) {
+                    Some(typ) => *typ,
+                    None => bail!("unknown table {}", table),
+                };
+                let index = reader.u32()?;
+                let value = reader.value(typ)?;
+                match code {
+                    0x25 => Event::TableGet {
+                        table,
+                        index,
+                        value,
+                    },
+                    _ => Event::TableSet {
+                        table,
+                        index,
+                        value,
+                    },
+                }
+            }
+            0x12 => {
+                let func = reader.u32()?;
+                let errno = reader.u32()? as i32;
+                let count = reader.u32()?;
+                let writes = (0..count)
+                    .map(|_| {
+                        let addr = reader.u32()?;
+                        let len = reader.u32()?;
+                        Ok(MemoryWrite {
+                            addr,
+                            bytes: reader.slice(len as usize)?.to_vec(),
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Event::Syscall {
+                    func,
+                    errno,
+                    writes,
+                }
+            }
+            0x1A => {
+                let opcode = reader.u8()?;
+                let addr = reader.u32()?;
+                let len = reader.u32()?;
+                let bytes = match reader.u8()? {
+                    0 => None,
+                    _ => Some(reader.slice(len as usize)?.to_vec()),
+                };
+                Event::BulkMemory {
+                    opcode,
+                    addr,
+                    len,
+                    bytes,
+                }
+            }
+            0x1B => Event::MemoryGrow {
+                delta: reader.u32()?,
+                result: reader.u32()? as i32,
+            },
+            0x1C => {
+                let opcode = reader.u8()?;
+                let table = reader.u32()?;
+                let typ = match self.tables.get(table as usize) {
+                    Some(typ) => *typ,
+                    None => bail!("unknown table {}", table),
+                };
+                let index = reader.u32()?;
+                let count = reader.u32()?;
+                let values = match reader.u8()? {
+                    0 => None,
+                    _ => Some(
+                        (0..count)
+                            .map(|_| reader.value(typ))
+                            .collect::<Result<_>>()?,
+                    ),
+                };
+                Event::BulkTable {
+                    opcode,
+                    table,
+                    index,
+                    count,
+                    values,
+                }
+            }
+            0x16 => {
+                let depth = reader.u32()?;
+                let count = reader.u32()?;
+                let frames = (0..count)
+                    .map(|_| {
+                        Ok(TrapFrame {
+                            func: reader.u32()?,
+                            offset: reader.u32()?,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Event::Trap { depth, frames }
+            }
+            0x17 => {
+                let (globals, tables, memory) = self.read_state(reader, true)?;
+                Event::InitialState {
+                    globals,
+                    tables,
+                    memory,
+                }
+            }
+            0x18 => {
+                let (globals, tables, memory) = self.read_state(reader, false)?;
+                Event::Checkpoint {
+                    globals,
+                    tables,
+                    memory,
+                }
+            }
+            opcode => match access_type(opcode) {
+                Some(typ) => {
+                    let addr = reader.u32()?;
+                    let value = reader.value(typ)?;
+                    if opcode < 0x36 {
+                        Event::Load {
+                            opcode,
+                            addr,
+                            value,
+                        }
+                    } else {
+                        Event::Store {
+                            opcode,
+                            addr,
+                            value,
+                        }
+                    }
+                }
+                None => bail!("unknown record code {:#x}", opcode),
+            },
+        })
+    }
+
+    /// Reads the state of an initial state or checkpoint record, `initial`
+    /// for the one before the start function.
+    fn read_state(&self, reader: &mut Reader, initial: bool) -> Result<State> {
+        let globals = self
+            .globals
+            .iter()
+            .enumerate()
+            .filter(|(_, ty)| **ty != ValType::V128)
+            .map(|(i, ty)| Ok((i as u32, reader.value(*ty)?)))
+            .collect::<Result<_>>()?;
+        let tables = self
+            .tables
+            .iter()
+            .zip(&self.elements)
+            .map(|(ty, elements)| {
+                let size = reader.u32()?;
+                let resolve = initial || elements.fixed;
+                (0..size)
+                    .map(|i| match reader.value(*ty)? {
+                        Value::Ref { is_null: false } if resolve => {
+                            match elements.funcs.get(i as usize) {
+                                Some(Some(func)) => Ok(Value::Func(*func)),
+                                _ => Ok(Value::Ref { is_null: false }),
+                            }
+                        }
+                        value => Ok(value),
+                    })
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+        let memory = match reader.u8()? {
+            0 => None,
+            _ => {
+                let pages = reader.u32()?;
+                if pages > MAX_PAGES {
+                    bail!("memory of {} pages", pages);
+                }
+                let size = pages as u64 * PAGE_SIZE as u64;
+                let count = reader.u32()?;
+                let runs = (0..count)
+                    .map(|_| {
+                        let addr = reader.u32()?;
+                        let len = reader.u32()?;
+                        if addr as u64 + len as u64 > size {
+                            bail!(
+                                "memory run of {} bytes at {:#x} ends behind the {} pages",
+                                len,
+                                addr,
+                                pages
+                            );
+                        }
+                        Ok(MemoryWrite {
+                            addr,
+                            bytes: reader.slice(len as usize)?.to_vec(),
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Some(MemorySnapshot { pages, runs })
+            }
+        };
+        Ok((globals, tables, memory))
+    }
+
+    fn func_type(&self, func: u32) -> Result<&(Vec<ValType>, Vec<ValType>)> {
+        match self.func_types.get(func as usize) {
+            Some(type_index) => self.type_at(*type_index),
+            None => bail!("unknown function {}", func),
+        }
+    }
+
+    fn type_at(&self, type_index: u32) -> Result<&(Vec<ValType>, Vec<ValType>)> {
+        match self.types.get(type_index as usize) {
+            Some(typ) => Ok(typ),
+            None => bail!("unknown type {}", type_index),
+        }
+    }
+}
+
+/// The elements of each table of `module`, see [`Elements`].
+fn elements(module: &Module) -> Vec<Elements> {
+    let func_indices: HashMap<FunctionId, u32> = module
+        .funcs
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.id(), i as u32))
+        .collect();
+    let mut written = WrittenTables::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut written, func, func.entry_block());
+    }
+    for export in module.exports.iter() {
+        if let ExportItem::Table(table) = export.item {
+            written.0.insert(table);
+        }
+    }
+    // Offsets from imported globals are not known
+    let offset = |offset: &InitExpr| match offset {
+        InitExpr::Value(ir::Value::I32(offset)) => Some(*offset as u32 as usize),
+        _ => None,
+    };
+    module
+        .tables
+        .iter()
+        .map(|table| {
+            let mut funcs = match table.import {
+                Some(_) => None,
+                None => Some(vec![None; table.initial as usize]),
+            };
+            for element in module.elements.iter() {
+                let ElementKind::Active {
+                    table: id,
+                    offset: ref start,
+                } = element.kind
+                else {
+                    continue;
+                };
+                if id != table.id() {
+                    continue;
+                }
+                let (Some(elements), Some(start)) = (&mut funcs, offset(start)) else {
+                    funcs = None;
+                    continue;
+                };
+                for (i, member) in element.members.iter().enumerate() {
+                    if let Some(slot) = elements.get_mut(start + i) {
+                        *slot = member.map(|f| func_indices[&f]);
+                    }
+                }
+            }
+            Elements {
+                fixed: funcs.is_some() && !written.0.contains(&table.id()),
+                funcs: funcs.unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// The tables written by an instruction.
+#[derive(Default)]
+struct WrittenTables(HashSet<TableId>);
+
+impl Visitor<'_> for WrittenTables {
+    fn visit_instr(&mut self, instr: &Instr, _: &InstrLocId) {
+        match instr {
+            Instr::TableSet(ir::TableSet { table })
+            | Instr::TableFill(ir::TableFill { table })
+            | Instr::TableCopy(ir::TableCopy { dst: table, .. })
+            | Instr::TableInit(ir::TableInit { table, .. })
+            | Instr::TableGrow(ir::TableGrow { table }) => {
+                self.0.insert(*table);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Reader<'a> {
+    trace: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.slice(N)?.try_into().unwrap())
+    }
+
+    fn slice(&mut self, len: usize) -> Result<&[u8]> {
+        match self.trace.get(self.pos..self.pos.saturating_add(len)) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => bail!("trace ends inside the record"),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn value(&mut self, typ: ValType) -> Result<Value> {
+        Ok(match typ {
+            ValType::I32 => Value::I32(i32::from_le_bytes(self.bytes()?)),
+            ValType::I64 => Value::I64(i64::from_le_bytes(self.bytes()?)),
+            ValType::F32 => Value::F32(f32::from_le_bytes(self.bytes()?)),
+            ValType::F64 => Value::F64(f64::from_le_bytes(self.bytes()?)),
+            ValType::Externref | ValType::Funcref => Value::Ref {
+                is_null: self.u32()? != 0,
+            },
+            ValType::V128 => bail!("v128 values are not recorded"),
+        })
+    }
+
+    fn values(&mut self, types: &[ValType]) -> Result<Vec<Value>> {
+        types.iter().map(|t| self.value(*t)).collect()
+    }
+}
@@ -0,0 +1,175 @@
+//! Source locations from the DWARF line table of a module.
+//!
+//! Addresses in the DWARF of a Wasm module are offsets from the start of the
+//! code section contents, [`SourceMap`] converts them from and to byte
+//! offsets in the module, like the ones of trap frames.
+
+use std::{collections::HashMap, fmt, ops::Range, path::PathBuf};
+
+use anyhow::Result;
+use gimli::{ColumnType, Dwarf, EndianSlice, LittleEndian, SectionId};
+use wasmparser::{Parser, Payload, TypeRef};
+
+/// A line of a source file, `column` is 0 if the line table has none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if self.column != 0 {
+            write!(f, ":{}", self.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// The line table of a module and the byte ranges of its function bodies.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// Offset of the code section contents in the module.
+    code_start: u32,
+    /// Byte ranges in the module by function index.
+    bodies: HashMap<u32, Range<u32>>,
+    files: Vec<String>,
+    /// Sorted by address, `None` ends a sequence.
+    rows: Vec<(u64, Option<Row>)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    file: usize,
+    line: u32,
+    column: u32,
+}
+
+impl SourceMap {
+    /// Reads the line table of a binary module. Without DWARF the map is
+    /// empty and locates nothing.
+    pub fn from_buffer(wasm: &[u8]) -> Result<Self> {
+        let mut map = SourceMap::default();
+        let mut sections = HashMap::new();
+        let mut func = 0;
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        func += matches!(import?.ty, TypeRef::Func(_)) as u32;
+                    }
+                }
+                Payload::CodeSectionStart { range, .. } => map.code_start = range.start as u32,
+                Payload::CodeSectionEntry(body) => {
+                    let range = body.range();
+                    map.bodies
+                        .insert(func, range.start as u32..range.end as u32);
+                    func += 1;
+                }
+                Payload::CustomSection(section) if section.name().starts_with(".debug_") => {
+                    sections.insert(section.name(), section.data());
+                }
+                _ => {}
+            }
+        }
+        if sections.contains_key(".debug_line") {
+            map.read_lines(&sections)?;
+        }
+        Ok(map)
+    }
+
+    /// Whether the module has a line table.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The location of the instruction at byte offset `offset` in the
+    /// module.
+    pub fn locate(&self, offset: u32) -> Option<Location> {
+        let address = offset.checked_sub(self.code_start)? as u64;
+        let i = self.rows.partition_point(|(a, _)| *a <= address);
+        let (_, row) = self.rows.get(i.checked_sub(1)?)?;
+        row.map(|row| self.location(row))
+    }
+
+    /// The location of the first instruction of function `func` that has
+    /// one.
+    pub fn locate_function(&self, func: u32) -> Option<Location> {
+        let body = self.bodies.get(&func)?;
+        let start = (body.start - self.code_start) as u64;
+        let end = (body.end - self.code_start) as u64;
+        let i = self.rows.partition_point(|(a, _)| *a < start);
+        self.rows[i..]
+            .iter()
+            .take_while(|(a, _)| *a < end)
+            .find_map(|(_, row)| *row)
+            .map(|row| self.location(row))
+    }
+
+    fn location(&self, row: Row) -> Location {
+        Location {
+            file: self.files[row.file].clone(),
+            line: row.line,
+            column: row.column,
+        }
+    }
+
+    fn read_lines(&mut self, sections: &HashMap<&str, &[u8]>) -> Result<()> {
+        let dwarf = Dwarf::load(|id: SectionId| -> gimli::Result<_> {
+            let data = sections.get(id.name()).copied().unwrap_or_default();
+            Ok(EndianSlice::new(data, LittleEndian))
+        })?;
+        let mut file_indices = HashMap::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() {
+                    self.rows.push((row.address(), None));
+                    continue;
+                }
+                let mut path = PathBuf::new();
+                if let Some(dir) = &unit.comp_dir {
+                    path.push(&*dir.to_string_lossy());
+                }
+                if let Some(file) = row.file(header) {
+                    // Directory 0 is the compilation directory
+                    if let Some(dir) = file
+                        .directory(header)
+                        .filter(|_| file.directory_index() != 0)
+                    {
+                        path.push(&*dwarf.attr_string(&unit, dir)?.to_string_lossy());
+                    }
+                    path.push(
+                        &*dwarf
+                            .attr_string(&unit, file.path_name())?
+                            .to_string_lossy(),
+                    );
+                }
+                let path = path.to_string_lossy().into_owned();
+                let next = self.files.len();
+                let file = *file_indices.entry(path.clone()).or_insert(next);
+                if file == next {
+                    self.files.push(path);
+                }
+                let column = match row.column() {
+                    ColumnType::LeftEdge => 0,
+                    ColumnType::Column(column) => column.get() as u32,
+                };
+                let line = row.line().map_or(0, |line| line.get() as u32);
+                self.rows
+                    .push((row.address(), Some(Row { file, line, column })));
+            }
+        }
+        // Sequence ends sort before rows starting at the same address
+        self.rows
+            .sort_by_key(|(address, row)| (*address, row.is_some()));
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt::{self, Display};
+
+use crate::ValidationError;
+
+/// Why a module could not be instrumented.
+#[derive(Debug)]
+pub enum InstrumentError {
+    /// The input is not a Wasm module walrus can read.
+    Parse(anyhow::Error),
+    /// An instruction or function signature uses a feature the tracer cannot
+    /// record.
+    Unsupported {
+        feature: &'static str,
+        func_index: u32,
+        /// Byte offset of the instruction in the input, `None` for the
+        /// function entry.
+        instr_offset: Option<u32>,
+    },
+    /// An instruction refers to a function, type, global or table the
+    /// module does not define.
+    UnknownReference {
+        kind: &'static str,
+        func_index: u32,
+        instr_offset: Option<u32>,
+    },
+    /// [`Options::trigger_function`](crate::Options::trigger_function) names
+    /// no function of the module.
+    TriggerNotFound(String),
+    /// A `wasi_snapshot_preview1` function the tracer does not know the
+    /// memory writes of, or with an unexpected signature. A replay would
+    /// miss what the host writes.
+    UnknownWasiImport(String),
+    /// [`Options::ring_buffer`](crate::Options::ring_buffer) cannot hold the
+    /// longest record.
+    RingBufferTooSmall { size: u32, max_record_len: u32 },
+    /// [`Options::watch`](crate::Options::watch) has more ranges than the
+    /// range table holds.
+    TooManyWatchRanges { count: usize, max: u32 },
+    /// The instrumented module does not validate, see
+    /// [`Options::validate`](crate::Options::validate).
+    Invalid(ValidationError),
+}
+
+impl Display for InstrumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstrumentError::Parse(e) => write!(f, "failed to parse module: {}", e),
+            InstrumentError::Unsupported {
+                feature,
+                func_index,
+                instr_offset,
+            } => {
+                write!(
+                    f,
+                    "unsupported feature {} in function {}",
+                    feature, func_index
+                )?;
+                write_offset(f, *instr_offset)
+            }
+            InstrumentError::UnknownReference {
+                kind,
+                func_index,
+                instr_offset,
+            } => {
+                write!(f, "unknown {} referenced in function {}", kind, func_index)?;
+                write_offset(f, *instr_offset)
+            }
+            InstrumentError::TriggerNotFound(name) => {
+                write!(f, "trigger function {} not found", name)
+            }
+            InstrumentError::UnknownWasiImport(name) => write!(
+                f,
+                "unknown import wasi_snapshot_preview1.{}, the memory it writes cannot be recorded",
+                name
+            ),
+            InstrumentError::RingBufferTooSmall {
+                size,
+                max_record_len,
+            } => write!(
+                f,
+                "ring buffer of {} bytes is too small for records of {} bytes",
+                size, max_record_len
+            ),
+            InstrumentError::TooManyWatchRanges { count, max } => {
+                write!(f, "{} watched ranges, at most {} are supported", count, max)
+            }
+            InstrumentError::Invalid(e) => e.fmt(f),
+        }
+    }
+}
+
+fn write_offset(f: &mut fmt::Formatter<'_>, instr_offset: Option<u32>) -> fmt::Result {
+    match instr_offset {
+        Some(offset) => write!(f, " at offset {:#x}", offset),
+        None => write!(f, " at function entry"),
+    }
+}
+
+// The parse error is part of the message, so it is not returned as `source`
+// to keep it from being printed twice.
+impl std::error::Error for InstrumentError {}
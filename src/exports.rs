@@ -0,0 +1,170 @@
+//! Wrappers of the exported functions that record when the host enters the
+//! module.
+//!
+//! Every function export is pointed at a wrapper that writes an export call
+//! record (`0x14`: export idx, args), calls the original function, writes an
+//! export return record (`0x15`: export idx, results) and flushes the
+//! trace. The export idx counts the function exports of the original module
+//! in order, see [`Metadata::exports`](crate::metadata::Metadata::exports).
+//! Calls from inside the module still go to the original function.
+
+use walrus::{
+    ir::{MemArg, StoreKind},
+    ExportId, ExportItem, FunctionBuilder, FunctionId, GlobalId, InstrSeqBuilder, LocalId,
+    MemoryId, Module, ValType,
+};
+
+use crate::{runtime::Runtime, value_len};
+
+/// The function exports of the original module, in the order of the export
+/// indices. Taken before the tracer adds its own exports.
+pub(crate) fn function_exports(module: &Module) -> Vec<(ExportId, FunctionId)> {
+    module
+        .exports
+        .iter()
+        .filter_map(|e| match e.item {
+            ExportItem::Function(f) => Some((e.id(), f)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Points the exports at wrappers. Returns the length of the longest record
+/// they write. Exports with `v128` values are not wrapped.
+pub(crate) fn wrap(
+    module: &mut Module,
+    exports: &[(ExportId, FunctionId)],
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    runtime: &Runtime,
+    check_mem_id: Option<FunctionId>,
+) -> u32 {
+    let mut max_record_len = 0;
+    for (index, (export, func)) in exports.iter().enumerate() {
+        let ty = module.types.get(module.funcs.get(*func).ty());
+        let (params, results) = (ty.params().to_vec(), ty.results().to_vec());
+        if params.iter().chain(&results).any(|t| *t == ValType::V128) {
+            continue;
+        }
+        let wrapper = Wrapper {
+            trace_mem_id,
+            mem_pointer,
+            index: index as u32,
+            args: params.iter().map(|t| module.locals.add(*t)).collect(),
+            results: results.iter().map(|t| module.locals.add(*t)).collect(),
+        };
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        let mut body = builder.func_body();
+        let call_len = wrapper.record(&mut body, 0x14, &wrapper.args, &params, runtime);
+        for arg in &wrapper.args {
+            body.local_get(*arg);
+        }
+        body.call(*func);
+        for result in wrapper.results.iter().rev() {
+            body.local_set(*result);
+        }
+        let return_len = wrapper.record(&mut body, 0x15, &wrapper.results, &results, runtime);
+        flush(&mut body, mem_pointer, runtime, check_mem_id);
+        for result in &wrapper.results {
+            body.local_get(*result);
+        }
+        let wrapper_id = builder.finish(wrapper.args, &mut module.funcs);
+        module.exports.get_mut(*export).item = ExportItem::Function(wrapper_id);
+        max_record_len = max_record_len.max(call_len).max(return_len);
+    }
+    max_record_len
+}
+
+struct Wrapper {
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    index: u32,
+    args: Vec<LocalId>,
+    results: Vec<LocalId>,
+}
+
+impl Wrapper {
+    /// ```wasm
+    /// global.get $mem_pointer
+    /// i32.const ;; code
+    /// i32.store8 $trace_mem offset=0
+    /// global.get $mem_pointer
+    /// i32.const ;; export idx
+    /// i32.store $trace_mem offset=1
+    /// global.get $mem_pointer
+    /// local.get $arg_1
+    /// xxx.store $trace_mem offset=5
+    /// ;; ... the other values
+    /// i32.const ;; record byte length
+    /// call $advance ;; or the inline mem_pointer increment
+    /// ```
+    fn record(
+        &self,
+        body: &mut InstrSeqBuilder,
+        code: i32,
+        locals: &[LocalId],
+        types: &[ValType],
+        runtime: &Runtime,
+    ) -> u32 {
+        body.global_get(self.mem_pointer).i32_const(code).store(
+            self.trace_mem_id,
+            StoreKind::I32_8 { atomic: false },
+            MemArg {
+                align: 1,
+                offset: 0,
+            },
+        );
+        body.global_get(self.mem_pointer)
+            .i32_const(self.index as i32)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                MemArg {
+                    align: 4,
+                    offset: 1,
+                },
+            );
+        let mut offset = 5;
+        for (local, ty) in locals.iter().zip(types) {
+            body.global_get(self.mem_pointer).local_get(*local);
+            let (kind, align) = match ty {
+                ValType::I64 => (StoreKind::I64 { atomic: false }, 8),
+                ValType::F32 => (StoreKind::F32, 4),
+                ValType::F64 => (StoreKind::F64, 8),
+                ValType::Externref | ValType::Funcref => {
+                    // References are opaque, only record whether they are null
+                    body.ref_is_null();
+                    (StoreKind::I32 { atomic: false }, 4)
+                }
+                _ => (StoreKind::I32 { atomic: false }, 4),
+            };
+            body.store(self.trace_mem_id, kind, MemArg { align, offset });
+            offset += value_len(*ty);
+        }
+        body.i32_const(offset as i32);
+        runtime.commit(body, self.mem_pointer);
+        offset
+    }
+}
+
+/// Passes the trace to `r3.check_mem` like a return of the module does.
+pub(crate) fn flush(
+    body: &mut InstrSeqBuilder,
+    mem_pointer: GlobalId,
+    runtime: &Runtime,
+    check_mem_id: Option<FunctionId>,
+) {
+    if let (Some(checkpoint), Some(_)) = (runtime.checkpoint, check_mem_id) {
+        body.call(checkpoint);
+    }
+    match (runtime.flush, check_mem_id) {
+        (Some(flush), _) => {
+            body.call(flush);
+        }
+        (None, Some(check_mem)) => {
+            body.call(check_mem).i32_const(0).global_set(mem_pointer);
+        }
+        // The ring buffer is never flushed
+        (None, None) => {}
+    }
+}
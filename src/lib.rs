@@ -1,18 +1,56 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    mem,
+};
 
-use anyhow::Result;
 use walrus::{
     ir::{
-        self, BinaryOp, Binop, Call, Const, GlobalGet, GlobalSet, IfElse, Instr, LocalGet,
-        LocalSet, MemArg, Store, StoreKind, Value, VisitorMut,
+        self, BinaryOp, Binop, Block, Call, Const, GlobalGet, GlobalSet, Instr, InstrSeqId,
+        InstrSeqType, LocalGet, LocalSet, LocalTee, MemArg, RefIsNull, Select, Store, StoreKind,
+        Value, VisitorMut,
     },
-    FunctionBuilder, FunctionId, GlobalId, InstrLocId, LocalId, MemoryId, Module, ModuleConfig,
-    TableId, Type, TypeId, ValType,
+    ExportItem, FunctionId, FunctionKind, GlobalId, InstrLocId, LocalFunction, LocalId, MemoryId,
+    Module, TableId, Type, TypeId, ValType,
 };
 use wasm_bindgen::prelude::*;
 
+mod bulk;
+pub mod call_tree;
+pub mod dap;
+pub mod decode;
+pub mod dwarf;
+mod error;
+mod exports;
+pub mod metadata;
+mod options;
+#[cfg(feature = "run")]
+pub mod run;
+mod runtime;
+pub mod sites;
+mod snapshot;
+pub mod state;
+pub mod symbolize;
+pub mod text;
+mod trap;
+mod validate;
+pub mod wasabi;
+mod wasi;
+mod watch;
+
+use bulk::Bulk;
+pub use error::InstrumentError;
+pub use options::{Options, Sampling};
+use runtime::{Runtime, Trigger};
+use sites::Sites;
+use trap::ShadowStack;
+pub use validate::{validate, InvalidInstr, ValidationError};
+
 type Instruction = (Instr, InstrLocId);
 
+/// Site id written for an instruction missing from the site map.
+const NO_SITE: u32 = u32::MAX;
+
 #[wasm_bindgen]
 pub fn instrument_wasm_js(buffer: &[u8]) -> Result<JsValue, JsValue> {
     let mut module = instrument_wasm(buffer).map_err(|e| JsValue::from_str(&e.to_string()))?;
@@ -20,9 +58,32 @@ pub fn instrument_wasm_js(buffer: &[u8]) -> Result<JsValue, JsValue> {
     Ok(value)
 }
 
-pub fn instrument_wasm(buffer: &[u8]) -> Result<Module> {
-    let mut module = Module::from_buffer(buffer)?;
-    let trace_mem_id = module.memories.add_local(false, 30000, None); // around 2 GB
+pub fn instrument_wasm(buffer: &[u8]) -> Result<Module, InstrumentError> {
+    instrument_wasm_with_options(buffer, &Options::default())
+}
+
+/// Instruments a module given in the binary or the text format. Use
+/// [`text::emit_wat`] to print the result with the inserted instructions
+/// annotated.
+pub fn instrument_wasm_with_options(
+    buffer: &[u8],
+    options: &Options,
+) -> Result<Module, InstrumentError> {
+    let buffer = text::parse(buffer).map_err(InstrumentError::Parse)?;
+    let mut module = Module::from_buffer(&buffer).map_err(InstrumentError::Parse)?;
+    let func_indices: HashMap<FunctionId, u32> = module
+        .funcs
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.id(), i as u32))
+        .collect();
+    let exports = exports::function_exports(&module);
+    let initial_state = snapshot::State::new(&module);
+    let trace_pages = match options.ring_buffer {
+        Some(size) => (size as u64).div_ceil(64 * 1024).max(1) as u32,
+        None => 30000, // around 2 GB
+    };
+    let trace_mem_id = module.memories.add_local(false, trace_pages, None);
     module.exports.add("trace", trace_mem_id);
     let mem_pointer = module.globals.add_local(
         walrus::ValType::I32,
@@ -30,92 +91,273 @@ pub fn instrument_wasm(buffer: &[u8]) -> Result<Module> {
         walrus::InitExpr::Value(walrus::ir::Value::I32(0)),
     );
     module.exports.add("trace_byte_length", mem_pointer);
-    let locals = add_locals(&mut module);
+    let shadow_stack = options.trap_info.then(|| ShadowStack::add(&mut module));
+    let added_locals = add_locals(&mut module);
     let module_types = Types::new(&module);
-    let current_type = module
-        .types
-        .get(module.functions().find(|_| true).unwrap().ty())
-        .clone();
+    let empty_type = match module.types.find(&[], &[]) {
+        Some(t) => t,
+        None => module.types.add(&[], &[]),
+    };
+    let current_type = module.types.get(empty_type).clone();
+    let func_types: Vec<(FunctionId, TypeId)> = module
+        .funcs
+        .iter_local()
+        .map(|(id, f)| (id, f.ty()))
+        .collect();
+    let body_types: HashMap<FunctionId, InstrSeqType> = func_types
+        .into_iter()
+        .map(|(id, ty)| {
+            let results = module.types.results(ty).to_vec();
+            (id, InstrSeqType::new(&mut module.types, &[], &results))
+        })
+        .collect();
     // Add return instruction at the end of each function (Importend for Instrumentation)
-    module.funcs.iter_local_mut().for_each(|(_, f)| {
+    module.funcs.iter_local_mut().for_each(|(id, f)| {
+        wrap_body(f, body_types[&id]);
         f.builder_mut().func_body().return_();
     });
-    // Mem check imported function
-    let typ = module.types.find(&[], &[]);
-    let typ = match typ {
-        Some(t) => t,
-        None => module.types.add(&[], &[]),
+    let original_funcs: Vec<FunctionId> = module.funcs.iter_local().map(|(id, _)| id).collect();
+    // Mem check imported function, not needed when the trace is kept in a ring buffer
+    let check_mem_id = match options.ring_buffer {
+        Some(_) => None,
+        None => Some(module.add_import_func("r3", "check_mem", empty_type).0),
+    };
+    let trigger = match &options.trigger_function {
+        Some(name) => Some(
+            find_function(&module, name)
+                .ok_or_else(|| InstrumentError::TriggerNotFound(name.clone()))?,
+        ),
+        None => None,
     };
-    let (check_mem_id, _) = module.add_import_func("r3", "check_mem", typ);
+    let mut runtime = Runtime::add(
+        &mut module,
+        options,
+        trace_mem_id,
+        mem_pointer,
+        check_mem_id,
+    );
+    // Checkpoints are written before flushes, there are none with a ring
+    // buffer
+    if let (Some(every), Some(_)) = (options.checkpoint_every, check_mem_id) {
+        runtime.checkpoint =
+            Some(initial_state.add_checkpoint(&mut module, trace_mem_id, mem_pointer, every));
+    }
+    // Records start behind the site prefix
+    let record_start = if options.sites { sites::SITE_LEN } else { 0 };
+    let watch = options.watching().then(|| {
+        watch::add(
+            &mut module,
+            &options.watch,
+            trace_mem_id,
+            mem_pointer,
+            &runtime,
+            record_start + 1,
+        )
+    });
+    let watch = watch.transpose()?;
+    let syscalls = wasi::add_helpers(
+        &mut module,
+        trace_mem_id,
+        mem_pointer,
+        &runtime,
+        &func_indices,
+    )?;
+    let bulk = bulk::add(
+        &mut module,
+        &original_funcs,
+        trace_mem_id,
+        mem_pointer,
+        &runtime,
+    );
     let mut generator = Generator::new(
         trace_mem_id,
         mem_pointer,
-        locals.0,
-        locals.1,
+        added_locals,
         module_types,
         current_type,
         check_mem_id,
     );
+    if let Some(advance) = runtime.advance {
+        generator.set_advance(advance);
+    }
+    if let Some(advance) = runtime.advance_sampled {
+        generator.set_advance_sampled(advance);
+    }
+    if let Some(watch) = watch {
+        generator.set_watch(watch);
+    }
+    if options.sites {
+        let sites = Sites::new(&buffer).map_err(InstrumentError::Parse)?;
+        generator.set_sites(&sites);
+    }
+    if let Some(flush) = runtime.flush {
+        generator.set_flush(flush);
+    }
+    if let Some(checkpoint) = runtime.checkpoint {
+        generator.set_checkpoint(checkpoint);
+    }
+    if let (Some(func), Some(state)) = (trigger, runtime.trigger) {
+        generator.set_trigger(func, state);
+    }
+    generator.set_syscalls(syscalls);
+    generator.set_bulk(bulk);
+    if let Some(shadow_stack) = shadow_stack {
+        generator.set_shadow_stack(shadow_stack);
+    }
     // Instrument
-    module.funcs.iter_local_mut().for_each(|(_, f)| {
+    for (id, f) in module
+        .funcs
+        .iter_local_mut()
+        .filter(|(id, _)| original_funcs.contains(id))
+    {
+        generator.set_current_func(id, func_indices[&id]);
         generator.set_current_func_type(module.types.get(f.ty()).clone());
+        generator.set_current_func_args(f.args.clone());
         generator.set_func_entry(true);
-        ir::dfs_pre_order_mut(&mut generator, f, f.entry_block())
-    });
+        ir::dfs_pre_order_mut(&mut generator, f, f.entry_block());
+        if let Some(error) = generator.take_error() {
+            return Err(error);
+        }
+    }
+    let wrapper_record_len = exports::wrap(
+        &mut module,
+        &exports,
+        trace_mem_id,
+        mem_pointer,
+        &runtime,
+        check_mem_id,
+    );
+    // A ring buffer only keeps the most recent events, not the start
+    if options.snapshot() && options.ring_buffer.is_none() {
+        initial_state.add_start(
+            &mut module,
+            trace_mem_id,
+            mem_pointer,
+            options.snapshot_memory,
+        );
+    }
+    if let Some(shadow_stack) = shadow_stack {
+        shadow_stack.add_trap_info(
+            &mut module,
+            trace_mem_id,
+            mem_pointer,
+            &runtime,
+            check_mem_id,
+        );
+    }
+    runtime.finish(
+        &mut module,
+        generator.max_record_len.max(wrapper_record_len),
+    )?;
+    if options.validate {
+        validate::validate(&mut module, &func_indices).map_err(InstrumentError::Invalid)?;
+    }
     // dbg!(&module);
     Ok(module)
 }
 
+/// Looks a function up by export name or by its name in the `name` section.
+pub(crate) fn find_function(module: &Module, name: &str) -> Option<FunctionId> {
+    module
+        .exports
+        .iter()
+        .find_map(|e| match e.item {
+            ExportItem::Function(f) if e.name == name => Some(f),
+            _ => None,
+        })
+        .or_else(|| module.funcs.by_name(name))
+}
+
+/// Moves the body of a function into a block and retargets the branches to
+/// the function label to that block, so they reach the `return` appended
+/// after it instead of leaving the function unrecorded.
+fn wrap_body(func: &mut LocalFunction, ty: InstrSeqType) {
+    let entry = func.entry_block();
+    let instrs = mem::take(func.builder_mut().func_body().instrs_mut());
+    let mut body = func.builder_mut().dangling_instr_seq(ty);
+    *body.instrs_mut() = instrs;
+    let body = body.id();
+    func.builder_mut().func_body().instr(Block { seq: body });
+    ir::dfs_pre_order_mut(
+        &mut Retarget {
+            from: entry,
+            to: body,
+        },
+        func,
+        body,
+    );
+}
+
+struct Retarget {
+    from: InstrSeqId,
+    to: InstrSeqId,
+}
+
+impl VisitorMut for Retarget {
+    fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+        let retarget = |block: &mut InstrSeqId| {
+            if *block == self.from {
+                *block = self.to;
+            }
+        };
+        match instr {
+            Instr::Br(br) => retarget(&mut br.block),
+            Instr::BrIf(br) => retarget(&mut br.block),
+            Instr::BrTable(br) => {
+                br.blocks.iter_mut().for_each(retarget);
+                retarget(&mut br.default);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn instr_offset(loc: Option<InstrLocId>) -> Option<u32> {
+    loc.filter(|l| !l.is_default()).map(|l| l.data())
+}
+
+/// Bytes a value takes in the trace.
+fn value_len(val_type: ValType) -> u32 {
+    match val_type {
+        ValType::I64 | ValType::F64 => 8,
+        ValType::V128 => 16,
+        // References are recorded as an i32 null flag
+        ValType::I32 | ValType::F32 | ValType::Externref | ValType::Funcref => 4,
+    }
+}
+
 type Locals = HashMap<ValType, Vec<LocalId>>;
-fn add_locals(module: &mut Module) -> (Locals, Locals) {
-    let mut added_locals: Locals = HashMap::new();
-    added_locals.insert(ValType::I32, vec![module.locals.add(ValType::I32)]);
-    added_locals.insert(ValType::I64, vec![module.locals.add(ValType::I64)]);
-    added_locals.insert(ValType::F32, vec![module.locals.add(ValType::F32)]);
-    added_locals.insert(ValType::F64, vec![module.locals.add(ValType::F64)]);
+/// Adds the scratch locals used to save values from the stack. Every type
+/// gets at least two of them, so a store can save an address and a value of
+/// the same type, and as many as the longest list of one type in a signature
+/// (plus the table index of `call_indirect`).
+fn add_locals(module: &mut Module) -> Locals {
+    const TYPES: [ValType; 6] = [
+        ValType::I32,
+        ValType::I64,
+        ValType::F32,
+        ValType::F64,
+        ValType::Externref,
+        ValType::Funcref,
+    ];
+    let mut amounts: HashMap<ValType, usize> = TYPES.into_iter().map(|t| (t, 2)).collect();
     module.types.iter().for_each(|t| {
-        let params = t.params();
-        let mut amounts: HashMap<ValType, usize> = HashMap::new();
-        params.iter().for_each(|t| {
-            let _ = amounts.entry(*t).and_modify(|e| *e += 1).or_insert(1);
-        });
-        amounts.iter().for_each(|(t, a)| {
-            added_locals.entry(*t).and_modify(|e| {
-                if e.len() < *a {
-                    let mut vec = Vec::with_capacity(*a);
-                    for _ in 0..*a {
-                        vec.push(module.locals.add(*t));
-                    }
-                    *e = vec;
-                }
-            });
-        });
-        let results = t.results();
-        results.iter().for_each(|t| {
-            let _ = amounts.entry(*t).and_modify(|e| *e += 1).or_insert(1);
-        });
-        amounts.iter().for_each(|(t, a)| {
-            added_locals.entry(*t).and_modify(|e| {
-                if e.len() < *a {
-                    let mut vec = Vec::with_capacity(*a);
-                    for _ in 0..*a {
-                        vec.push(module.locals.add(*t));
-                    }
-                    *e = vec;
-                }
+        for (values, extra_i32) in [(t.params(), 1), (t.results(), 0)] {
+            let mut counts: HashMap<ValType, usize> = HashMap::new();
+            counts.insert(ValType::I32, extra_i32);
+            values
+                .iter()
+                .for_each(|t| *counts.entry(*t).or_insert(0) += 1);
+            counts.into_iter().for_each(|(t, count)| {
+                amounts.entry(t).and_modify(|a| *a = (*a).max(count));
             });
-        });
-    });
-    let mut locals = HashMap::new();
-    module.locals.iter().for_each(|l| {
-        let _ = locals
-            .entry(l.ty())
-            .and_modify(|e: &mut Vec<LocalId>| {
-                e.push(l.id());
-            })
-            .or_insert(vec![l.id()]);
+        }
     });
-    (locals, added_locals)
+    // In a fixed order, so the output does not depend on the hash seed
+    TYPES
+        .into_iter()
+        .map(|t| (t, (0..amounts[&t]).map(|_| module.locals.add(t)).collect()))
+        .collect()
 }
 
 #[derive(Debug)]
@@ -124,6 +366,12 @@ struct Types {
     by_id: HashMap<TypeId, Type>,
     global_types: HashMap<GlobalId, ValType>,
     element_types: HashMap<TableId, ValType>,
+    imported_funcs: HashSet<FunctionId>,
+    /// Indices in the original module, written to the trace.
+    func_indices: HashMap<FunctionId, u32>,
+    type_indices: HashMap<TypeId, u32>,
+    global_indices: HashMap<GlobalId, u32>,
+    table_indices: HashMap<TableId, u32>,
 }
 
 impl Types {
@@ -132,21 +380,34 @@ impl Types {
             .functions()
             .map(|f| (f.id(), module.types.get(f.ty()).clone()))
             .collect();
-        let by_id = module
-            .functions()
-            .map(|f| (f.ty(), module.types.get(f.ty()).clone()))
-            .collect();
+        let by_id = module.types.iter().map(|t| (t.id(), t.clone())).collect();
         let global_types = module.globals.iter().map(|g| (g.id(), g.ty)).collect();
         let element_types = module
             .tables
             .iter()
             .map(|t| (t.id(), t.element_ty))
             .collect();
+        let imported_funcs = module
+            .funcs
+            .iter()
+            .filter(|f| matches!(f.kind, FunctionKind::Import(_)))
+            .map(|f| f.id())
+            .collect();
+        // walrus keeps the items in the order of the index spaces
+        let func_indices = indices(module.funcs.iter().map(|f| f.id()));
+        let type_indices = indices(module.types.iter().map(|t| t.id()));
+        let global_indices = indices(module.globals.iter().map(|g| g.id()));
+        let table_indices = indices(module.tables.iter().map(|t| t.id()));
         Self {
             by_func,
             by_id,
             global_types,
             element_types,
+            imported_funcs,
+            func_indices,
+            type_indices,
+            global_indices,
+            table_indices,
         }
     }
 
@@ -165,6 +426,30 @@ impl Types {
     fn get_element_type(&self, id: &TableId) -> Option<&ValType> {
         self.element_types.get(id)
     }
+
+    fn is_import(&self, id: &FunctionId) -> bool {
+        self.imported_funcs.contains(id)
+    }
+
+    fn func_index(&self, id: &FunctionId) -> u32 {
+        self.func_indices[id]
+    }
+
+    fn type_index(&self, id: &TypeId) -> u32 {
+        self.type_indices[id]
+    }
+
+    fn global_index(&self, id: &GlobalId) -> u32 {
+        self.global_indices[id]
+    }
+
+    fn table_index(&self, id: &TableId) -> u32 {
+        self.table_indices[id]
+    }
+}
+
+fn indices<T: std::hash::Hash + Eq>(ids: impl Iterator<Item = T>) -> HashMap<T, u32> {
+    ids.enumerate().map(|(i, id)| (id, i as u32)).collect()
 }
 
 enum InstructionsEnum {
@@ -197,214 +482,381 @@ impl InstructionsEnum {
 struct Generator {
     trace_mem_id: MemoryId,
     mem_pointer: GlobalId,
-    locals: Locals,
     added_locals: Locals,
     module_types: Types,
     current_func_type: Type,
+    current_func_args: Vec<LocalId>,
     func_entry: bool,
-    check_mem_id: FunctionId,
+    check_mem_id: Option<FunctionId>,
+    advance_id: Option<FunctionId>,
+    advance_sampled_id: Option<FunctionId>,
+    watch_id: Option<FunctionId>,
+    flush_id: Option<FunctionId>,
+    checkpoint_id: Option<FunctionId>,
+    trigger: Option<(FunctionId, Trigger)>,
+    /// Helpers recording the memory written by WASI imports.
+    syscalls: HashMap<FunctionId, FunctionId>,
+    /// Helpers recording the ranges written by bulk instructions.
+    bulk: Option<Bulk>,
+    /// Scratch locals holding the values of the last `save_stack`, in stack
+    /// order.
+    saved_locals: Vec<LocalId>,
+    shadow_stack: Option<ShadowStack>,
+    current_func: Option<FunctionId>,
+    current_func_index: u32,
+    /// Site ids by instruction offset and of the function ends, with
+    /// `Options::sites`.
+    site_ids: Option<(HashMap<u32, u32>, HashMap<u32, u32>)>,
+    /// Site id of the instruction being instrumented.
+    site: u32,
+    max_record_len: u32,
+    error: Option<InstrumentError>,
 }
 
 impl VisitorMut for Generator {
     fn start_instr_seq_mut(&mut self, seq: &mut ir::InstrSeq) {
         let mut added_instr_count = 0;
         let mut instrumentation_code = Vec::new();
-        seq.clone().iter().enumerate().for_each(|(i, (instr, _))| {
-            let mut gen_seq: Vec<Instruction> = vec![];
-            let offset: &mut u32 = &mut 0;
-            if self.func_entry == true {
-                let opcode = 0x02;
-                let c = self.current_func_type.clone();
-                let params = c.params();
-                gen_seq.append(
-                    &mut InstructionsEnum::from_vec(vec![
-                        self.trace_code(opcode, offset),
-                        self.save_locals(params, offset),
-                        self.increment_mem_pointer(*offset),
-                    ])
-                    .flatten(),
-                );
-                self.func_entry = false;
-            }
-            match instr {
-                Instr::Load(load) => {
-                    let (opcode, local_type) = match load.kind {
-                        ir::LoadKind::I32 { .. } => (0x28, ValType::I32),
-                        ir::LoadKind::I64 { .. } => (0x29, ValType::I64),
-                        ir::LoadKind::F32 => (0x2A, ValType::F32),
-                        ir::LoadKind::F64 => (0x2B, ValType::F64),
-                        ir::LoadKind::V128 => todo!(),
-                        ir::LoadKind::I32_8 { .. } => (0x2C, ValType::I32),
-                        ir::LoadKind::I32_16 { .. } => (0x2E, ValType::I32),
-                        ir::LoadKind::I64_8 { .. } => (0x30, ValType::I64),
-                        ir::LoadKind::I64_16 { .. } => (0x32, ValType::I64),
-                        ir::LoadKind::I64_32 { .. } => (0x34, ValType::I64),
-                    };
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(&[ValType::I32], offset),
-                            self.instr(instr.clone()),
-                            self.save_stack(&[local_type], offset),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::Store(store) => {
-                    let (opcode, local_type) = match store.kind {
-                        ir::StoreKind::I32 { .. } => (0x36, ValType::I32),
-                        ir::StoreKind::I64 { .. } => (0x37, ValType::I64),
-                        ir::StoreKind::F32 => (0x38, ValType::F32),
-                        ir::StoreKind::F64 => (0x39, ValType::F64),
-                        ir::StoreKind::V128 => todo!(),
-                        ir::StoreKind::I32_8 { .. } => (0x3A, ValType::I32),
-                        ir::StoreKind::I32_16 { .. } => (0x3B, ValType::I32),
-                        ir::StoreKind::I64_8 { .. } => (0x3C, ValType::I64),
-                        ir::StoreKind::I64_16 { .. } => (0x3D, ValType::I64),
-                        ir::StoreKind::I64_32 { .. } => (0x3E, ValType::I64),
-                    };
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(&[ValType::I32, local_type], offset),
-                            self.instr(instr.clone()),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::Call(call) => {
-                    let opcode = 0x10;
-                    let typ = self.module_types.get_by_func(&call.func).unwrap().clone();
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(typ.params(), offset),
-                            self.instr(instr.clone()),
-                            self.save_stack(typ.results(), offset),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::CallIndirect(call) => {
-                    let opcode = 0x11;
-                    let typ = self.module_types.get_by_id(&call.ty).unwrap().clone();
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(&[typ.params(), &[ValType::I32]].concat(), offset),
-                            self.instr(instr.clone()),
-                            self.save_stack(typ.results(), offset),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::GlobalGet(g) => {
-                    let opcode = 0x23;
-                    let typ = self
-                        .module_types
-                        .get_global_type(&g.global)
-                        .unwrap()
-                        .clone();
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.instr(instr.clone()),
-                            self.save_stack(&[typ], offset),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::GlobalSet(get) => {
-                    let opcode = 0x23;
-                    let typ = self
-                        .module_types
-                        .get_global_type(&get.global)
-                        .unwrap()
-                        .clone();
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(&[typ], offset),
-                            self.instr(instr.clone()),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::TableGet(set) => {
-                    let opcode = 0x26;
-                    let typ = self
-                        .module_types
-                        .get_element_type(&set.table)
-                        .unwrap()
-                        .clone();
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(&[ValType::I32], offset),
-                            self.instr(instr.clone()),
-                            self.save_stack(&[typ], offset),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
-                }
-                Instr::TableSet(set) => {
-                    let opcode = 0x26;
-                    let typ = self
-                        .module_types
-                        .get_element_type(&set.table)
-                        .unwrap()
-                        .clone();
-                    gen_seq.append(
-                        &mut InstructionsEnum::from_vec(vec![
-                            self.trace_code(opcode, offset),
-                            self.save_stack(&[ValType::I32, typ], offset),
-                            self.instr(instr.clone()),
-                            self.increment_mem_pointer(*offset),
-                        ])
-                        .flatten(),
-                    );
+        seq.clone()
+            .iter()
+            .enumerate()
+            .for_each(|(i, (instr, loc))| {
+                if self.error.is_some() {
+                    return;
                 }
-                // Instr::TableGrow(_) => todo!(),
-                // Instr::TableFill(_) => todo!(),
-                // Instr::LoadSimd(_) => todo!(),
-                // Instr::TableInit(_) => todo!(),
-                // Instr::ElemDrop(_) => todo!(),
-                // Instr::TableCopy(_) => todo!(),
-                Instr::Return(_) => {
-                    let opcode = 0x0F;
+                let instr_loc = *loc;
+                let loc = Some(instr_loc);
+                let mut gen_seq: Vec<Instruction> = vec![];
+                let offset: &mut u32 = &mut 0;
+                if self.func_entry {
+                    let opcode = 0x02;
                     let c = self.current_func_type.clone();
-                    let returns = c.results();
+                    let params = c.params();
+                    if !self.check_recordable(params, None) {
+                        return;
+                    }
                     gen_seq.append(
                         &mut InstructionsEnum::from_vec(vec![
+                            self.enter_trigger(),
+                            self.push_frame(),
                             self.trace_code(opcode, offset),
-                            self.save_stack(returns, offset),
+                            self.trace_index(self.current_func_index, offset),
+                            self.save_locals(params, offset),
                             self.increment_mem_pointer(*offset),
-                            self.check_mem(),
-                            self.instr(instr.clone()),
                         ])
                         .flatten(),
                     );
+                    *offset = 0;
+                    self.func_entry = false;
                 }
-                // Instr::MemoryGrow(_) => todo!(),
-                // Instr::MemoryInit(_) => todo!(),
-                // Instr::DataDrop(_) => todo!(),
-                // Instr::MemoryCopy(_) => todo!(),
-                // Instr::MemoryFill(_) => todo!(),
-                _ => return,
-            };
-            let gen_length = gen_seq.len() - 1;
-            instrumentation_code.push((i + added_instr_count, gen_seq));
-            added_instr_count += gen_length;
-        });
+                if trap::may_trap(instr) {
+                    gen_seq.append(&mut self.trap_site(loc).flatten());
+                }
+                self.site = self.site_of(loc);
+                match instr {
+                    Instr::Load(load) => {
+                        let (opcode, local_type) = match load.kind {
+                            ir::LoadKind::I32 { .. } => (0x28, ValType::I32),
+                            ir::LoadKind::I64 { .. } => (0x29, ValType::I64),
+                            ir::LoadKind::F32 => (0x2A, ValType::F32),
+                            ir::LoadKind::F64 => (0x2B, ValType::F64),
+                            ir::LoadKind::V128 => return self.fail_unsupported("simd", loc),
+                            ir::LoadKind::I32_8 { .. } => (0x2C, ValType::I32),
+                            ir::LoadKind::I32_16 { .. } => (0x2E, ValType::I32),
+                            ir::LoadKind::I64_8 { .. } => (0x30, ValType::I64),
+                            ir::LoadKind::I64_16 { .. } => (0x32, ValType::I64),
+                            ir::LoadKind::I64_32 { .. } => (0x34, ValType::I64),
+                        };
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.save_stack(&[ValType::I32], offset),
+                                self.save_effective_address(load.arg),
+                                self.instr(instr.clone(), instr_loc),
+                                self.save_stack(&[local_type], offset),
+                                self.increment_mem_pointer_watched(*offset, opcode as u8),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::Store(store) => {
+                        let (opcode, local_type) = match store.kind {
+                            ir::StoreKind::I32 { .. } => (0x36, ValType::I32),
+                            ir::StoreKind::I64 { .. } => (0x37, ValType::I64),
+                            ir::StoreKind::F32 => (0x38, ValType::F32),
+                            ir::StoreKind::F64 => (0x39, ValType::F64),
+                            ir::StoreKind::V128 => return self.fail_unsupported("simd", loc),
+                            ir::StoreKind::I32_8 { .. } => (0x3A, ValType::I32),
+                            ir::StoreKind::I32_16 { .. } => (0x3B, ValType::I32),
+                            ir::StoreKind::I64_8 { .. } => (0x3C, ValType::I64),
+                            ir::StoreKind::I64_16 { .. } => (0x3D, ValType::I64),
+                            ir::StoreKind::I64_32 { .. } => (0x3E, ValType::I64),
+                        };
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.save_stack(&[ValType::I32, local_type], offset),
+                                self.save_effective_address(store.arg),
+                                self.instr(instr.clone(), instr_loc),
+                                self.increment_mem_pointer_watched(*offset, opcode as u8),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::Call(call) => {
+                        // Calls leaving the module get their own code
+                        let opcode = if self.module_types.is_import(&call.func) {
+                            0x13
+                        } else {
+                            0x10
+                        };
+                        let typ = match self.module_types.get_by_func(&call.func) {
+                            Some(typ) => typ.clone(),
+                            None => return self.fail_unknown("function", loc),
+                        };
+                        if !self.check_recordable(&[typ.params(), typ.results()].concat(), loc) {
+                            return;
+                        }
+                        let host = self.module_types.is_import(&call.func);
+                        let func_index = self.module_types.func_index(&call.func);
+                        let type_index = self.module_types.type_index(&typ.id());
+                        // The call record is committed before the call, the
+                        // callee writes its own records behind it
+                        let end_offset = &mut 0;
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(func_index, offset),
+                                self.save_stack(typ.params(), offset),
+                            ])
+                            .flatten(),
+                        );
+                        // The single i32 result of a syscall gets a scratch
+                        // local of its own, the args are still available
+                        let args = self.saved_locals.clone();
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.increment_mem_pointer(*offset),
+                                self.save_sp(host),
+                                self.instr(instr.clone(), instr_loc),
+                                self.restore_sp(host),
+                                self.trace_site(end_offset),
+                                self.trace_code(0x0B, end_offset),
+                                self.trace_index(type_index, end_offset),
+                                self.save_stack(typ.results(), end_offset),
+                                self.increment_mem_pointer(*end_offset),
+                                self.record_syscall(call.func, &args),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::CallIndirect(call) => {
+                        let opcode = 0x11;
+                        let typ = match self.module_types.get_by_id(&call.ty) {
+                            Some(typ) => typ.clone(),
+                            None => return self.fail_unknown("type", loc),
+                        };
+                        if !self.check_recordable(&[typ.params(), typ.results()].concat(), loc) {
+                            return;
+                        }
+                        let type_index = self.module_types.type_index(&call.ty);
+                        let table_index = self.module_types.table_index(&call.table);
+                        // The callee may be an import
+                        let host = true;
+                        let end_offset = &mut 0;
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(type_index, offset),
+                                self.trace_index(table_index, offset),
+                                self.save_stack(&[typ.params(), &[ValType::I32]].concat(), offset),
+                                self.increment_mem_pointer(*offset),
+                                self.save_sp(host),
+                                self.instr(instr.clone(), instr_loc),
+                                self.restore_sp(host),
+                                self.trace_site(end_offset),
+                                self.trace_code(0x0B, end_offset),
+                                self.trace_index(type_index, end_offset),
+                                self.save_stack(typ.results(), end_offset),
+                                self.increment_mem_pointer(*end_offset),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::GlobalGet(g) => {
+                        let opcode = 0x23;
+                        let typ = match self.module_types.get_global_type(&g.global) {
+                            Some(typ) => *typ,
+                            None => return self.fail_unknown("global", loc),
+                        };
+                        if !self.check_recordable(&[typ], loc) {
+                            return;
+                        }
+                        let global_index = self.module_types.global_index(&g.global);
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(global_index, offset),
+                                self.instr(instr.clone(), instr_loc),
+                                self.save_stack(&[typ], offset),
+                                self.increment_mem_pointer(*offset),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::GlobalSet(get) => {
+                        let opcode = 0x24;
+                        let typ = match self.module_types.get_global_type(&get.global) {
+                            Some(typ) => *typ,
+                            None => return self.fail_unknown("global", loc),
+                        };
+                        if !self.check_recordable(&[typ], loc) {
+                            return;
+                        }
+                        let global_index = self.module_types.global_index(&get.global);
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(global_index, offset),
+                                self.save_stack(&[typ], offset),
+                                self.instr(instr.clone(), instr_loc),
+                                self.increment_mem_pointer(*offset),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::TableGet(set) => {
+                        let opcode = 0x25;
+                        let typ = match self.module_types.get_element_type(&set.table) {
+                            Some(typ) => *typ,
+                            None => return self.fail_unknown("table", loc),
+                        };
+                        let table_index = self.module_types.table_index(&set.table);
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(table_index, offset),
+                                self.save_stack(&[ValType::I32], offset),
+                                self.instr(instr.clone(), instr_loc),
+                                self.save_stack(&[typ], offset),
+                                self.increment_mem_pointer(*offset),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::TableSet(set) => {
+                        let opcode = 0x26;
+                        let typ = match self.module_types.get_element_type(&set.table) {
+                            Some(typ) => *typ,
+                            None => return self.fail_unknown("table", loc),
+                        };
+                        let table_index = self.module_types.table_index(&set.table);
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(table_index, offset),
+                                self.save_stack(&[ValType::I32, typ], offset),
+                                self.instr(instr.clone(), instr_loc),
+                                self.increment_mem_pointer(*offset),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::TableFill(ir::TableFill { table })
+                    | Instr::TableCopy(ir::TableCopy { dst: table, .. })
+                    | Instr::TableInit(ir::TableInit { table, .. }) => {
+                        let (opcode, value) = match instr {
+                            Instr::TableFill(_) => (bulk::TABLE_FILL, self.element_type(table)),
+                            Instr::TableCopy(_) => (bulk::TABLE_COPY, Some(ValType::I32)),
+                            _ => (bulk::TABLE_INIT, Some(ValType::I32)),
+                        };
+                        let (Some(value), Some(helper)) = (value, self.bulk_table(table)) else {
+                            return self.fail_unknown("table", loc);
+                        };
+                        gen_seq.append(
+                            &mut self
+                                .record_range(instr, instr_loc, value, opcode, helper)
+                                .flatten(),
+                        );
+                    }
+                    Instr::TableGrow(grow) => {
+                        let Some(helper) = self.bulk_table(&grow.table) else {
+                            return self.fail_unknown("table", loc);
+                        };
+                        gen_seq.append(&mut self.record_grow(instr, instr_loc, helper).flatten());
+                    }
+                    Instr::Return(_) => {
+                        let opcode = 0x0F;
+                        let c = self.current_func_type.clone();
+                        let returns = c.results();
+                        if !self.check_recordable(returns, loc) {
+                            return;
+                        }
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.trace_index(self.current_func_index, offset),
+                                self.save_stack(returns, offset),
+                                self.increment_mem_pointer(*offset),
+                                self.exit_trigger(),
+                                self.pop_frame(),
+                                self.check_mem(),
+                                self.instr(instr.clone(), instr_loc),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::MemoryGrow(_) => {
+                        let opcode = 0x1B;
+                        gen_seq.append(
+                            &mut InstructionsEnum::from_vec(vec![
+                                self.trace_site(offset),
+                                self.trace_code(opcode, offset),
+                                self.save_stack(&[ValType::I32], offset),
+                                self.instr(instr.clone(), instr_loc),
+                                self.save_stack(&[ValType::I32], offset),
+                                self.increment_mem_pointer(*offset),
+                            ])
+                            .flatten(),
+                        );
+                    }
+                    Instr::MemoryFill(ir::MemoryFill { memory })
+                    | Instr::MemoryCopy(ir::MemoryCopy { dst: memory, .. })
+                    | Instr::MemoryInit(ir::MemoryInit { memory, .. }) => {
+                        let opcode = match instr {
+                            Instr::MemoryFill(_) => bulk::MEMORY_FILL,
+                            Instr::MemoryCopy(_) => bulk::MEMORY_COPY,
+                            _ => bulk::MEMORY_INIT,
+                        };
+                        let helper = self.bulk.as_ref().and_then(|b| b.memories.get(memory));
+                        let Some(helper) = helper.copied() else {
+                            return self.fail_unknown("memory", loc);
+                        };
+                        gen_seq.append(
+                            &mut self
+                                .record_range(instr, instr_loc, ValType::I32, opcode, helper)
+                                .flatten(),
+                        );
+                    }
+                    // Keep the function entry record
+                    _ if !gen_seq.is_empty() => gen_seq.push((instr.clone(), instr_loc)),
+                    _ => return,
+                };
+                let gen_length = gen_seq.len() - 1;
+                instrumentation_code.push((i + added_instr_count, gen_seq));
+                added_instr_count += gen_length;
+            });
         instrumentation_code.iter().for_each(|(i, gen_seq)| {
-            seq.splice(i.clone()..(i.clone() + 1), gen_seq.clone());
+            seq.splice(*i..(*i + 1), gen_seq.clone());
         })
     }
 }
@@ -413,87 +865,217 @@ impl Generator {
     fn new(
         trace_mem_id: MemoryId,
         mem_pointer: GlobalId,
-        locals: Locals,
         added_locals: Locals,
         module_types: Types,
         current_func_type: Type,
-        check_mem_id: FunctionId,
+        check_mem_id: Option<FunctionId>,
     ) -> Self {
         Self {
             trace_mem_id,
             mem_pointer,
-            locals,
             added_locals,
             module_types,
             current_func_type,
+            current_func_args: Vec::new(),
             func_entry: true,
             check_mem_id,
+            advance_id: None,
+            advance_sampled_id: None,
+            watch_id: None,
+            flush_id: None,
+            checkpoint_id: None,
+            trigger: None,
+            syscalls: HashMap::new(),
+            bulk: None,
+            saved_locals: Vec::new(),
+            shadow_stack: None,
+            current_func: None,
+            current_func_index: 0,
+            site_ids: None,
+            site: NO_SITE,
+            max_record_len: 0,
+            error: None,
         }
     }
 
     fn check_mem(&self) -> InstructionsEnum {
+        if self.check_mem_id.is_none() {
+            return InstructionsEnum::Sequence(vec![]);
+        }
+        let checkpoint = match self.checkpoint_id {
+            Some(checkpoint) => self.call(checkpoint),
+            None => InstructionsEnum::Sequence(vec![]),
+        };
+        if let Some(flush) = self.flush_id {
+            return InstructionsEnum::from_vec(vec![checkpoint, self.call(flush)]);
+        }
         InstructionsEnum::from_vec(vec![
+            checkpoint,
             // self.get_const(ir::Value::I32(64000 * 20000)),
             // self.global_get(self.mem_pointer),
             // self.binop(BinaryOp::I32Eq),
             // self.check_mem_test_and_call(),
             self.call_check_mem(),
             self.get_const(Value::I32(0)),
-            self.global_set(self.mem_pointer)
+            self.global_set(self.mem_pointer),
         ])
     }
 
     fn save_locals(&mut self, values: &[ValType], offset: &mut u32) -> InstructionsEnum {
-        let mut locals = Vec::new();
         InstructionsEnum::from_vec(
             values
                 .iter()
-                .map(|t| {
-                    let local = *self.added_locals.get(t).unwrap().get(0).unwrap();
-                    locals.push(local);
-                    self.locals.entry(*t).and_modify(|e| {
-                        let id = e.remove(0);
-                        e.push(id);
-                    });
-                    let instrs = InstructionsEnum::from_vec(vec![
+                .zip(self.current_func_args.clone())
+                .map(|(t, local)| {
+                    InstructionsEnum::from_vec(vec![
                         self.global_get(self.mem_pointer),
                         self.local_get(local),
                         self.store_val_to_trace(*t, offset),
-                    ]);
-                    instrs
+                    ])
                 })
                 .collect(),
         )
     }
 
+    /// Saves the values on top of the stack to the trace and puts them back.
+    /// The values are written in stack order, the deepest one first.
     fn save_stack(&mut self, values: &[ValType], offset: &mut u32) -> InstructionsEnum {
+        let offsets: Vec<u32> = values
+            .iter()
+            .map(|t| {
+                let value_offset = *offset;
+                *offset += value_len(*t);
+                value_offset
+            })
+            .collect();
         let mut locals = Vec::new();
-        InstructionsEnum::from_vec(vec![
+        let code = InstructionsEnum::from_vec(vec![
             InstructionsEnum::from_vec(
                 values
                     .iter()
+                    .zip(offsets)
                     .rev()
-                    .map(|t| {
-                        let local = *self.added_locals.get(t).unwrap().get(0).unwrap();
+                    .map(|(t, mut value_offset)| {
+                        let local = *self.added_locals.get(t).unwrap().first().unwrap();
                         locals.push(local);
                         self.added_locals.entry(*t).and_modify(|e| {
                             let id = e.remove(0);
                             e.push(id);
                         });
-                        let instrs = InstructionsEnum::from_vec(vec![
+
+                        InstructionsEnum::from_vec(vec![
                             self.local_set(local),
                             self.global_get(self.mem_pointer),
                             self.local_get(local),
-                            self.store_val_to_trace(*t, offset),
-                        ]);
-                        instrs
+                            self.store_val_to_trace(*t, &mut value_offset),
+                        ])
                     })
                     .collect(),
             ),
             InstructionsEnum::from_vec(locals.iter().rev().map(|l| self.local_get(*l)).collect()),
+        ]);
+        locals.reverse();
+        self.saved_locals = locals;
+        code
+    }
+
+    /// Passes the args of a WASI call and its errno, on top of the stack, to
+    /// the helper recording the memory the host wrote.
+    fn record_syscall(&self, func: FunctionId, args: &[LocalId]) -> InstructionsEnum {
+        let Some(helper) = self.syscalls.get(&func) else {
+            return InstructionsEnum::Sequence(vec![]);
+        };
+        InstructionsEnum::from_vec(vec![
+            InstructionsEnum::from_vec(args.iter().map(|l| self.local_get(*l)).collect()),
+            self.local_get(self.saved_locals[0]),
+            self.call(*helper),
+        ])
+    }
+
+    /// Runs a bulk instruction taking a destination, a value of type `value`
+    /// and a length, then passes the destination and the length to the
+    /// helper recording the range it wrote.
+    fn record_range(
+        &self,
+        instr: &Instr,
+        loc: InstrLocId,
+        value: ValType,
+        opcode: u8,
+        helper: FunctionId,
+    ) -> InstructionsEnum {
+        let [dst, len, scratch] = self.bulk.as_ref().unwrap().locals;
+        let value_local = match value {
+            ValType::I32 => scratch,
+            t => self.added_locals[&t][0],
+        };
+        InstructionsEnum::from_vec(vec![
+            self.local_set(len),
+            self.local_set(value_local),
+            self.local_tee(dst),
+            self.local_get(value_local),
+            self.local_get(len),
+            self.instr(instr.clone(), loc),
+            self.get_const(Value::I32(opcode as i32)),
+            self.local_get(dst),
+            self.local_get(len),
+            self.call(helper),
         ])
     }
 
+    /// Runs `table.grow` and passes the old size and the number of added
+    /// elements, 0 if the table could not grow, to the helper recording them.
+    fn record_grow(&self, instr: &Instr, loc: InstrLocId, helper: FunctionId) -> InstructionsEnum {
+        let [_, len, result] = self.bulk.as_ref().unwrap().locals;
+        InstructionsEnum::from_vec(vec![
+            self.local_tee(len),
+            self.instr(instr.clone(), loc),
+            self.local_tee(result),
+            self.get_const(Value::I32(bulk::TABLE_GROW as i32)),
+            self.local_get(result),
+            self.local_get(len),
+            self.get_const(Value::I32(0)),
+            self.local_get(result),
+            self.get_const(Value::I32(-1)),
+            self.binop(BinaryOp::I32Ne),
+            InstructionsEnum::Single((Instr::Select(Select { ty: None }), InstrLocId::default())),
+            self.call(helper),
+        ])
+    }
+
+    fn bulk_table(&self, table: &TableId) -> Option<FunctionId> {
+        self.bulk.as_ref()?.tables.get(table).copied()
+    }
+
+    fn element_type(&self, table: &TableId) -> Option<ValType> {
+        self.module_types.get_element_type(table).copied()
+    }
+
+    /// Writes the site prefix of the records of the current instruction,
+    /// with `Options::sites`.
+    fn trace_site(&self, offset: &mut u32) -> InstructionsEnum {
+        if self.site_ids.is_none() {
+            return InstructionsEnum::Sequence(vec![]);
+        }
+        InstructionsEnum::from_vec(vec![
+            self.trace_code(sites::SITE_CODE as i32, offset),
+            self.trace_index(self.site, offset),
+        ])
+    }
+
+    /// The site id of the instruction at `loc`, the return appended to the
+    /// body gets the one of the final `end`.
+    fn site_of(&self, loc: Option<InstrLocId>) -> u32 {
+        let Some((by_offset, function_ends)) = &self.site_ids else {
+            return NO_SITE;
+        };
+        match instr_offset(loc) {
+            Some(offset) => by_offset.get(&offset),
+            None => function_ends.get(&self.current_func_index),
+        }
+        .copied()
+        .unwrap_or(NO_SITE)
+    }
+
     fn trace_code(&self, code: i32, offset: &mut u32) -> InstructionsEnum {
         InstructionsEnum::from_vec(vec![
             self.global_get(self.mem_pointer),
@@ -502,15 +1084,31 @@ impl Generator {
         ])
     }
 
+    fn trace_index(&self, index: u32, offset: &mut u32) -> InstructionsEnum {
+        InstructionsEnum::from_vec(vec![
+            self.global_get(self.mem_pointer),
+            self.get_const(Value::I32(index as i32)),
+            self.store_to_trace(StoreKind::I32 { atomic: false }, offset),
+        ])
+    }
+
     fn store_val_to_trace(&self, val_type: ValType, offset: &mut u32) -> InstructionsEnum {
         let kind = match val_type {
             ValType::I32 => StoreKind::I32 { atomic: false },
             ValType::I64 => StoreKind::I64 { atomic: false },
             ValType::F32 => StoreKind::F32,
             ValType::F64 => StoreKind::F64,
-            ValType::V128 => todo!(),
-            ValType::Externref => StoreKind::I32 { atomic: false },
-            ValType::Funcref => StoreKind::I32 { atomic: false },
+            ValType::V128 => unreachable!("rejected by check_recordable"),
+            // References are opaque, only record whether they are null
+            ValType::Externref | ValType::Funcref => {
+                return InstructionsEnum::from_vec(vec![
+                    InstructionsEnum::Single((
+                        Instr::RefIsNull(RefIsNull {}),
+                        InstrLocId::default(),
+                    )),
+                    self.store_to_trace(StoreKind::I32 { atomic: false }, offset),
+                ]);
+            }
         };
         self.store_to_trace(kind, offset)
     }
@@ -529,7 +1127,7 @@ impl Generator {
             StoreKind::I64 { .. } => 8,
             StoreKind::F32 => 4,
             StoreKind::F64 => 8,
-            StoreKind::V128 => todo!(),
+            StoreKind::V128 => 16,
             StoreKind::I32_8 { .. } => 1,
             StoreKind::I32_16 { .. } => 2,
             StoreKind::I64_8 { .. } => 1,
@@ -552,7 +1150,7 @@ impl Generator {
             StoreKind::I64 { .. } => *offset += 8,
             StoreKind::F32 => *offset += 4,
             StoreKind::F64 => *offset += 8,
-            StoreKind::V128 => todo!(),
+            StoreKind::V128 => *offset += 16,
             StoreKind::I32_8 { .. } => *offset += 1,
             StoreKind::I32_16 { .. } => *offset += 2,
             StoreKind::I64_8 { .. } => *offset += 1,
@@ -563,8 +1161,9 @@ impl Generator {
         // self.double_drop()
     }
 
-    fn instr(&self, instr: Instr) -> InstructionsEnum {
-        InstructionsEnum::Single((instr, InstrLocId::default()))
+    /// An instruction of the original module, keeping its location.
+    fn instr(&self, instr: Instr, loc: InstrLocId) -> InstructionsEnum {
+        InstructionsEnum::Single((instr, loc))
     }
 
     fn get_const(&self, value: Value) -> InstructionsEnum {
@@ -588,15 +1187,19 @@ impl Generator {
     fn call_check_mem(&self) -> InstructionsEnum {
         InstructionsEnum::Single((
             Instr::Call(Call {
-                func: self.check_mem_id,
+                func: self.check_mem_id.unwrap(),
             }),
             InstrLocId::default(),
         ))
     }
 
-    // fn local_tee(&self, local: LocalId) -> InstructionsEnum {
-    //     InstructionsEnum::Single((Instr::LocalTee(LocalTee { local }), InstrLocId::default()))
-    // }
+    fn call(&self, func: FunctionId) -> InstructionsEnum {
+        InstructionsEnum::Single((Instr::Call(Call { func }), InstrLocId::default()))
+    }
+
+    fn local_tee(&self, local: LocalId) -> InstructionsEnum {
+        InstructionsEnum::Single((Instr::LocalTee(LocalTee { local }), InstrLocId::default()))
+    }
 
     fn local_get(&self, local: LocalId) -> InstructionsEnum {
         InstructionsEnum::Single((Instr::LocalGet(LocalGet { local }), InstrLocId::default()))
@@ -606,7 +1209,14 @@ impl Generator {
         InstructionsEnum::Single((Instr::LocalSet(LocalSet { local }), InstrLocId::default()))
     }
 
-    fn increment_mem_pointer(&self, amount: u32) -> InstructionsEnum {
+    fn increment_mem_pointer(&mut self, amount: u32) -> InstructionsEnum {
+        self.max_record_len = self.max_record_len.max(amount);
+        if let Some(advance) = self.advance_id {
+            return InstructionsEnum::from_vec(vec![
+                self.get_const(Value::I32(amount as i32)),
+                self.call(advance),
+            ]);
+        }
         InstructionsEnum::from_vec(vec![
             self.global_get(self.mem_pointer),
             self.get_const(Value::I32(amount as i32)),
@@ -615,6 +1225,97 @@ impl Generator {
         ])
     }
 
+    fn increment_mem_pointer_sampled(&mut self, amount: u32) -> InstructionsEnum {
+        match self.advance_sampled_id {
+            Some(advance) => {
+                self.max_record_len = self.max_record_len.max(amount);
+                InstructionsEnum::from_vec(vec![
+                    self.get_const(Value::I32(amount as i32)),
+                    self.call(advance),
+                ])
+            }
+            None => self.increment_mem_pointer(amount),
+        }
+    }
+
+    /// Overwrites the address operand just saved by `save_stack` with the
+    /// effective address, the operand plus the static offset of `arg`. The
+    /// sum only wraps for accesses that trap, whose records are never
+    /// committed.
+    fn save_effective_address(&self, arg: MemArg) -> InstructionsEnum {
+        if arg.offset == 0 {
+            return InstructionsEnum::Sequence(vec![]);
+        }
+        InstructionsEnum::from_vec(vec![
+            self.global_get(self.mem_pointer),
+            self.local_get(self.saved_locals[0]),
+            self.get_const(Value::I32(arg.offset as i32)),
+            self.binop(BinaryOp::I32Add),
+            self.store_val_to_trace(ValType::I32, &mut self.addr_offset()),
+        ])
+    }
+
+    /// Offset of the address in a memory record, behind the site prefix.
+    fn addr_offset(&self) -> u32 {
+        match self.site_ids {
+            Some(_) => sites::SITE_LEN + 1,
+            None => 1,
+        }
+    }
+
+    /// Commits a memory record, in watch mode only if the access hits a
+    /// watched range.
+    fn increment_mem_pointer_watched(&mut self, amount: u32, opcode: u8) -> InstructionsEnum {
+        match self.watch_id {
+            Some(watch) => {
+                self.max_record_len = self.max_record_len.max(amount);
+                InstructionsEnum::from_vec(vec![
+                    self.get_const(Value::I32(amount as i32)),
+                    self.get_const(Value::I32(decode::access_len(opcode) as i32)),
+                    self.call(watch),
+                ])
+            }
+            None => self.increment_mem_pointer_sampled(amount),
+        }
+    }
+
+    /// Increments the trigger depth. Entering from depth 0 starts a new run,
+    /// which `r3_disable` did not stop.
+    fn enter_trigger(&self) -> InstructionsEnum {
+        match self.trigger {
+            Some((func, trigger)) if Some(func) == self.current_func => {
+                InstructionsEnum::from_vec(vec![
+                    self.global_get(trigger.stopped),
+                    self.global_get(trigger.depth),
+                    self.get_const(Value::I32(0)),
+                    self.binop(ir::BinaryOp::I32Ne),
+                    self.binop(ir::BinaryOp::I32And),
+                    self.global_set(trigger.stopped),
+                    self.add_to_trigger_depth(1),
+                ])
+            }
+            _ => InstructionsEnum::Sequence(vec![]),
+        }
+    }
+
+    fn exit_trigger(&self) -> InstructionsEnum {
+        self.add_to_trigger_depth(-1)
+    }
+
+    fn add_to_trigger_depth(&self, amount: i32) -> InstructionsEnum {
+        match self.trigger {
+            Some((func, trigger)) if Some(func) == self.current_func => {
+                InstructionsEnum::from_vec(vec![
+                    self.global_get(trigger.depth),
+                    self.get_const(Value::I32(amount)),
+                    self.binop(ir::BinaryOp::I32Add),
+                    self.global_set(trigger.depth),
+                ])
+            }
+            _ => InstructionsEnum::Sequence(vec![]),
+        }
+    }
+
     fn binop(&self, op: BinaryOp) -> InstructionsEnum {
         InstructionsEnum::Single((Instr::Binop(Binop { op }), InstrLocId::default()))
     }
@@ -640,7 +1341,173 @@ impl Generator {
         self.current_func_type = typ;
     }
 
+    fn set_current_func_args(&mut self, args: Vec<LocalId>) {
+        self.current_func_args = args;
+    }
+
     fn set_func_entry(&mut self, entry: bool) {
         self.func_entry = entry;
     }
+
+    fn set_advance(&mut self, advance: FunctionId) {
+        self.advance_id = Some(advance);
+    }
+
+    fn set_advance_sampled(&mut self, advance: FunctionId) {
+        self.advance_sampled_id = Some(advance);
+    }
+
+    fn set_watch(&mut self, watch: FunctionId) {
+        self.watch_id = Some(watch);
+    }
+
+    fn set_sites(&mut self, sites: &Sites) {
+        self.site_ids = Some((sites.ids_by_offset(), sites.function_ends()));
+    }
+
+    fn set_flush(&mut self, flush: FunctionId) {
+        self.flush_id = Some(flush);
+    }
+
+    fn set_checkpoint(&mut self, checkpoint: FunctionId) {
+        self.checkpoint_id = Some(checkpoint);
+    }
+
+    fn set_trigger(&mut self, func: FunctionId, trigger: Trigger) {
+        self.trigger = Some((func, trigger));
+    }
+
+    fn set_syscalls(&mut self, syscalls: HashMap<FunctionId, FunctionId>) {
+        self.syscalls = syscalls;
+    }
+
+    fn set_bulk(&mut self, bulk: Bulk) {
+        self.bulk = Some(bulk);
+    }
+
+    /// Pushes the frame of the current function to the shadow stack.
+    fn push_frame(&self) -> InstructionsEnum {
+        let Some(shadow) = self.shadow_stack else {
+            return InstructionsEnum::Sequence(vec![]);
+        };
+        InstructionsEnum::from_vec(vec![
+            self.global_get(shadow.sp),
+            self.get_const(Value::I32(8)),
+            self.binop(BinaryOp::I32Add),
+            self.global_set(shadow.sp),
+            self.global_get(shadow.sp),
+            self.get_const(Value::I32(trap::ADDR_MASK)),
+            self.binop(BinaryOp::I32And),
+            // func idx and offset 0 for the entry
+            self.get_const(Value::I64(self.current_func_index as i64)),
+            self.store_to_shadow(StoreKind::I64 { atomic: false }, 0),
+        ])
+    }
+
+    fn pop_frame(&self) -> InstructionsEnum {
+        let Some(shadow) = self.shadow_stack else {
+            return InstructionsEnum::Sequence(vec![]);
+        };
+        InstructionsEnum::from_vec(vec![
+            self.global_get(shadow.sp),
+            self.get_const(Value::I32(8)),
+            self.binop(BinaryOp::I32Sub),
+            self.global_set(shadow.sp),
+        ])
+    }
+
+    /// Sets the offset of the running frame to the instruction at `loc`.
+    fn trap_site(&self, loc: Option<InstrLocId>) -> InstructionsEnum {
+        let Some(shadow) = self.shadow_stack else {
+            return InstructionsEnum::Sequence(vec![]);
+        };
+        InstructionsEnum::from_vec(vec![
+            self.global_get(shadow.sp),
+            self.get_const(Value::I32(trap::ADDR_MASK)),
+            self.binop(BinaryOp::I32And),
+            self.get_const(Value::I32(instr_offset(loc).unwrap_or(0) as i32)),
+            self.store_to_shadow(StoreKind::I32 { atomic: false }, 4),
+        ])
+    }
+
+    /// Saves the shadow stack pointer before a call that may enter the host.
+    /// If the host catches a trap of an export it called, the frames the
+    /// trap left are dropped by restoring it after the call.
+    fn save_sp(&self, host: bool) -> InstructionsEnum {
+        match self.shadow_stack {
+            Some(shadow) if host => InstructionsEnum::from_vec(vec![
+                self.global_get(shadow.sp),
+                self.local_set(shadow.saved_sp),
+            ]),
+            _ => InstructionsEnum::Sequence(vec![]),
+        }
+    }
+
+    fn restore_sp(&self, host: bool) -> InstructionsEnum {
+        match self.shadow_stack {
+            Some(shadow) if host => InstructionsEnum::from_vec(vec![
+                self.local_get(shadow.saved_sp),
+                self.global_set(shadow.sp),
+            ]),
+            _ => InstructionsEnum::Sequence(vec![]),
+        }
+    }
+
+    fn store_to_shadow(&self, kind: StoreKind, offset: u32) -> InstructionsEnum {
+        let align = match kind {
+            StoreKind::I64 { .. } => 8,
+            _ => 4,
+        };
+        InstructionsEnum::Single((
+            Instr::Store(Store {
+                memory: self.shadow_stack.unwrap().memory,
+                kind,
+                arg: MemArg { align, offset },
+            }),
+            InstrLocId::default(),
+        ))
+    }
+
+    fn set_shadow_stack(&mut self, shadow_stack: ShadowStack) {
+        self.shadow_stack = Some(shadow_stack);
+    }
+
+    fn set_current_func(&mut self, func: FunctionId, index: u32) {
+        self.current_func = Some(func);
+        self.current_func_index = index;
+    }
+
+    fn take_error(&mut self) -> Option<InstrumentError> {
+        self.error.take()
+    }
+
+    /// Remembers the first error, `start_instr_seq_mut` cannot return it.
+    fn fail(&mut self, error: InstrumentError) {
+        self.error.get_or_insert(error);
+    }
+
+    fn fail_unsupported(&mut self, feature: &'static str, loc: Option<InstrLocId>) {
+        self.fail(InstrumentError::Unsupported {
+            feature,
+            func_index: self.current_func_index,
+            instr_offset: instr_offset(loc),
+        });
+    }
+
+    fn fail_unknown(&mut self, kind: &'static str, loc: Option<InstrLocId>) {
+        self.fail(InstrumentError::UnknownReference {
+            kind,
+            func_index: self.current_func_index,
+            instr_offset: instr_offset(loc),
+        });
+    }
+
+    /// Fails if one of the values cannot be written to the trace.
+    fn check_recordable(&mut self, values: &[ValType], loc: Option<InstrLocId>) -> bool {
+        if values.contains(&ValType::V128) {
+            self.fail_unsupported("simd", loc);
+            return false;
+        }
+        true
+    }
 }
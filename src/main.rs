@@ -1,11 +1,610 @@
 extern crate r3_tracer;
-use std::fs;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use r3_tracer::instrument_wasm;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use r3_tracer::{
+    call_tree::CallTree,
+    dap::Session,
+    decode::{self, Decoder, Event, RingState},
+    instrument_wasm_with_options,
+    metadata::Metadata,
+    sites::Sites,
+    symbolize::Symbols,
+    text, wasabi, Options, Sampling,
+};
+use walrus::{ir, ActiveDataLocation, DataKind, InitExpr, Module};
 
-fn main() {
-    let test_name = "tests/load";
-    let buffer = &fs::read(format!("{}.wasm", test_name)).unwrap();
-    let mut module = instrument_wasm(buffer).unwrap();
-    let _ = module.emit_wasm_file(format!("{}-instrumented.wasm", test_name));
-}
\ No newline at end of file
+/// Records execution traces of WebAssembly modules.
+///
+/// Exit status: 0 on success, 1 on errors, 2 on invalid arguments and 3 if
+/// `replay` found loads that disagree with the replayed memory.
+#[derive(Parser)]
+#[command(name = "r3_tracer", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Instrument a module to record a trace.
+    Instrument(InstrumentArgs),
+    /// Print the events of a trace, one per line.
+    Decode {
+        #[command(flatten)]
+        trace: TraceArgs,
+        /// Print the lines a Wasabi analysis logging its hooks would print,
+        /// with indices instead of names, to diff against Wasabi.
+        #[arg(long)]
+        wasabi: bool,
+    },
+    /// Print statistics about the events of a trace.
+    Stats(TraceArgs),
+    /// Print the call tree of a trace and the calls that did not return.
+    Calls(TraceArgs),
+    /// Replay the stores and the syscall writes of a trace on the initial
+    /// memory, from the snapshot if there is one, and check that every load
+    /// reads the recorded value.
+    Replay {
+        #[command(flatten)]
+        trace: TraceArgs,
+        /// Write the memory at the end of the trace to this file.
+        #[arg(long, value_name = "FILE")]
+        memory_out: Option<PathBuf>,
+        /// Stop after this event, starting from the last checkpoint before
+        /// it.
+        #[arg(long, value_name = "EVENT")]
+        to: Option<usize>,
+    },
+    /// Serve the Debug Adapter Protocol on stdin and stdout to step forward
+    /// and backward through a trace, e.g. from VS Code.
+    Dap(TraceArgs),
+    /// Instrument a module, call one of its exports in an embedded wasmtime
+    /// and write the recorded trace. Modules importing WASI are rejected.
+    #[cfg(feature = "run")]
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+struct InstrumentArgs {
+    /// The module to instrument, in the binary or the text format.
+    input: PathBuf,
+    /// Where to write the instrumented module, defaults to
+    /// `<input>-instrumented.wasm`. Written in the text format if the name
+    /// ends with `.wat`.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Write the text format, with the inserted instructions marked by a
+    /// `;; r3` comment.
+    #[arg(long)]
+    wat: bool,
+    /// Also write a JSON sidecar naming the imports and exports the trace
+    /// refers to.
+    #[arg(long)]
+    meta: bool,
+    /// Where to write the sidecar with `--meta`, defaults to the output
+    /// with a `.meta.json` extension.
+    #[arg(long, value_name = "FILE")]
+    metadata: Option<PathBuf>,
+    /// Where to write the JSON map of the site ids with `--sites`, defaults
+    /// to the output with a `.sites.json` extension.
+    #[arg(long, value_name = "FILE")]
+    site_map: Option<PathBuf>,
+    #[command(flatten)]
+    options: OptionArgs,
+}
+
+/// The instrumentation [`Options`].
+#[derive(Args)]
+struct OptionArgs {
+    /// Keep only the most recent events in a ring buffer of this many bytes.
+    #[arg(long, value_name = "BYTES")]
+    ring_buffer: Option<u32>,
+    /// Export `r3_enable` and `r3_disable` to control recording at runtime.
+    #[arg(long)]
+    runtime_control: bool,
+    /// Only record while this function runs.
+    #[arg(long, value_name = "NAME")]
+    trigger: Option<String>,
+    /// Record every Nth memory access.
+    #[arg(long, value_name = "N", conflicts_with = "sample_window")]
+    sample_every: Option<u32>,
+    /// Record the first WINDOW memory accesses out of every PERIOD.
+    #[arg(long, value_name = "WINDOW/PERIOD", value_parser = parse_window)]
+    sample_window: Option<(u32, u32)>,
+    /// Only record the loads and stores that access this address range,
+    /// end excluded, e.g. `0x1000..0x1010`. Can be repeated.
+    #[arg(long, value_name = "START..END", value_parser = parse_range)]
+    watch: Vec<Range<u32>>,
+    /// Only record the loads and stores in the ranges the host sets through
+    /// `r3_watch`, even without `--watch`.
+    #[arg(long)]
+    runtime_watch: bool,
+    /// Check that the instrumented module validates.
+    #[arg(long)]
+    validate: bool,
+    /// Export `r3_trap_info` to record where a trap happened.
+    #[arg(long)]
+    trap_info: bool,
+    /// Record the globals and tables at instantiation.
+    #[arg(long)]
+    snapshot: bool,
+    /// Also record the initial memory in the snapshot.
+    #[arg(long)]
+    snapshot_memory: bool,
+    /// Write a checkpoint of the state before every Nth flush.
+    #[arg(long, value_name = "N")]
+    checkpoint_every: Option<u32>,
+    /// Write the site id of the instruction before its records, `decode`
+    /// prints the site of every event.
+    #[arg(long)]
+    sites: bool,
+}
+
+impl OptionArgs {
+    fn options(self) -> Options {
+        let sampling = match (self.sample_every, self.sample_window) {
+            (Some(n), _) => Some(Sampling::EveryNth(n)),
+            (_, Some((window, period))) => Some(Sampling::Window { window, period }),
+            _ => None,
+        };
+        Options {
+            ring_buffer: self.ring_buffer,
+            runtime_control: self.runtime_control,
+            trigger_function: self.trigger,
+            sampling,
+            watch: self.watch,
+            runtime_watch: self.runtime_watch,
+            validate: self.validate,
+            trap_info: self.trap_info,
+            snapshot: self.snapshot,
+            snapshot_memory: self.snapshot_memory,
+            checkpoint_every: self.checkpoint_every,
+            sites: self.sites,
+        }
+    }
+}
+
+#[cfg(feature = "run")]
+#[derive(Args)]
+struct RunArgs {
+    /// The module to run, in the binary or the text format.
+    module: PathBuf,
+    /// Where to write the trace.
+    #[arg(long, value_name = "FILE")]
+    trace: PathBuf,
+    /// The exported function to call.
+    #[arg(long, value_name = "NAME", default_value = "_start")]
+    invoke: String,
+    /// Arguments of the function, parsed according to its parameter types.
+    #[arg(allow_hyphen_values = true)]
+    args: Vec<String>,
+    #[command(flatten)]
+    options: OptionArgs,
+}
+
+#[derive(Args)]
+struct TraceArgs {
+    /// The original, not instrumented, module in the binary or the text
+    /// format.
+    module: PathBuf,
+    /// The bytes of the `trace` memory. Without `--ring` these are all the
+    /// chunks passed to `r3.check_mem`, concatenated.
+    trace: PathBuf,
+    /// The trace is the `trace` memory of a module instrumented with
+    /// `--ring-buffer`. Takes the values of the exported globals.
+    #[arg(
+        long,
+        value_name = "MEM_POINTER,RING_END,WRAP_COUNT,LIMIT,SIZE,WRITTEN",
+        value_parser = parse_ring_state
+    )]
+    ring: Option<RingState>,
+}
+
+fn parse_window(s: &str) -> Result<(u32, u32), String> {
+    let (window, period) = s
+        .split_once('/')
+        .ok_or_else(|| "expected WINDOW/PERIOD".to_string())?;
+    let window = window.parse().map_err(|e| format!("window: {}", e))?;
+    let period = period.parse().map_err(|e| format!("period: {}", e))?;
+    Ok((window, period))
+}
+
+fn parse_range(s: &str) -> Result<Range<u32>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| "expected START..END".to_string())?;
+    let parse = |n: &str| match n.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => n.parse(),
+    };
+    let start = parse(start).map_err(|e| format!("start: {}", e))?;
+    let end = parse(end).map_err(|e| format!("end: {}", e))?;
+    Ok(start..end)
+}
+
+fn parse_ring_state(s: &str) -> Result<RingState, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<u32>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [mem_pointer, ring_end, wrap_count, limit, size, written] => Ok(RingState {
+            mem_pointer,
+            ring_end,
+            wrap_count,
+            limit,
+            size,
+            written,
+        }),
+        _ => Err("expected six comma separated values".to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Instrument(args) => instrument(args).map(|_| ExitCode::SUCCESS),
+        Command::Decode { trace, wasabi } => {
+            print_events(&trace, wasabi).map(|_| ExitCode::SUCCESS)
+        }
+        Command::Stats(args) => print_stats(&args).map(|_| ExitCode::SUCCESS),
+        Command::Calls(args) => print_calls(&args).map(|_| ExitCode::SUCCESS),
+        Command::Replay {
+            trace,
+            memory_out,
+            to,
+        } => replay(&trace, memory_out.as_deref(), to),
+        Command::Dap(args) => debug(&args).map(|_| ExitCode::SUCCESS),
+        #[cfg(feature = "run")]
+        Command::Run(args) => run(args),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn instrument(args: InstrumentArgs) -> Result<()> {
+    let buffer =
+        fs::read(&args.input).with_context(|| format!("reading {}", args.input.display()))?;
+    let options = args.options.options();
+    let mut module = instrument_wasm_with_options(&buffer, &options)
+        .with_context(|| format!("instrumenting {}", args.input.display()))?;
+    let output = args
+        .output
+        .unwrap_or_else(|| default_output(&args.input, if args.wat { "wat" } else { "wasm" }));
+    let bytes = if args.wat || output.extension().is_some_and(|e| e == "wat") {
+        text::emit_wat(&mut module)?.into_bytes()
+    } else {
+        module.emit_wasm()
+    };
+    fs::write(&output, bytes).with_context(|| format!("writing {}", output.display()))?;
+    if args.meta {
+        let metadata = args
+            .metadata
+            .unwrap_or_else(|| output.with_extension("meta.json"));
+        fs::write(&metadata, Metadata::from_buffer(&buffer)?.to_json())
+            .with_context(|| format!("writing {}", metadata.display()))?;
+    }
+    if options.sites {
+        let site_map = args
+            .site_map
+            .unwrap_or_else(|| output.with_extension("sites.json"));
+        fs::write(&site_map, Sites::from_buffer(&buffer)?.to_json())
+            .with_context(|| format!("writing {}", site_map.display()))?;
+    }
+    Ok(())
+}
+
+/// Prints the results of the call. A trap is reported like an error, after
+/// the trace recorded up to it was written.
+#[cfg(feature = "run")]
+fn run(args: RunArgs) -> Result<ExitCode> {
+    let wasm =
+        fs::read(&args.module).with_context(|| format!("reading {}", args.module.display()))?;
+    let trace = fs::File::create(&args.trace)
+        .with_context(|| format!("creating {}", args.trace.display()))?;
+    let recording = r3_tracer::run::record(
+        &wasm,
+        &args.options.options(),
+        &args.invoke,
+        &args.args,
+        BufWriter::new(trace),
+    )
+    .with_context(|| format!("running {}", args.module.display()))?;
+    for value in recording.results? {
+        println!("{}", value);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn default_output(input: &Path, extension: &str) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    input.with_file_name(format!("{}-instrumented.{}", stem, extension))
+}
+
+fn read_events(args: &TraceArgs) -> Result<Vec<Event>> {
+    Ok(read_events_with_sites(args)?
+        .into_iter()
+        .map(|(event, _)| event)
+        .collect())
+}
+
+fn read_events_with_sites(args: &TraceArgs) -> Result<Vec<(Event, Option<u32>)>> {
+    let wasm =
+        fs::read(&args.module).with_context(|| format!("reading {}", args.module.display()))?;
+    let decoder = Decoder::from_buffer(&wasm)
+        .with_context(|| format!("parsing {}", args.module.display()))?;
+    let mut trace =
+        fs::read(&args.trace).with_context(|| format!("reading {}", args.trace.display()))?;
+    if let Some(state) = args.ring {
+        trace = decode::reassemble_ring(&trace, state)?;
+    }
+    decoder.decode_with_sites(&trace)
+}
+
+fn read_symbols(args: &TraceArgs) -> Result<Symbols> {
+    Symbols::from_buffer(&fs::read(&args.module)?)
+        .with_context(|| format!("parsing {}", args.module.display()))
+}
+
+fn print_events(args: &TraceArgs, wasabi: bool) -> Result<()> {
+    if wasabi {
+        let mut out = BufWriter::new(io::stdout().lock());
+        for line in wasabi::dump(&read_events(args)?) {
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    let events = read_events_with_sites(args)?;
+    let symbols = read_symbols(args)?;
+    let sites = if events.iter().any(|(_, site)| site.is_some()) {
+        Sites::from_buffer(&fs::read(&args.module)?)?
+    } else {
+        Sites::default()
+    };
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (event, site) in &events {
+        let event = symbols.event(event);
+        match site.and_then(|id| sites.get(id)) {
+            Some(site) => writeln!(out, "{} at {}", event, symbols.site(site))?,
+            None => writeln!(out, "{}", event)?,
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn print_calls(args: &TraceArgs) -> Result<()> {
+    let tree = CallTree::new(&read_events(args)?);
+    let symbols = read_symbols(args)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    write!(out, "{}", symbols.call_tree(&tree))?;
+    out.flush()?;
+    Ok(())
+}
+
+fn debug(args: &TraceArgs) -> Result<()> {
+    let wasm =
+        fs::read(&args.module).with_context(|| format!("reading {}", args.module.display()))?;
+    let session = Session::new(&wasm, read_events(args)?)
+        .with_context(|| format!("parsing {}", args.module.display()))?;
+    session.serve(io::stdin().lock(), io::stdout().lock())
+}
+
+fn print_stats(args: &TraceArgs) -> Result<()> {
+    let events = read_events(args)?;
+    let symbols = read_symbols(args)?;
+    let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut calls: BTreeMap<u32, usize> = BTreeMap::new();
+    let mut import_calls: BTreeMap<u32, usize> = BTreeMap::new();
+    let (mut bytes_loaded, mut bytes_stored, mut bytes_written) = (0u64, 0u64, 0u64);
+    let (mut depth, mut max_depth) = (0usize, 0usize);
+    for event in &events {
+        let kind = match event {
+            Event::FunctionEntry { func, .. } => {
+                *calls.entry(*func).or_default() += 1;
+                depth += 1;
+                max_depth = max_depth.max(depth);
+                "function entry"
+            }
+            Event::Return { .. } => {
+                depth = depth.saturating_sub(1);
+                "return"
+            }
+            Event::Call { .. } => "call",
+            Event::ImportCall { func, .. } => {
+                *import_calls.entry(*func).or_default() += 1;
+                "import call"
+            }
+            Event::CallIndirect { .. } => "call_indirect",
+            Event::CallEnd { .. } => "call end",
+            Event::ExportCall { .. } => "export call",
+            Event::ExportReturn { .. } => "export return",
+            Event::Load { opcode, .. } => {
+                bytes_loaded += decode::access_len(*opcode) as u64;
+                "load"
+            }
+            Event::Store { opcode, .. } => {
+                bytes_stored += decode::access_len(*opcode) as u64;
+                "store"
+            }
+            Event::GlobalGet { .. } => "global.get",
+            Event::GlobalSet { .. } => "global.set",
+            Event::TableGet { .. } => "table.get",
+            Event::TableSet { .. } => "table.set",
+            Event::Syscall { writes, .. } => {
+                bytes_written += writes.iter().map(|w| w.bytes.len() as u64).sum::<u64>();
+                "syscall"
+            }
+            Event::BulkMemory { opcode, .. } | Event::BulkTable { opcode, .. } => {
+                decode::bulk_name(*opcode)
+            }
+            Event::MemoryGrow { .. } => "memory.grow",
+            Event::Trap { .. } => "trap",
+            Event::InitialState { .. } => "initial state",
+            Event::Checkpoint { .. } => "checkpoint",
+        };
+        *kinds.entry(kind).or_default() += 1;
+    }
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "events: {}", events.len())?;
+    for (kind, count) in &kinds {
+        writeln!(out, "  {}: {}", kind, count)?;
+    }
+    writeln!(out, "bytes loaded: {}", bytes_loaded)?;
+    writeln!(out, "bytes stored: {}", bytes_stored)?;
+    writeln!(out, "bytes written by syscalls: {}", bytes_written)?;
+    writeln!(out, "max call depth: {}", max_depth)?;
+    let mut calls: Vec<(u32, usize)> = calls.into_iter().collect();
+    calls.sort_by_key(|(func, count)| (std::cmp::Reverse(*count), *func));
+    writeln!(out, "functions entered: {}", calls.len())?;
+    for (func, count) in calls {
+        writeln!(out, "  {}: {}", function_label(&symbols, func), count)?;
+    }
+    writeln!(out, "imports called: {}", import_calls.len())?;
+    for (func, count) in import_calls {
+        writeln!(out, "  {}: {}", function_label(&symbols, func), count)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// The name of a function followed by its index, or only the index.
+fn function_label(symbols: &Symbols, func: u32) -> String {
+    match symbols.function(func) {
+        Some(name) => format!("{} (function {})", name, func),
+        None => format!("function {}", func),
+    }
+}
+
+/// Replays from the initial state or the data segments, or up to `to` from
+/// the last snapshot with the memory before it.
+fn replay(args: &TraceArgs, memory_out: Option<&Path>, to: Option<usize>) -> Result<ExitCode> {
+    let wasm =
+        fs::read(&args.module).with_context(|| format!("reading {}", args.module.display()))?;
+    let module = text::parse(&wasm)
+        .and_then(|wasm| Module::from_buffer(&wasm))
+        .with_context(|| format!("parsing {}", args.module.display()))?;
+    let events = read_events(args)?;
+    let symbols = read_symbols(args)?;
+    let end = to.map_or(events.len(), |to| to.saturating_add(1).min(events.len()));
+    // Without an end the whole trace is checked, from the initial state
+    let start = decode::checkpoint_before(&events, to.map_or(0, |_| end.saturating_sub(1)));
+    let mut memory = match start.map(|i| &events[i]) {
+        Some(
+            Event::InitialState {
+                memory: Some(snapshot),
+                ..
+            }
+            | Event::Checkpoint {
+                memory: Some(snapshot),
+                ..
+            },
+        ) => snapshot.bytes(),
+        _ => initial_memory(&module)?,
+    };
+    let start = start.unwrap_or(0);
+    let (mut loads, mut stores, mut diverged) = (0usize, 0usize, 0usize);
+    for (i, event) in events.iter().enumerate().take(end).skip(start) {
+        match event {
+            Event::BulkMemory { bytes: None, .. } => {
+                eprintln!(
+                    "event {}: {}, the replayed memory is incomplete",
+                    i,
+                    symbols.event(event)
+                );
+            }
+            Event::Store { .. } | Event::Syscall { .. } | Event::BulkMemory { .. } => {
+                for write in event.memory_writes() {
+                    write.apply(&mut memory);
+                }
+                stores += matches!(event, Event::Store { .. }) as usize;
+            }
+            Event::MemoryGrow { delta, result } if *result != -1 => {
+                let len = (*result as usize + *delta as usize) * 64 * 1024;
+                if memory.len() < len {
+                    memory.resize(len, 0);
+                }
+            }
+            Event::Load {
+                opcode,
+                addr,
+                value,
+            } => {
+                let bytes = value.to_le_bytes(decode::access_len(*opcode));
+                let start = *addr as usize;
+                let actual = memory.get(start..start + bytes.len());
+                if actual != Some(&bytes[..]) {
+                    if diverged < 10 {
+                        eprintln!(
+                            "event {}: {} but the replayed memory holds {:?}",
+                            i,
+                            symbols.event(event),
+                            actual.unwrap_or_default()
+                        );
+                    }
+                    diverged += 1;
+                }
+                loads += 1;
+            }
+            _ => {}
+        }
+    }
+    println!(
+        "replayed {} events from event {}: {} stores, {} loads, {} loads diverged",
+        end - start,
+        start,
+        stores,
+        loads,
+        diverged
+    );
+    if let Some(path) = memory_out {
+        fs::write(path, &memory).with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(if diverged > 0 {
+        ExitCode::from(3)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The first memory of the module with its active data segments applied.
+fn initial_memory(module: &Module) -> Result<Vec<u8>> {
+    let Some(memory) = module.memories.iter().next() else {
+        return Ok(Vec::new());
+    };
+    let mut bytes = vec![0; memory.initial as usize * 64 * 1024];
+    for data in module.data.iter() {
+        let offset = match data.kind {
+            DataKind::Active(ref active) if active.memory == memory.id() => match active.location {
+                ActiveDataLocation::Absolute(offset) => offset as usize,
+                ActiveDataLocation::Relative(global) => match module.globals.get(global).kind {
+                    walrus::GlobalKind::Local(InitExpr::Value(ir::Value::I32(offset))) => {
+                        offset as u32 as usize
+                    }
+                    _ => bail!("data segment with an imported offset cannot be replayed"),
+                },
+            },
+            _ => continue,
+        };
+        if bytes.len() < offset + data.value.len() {
+            bytes.resize(offset + data.value.len(), 0);
+        }
+        bytes[offset..offset + data.value.len()].copy_from_slice(&data.value);
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,90 @@
+//! Sidecar metadata written next to the instrumented module. The trace only
+//! holds indices into the original module, the sidecar names them.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use walrus::{ExportItem, FunctionKind, Module};
+
+/// Imported and exported functions of the original module.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Function imports, recorded by import call events.
+    pub imports: Vec<Import>,
+    /// Function exports, the entry points of the host.
+    pub exports: Vec<Export>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Import {
+    /// Function index in the original module.
+    pub func: u32,
+    pub module: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Export {
+    /// Function index in the original module.
+    pub func: u32,
+    pub name: String,
+}
+
+impl Metadata {
+    /// Collects the metadata of the original, not instrumented, module.
+    pub fn new(module: &Module) -> Self {
+        let imports = module
+            .funcs
+            .iter()
+            .enumerate()
+            .filter_map(|(func, f)| match f.kind {
+                FunctionKind::Import(ref import) => {
+                    let import = module.imports.get(import.import);
+                    Some(Import {
+                        func: func as u32,
+                        module: import.module.clone(),
+                        name: import.name.clone(),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        let func_indices: HashMap<_, _> = module
+            .funcs
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.id(), i as u32))
+            .collect();
+        let exports = module
+            .exports
+            .iter()
+            .filter_map(|e| match e.item {
+                ExportItem::Function(f) => Some(Export {
+                    func: func_indices[&f],
+                    name: e.name.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        Self { imports, exports }
+    }
+
+    /// Parses the original module, in the binary or the text format.
+    pub fn from_buffer(wasm: &[u8]) -> Result<Self> {
+        Ok(Self::new(&Module::from_buffer(&crate::text::parse(wasm)?)?))
+    }
+
+    /// The import of a function index.
+    pub fn import(&self, func: u32) -> Option<&Import> {
+        self.imports.iter().find(|i| i.func == func)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("metadata serializes")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
@@ -0,0 +1,118 @@
+use std::ops::Range;
+
+/// Configuration of the instrumentation done by [`instrument_wasm_with_options`].
+///
+/// The default options record every event and flush the trace through the
+/// imported `r3.check_mem` function on each return.
+///
+/// [`instrument_wasm_with_options`]: crate::instrument_wasm_with_options
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Size in bytes of the ring buffer used in "flight recorder" mode.
+    ///
+    /// When set, the trace is never flushed. Instead `trace_byte_length`
+    /// wraps around within the first `ring_buffer` bytes of the `trace`
+    /// memory, so only the most recent events are kept. Every record is
+    /// followed by its length as a 16 bit footer, the number of wraps is
+    /// exported as `trace_wrap_count`, the end of the previous lap as
+    /// `trace_ring_end`, the wrap limit as `trace_ring_limit`, the size as
+    /// `trace_ring_size` and the end of the longer records of the current
+    /// lap as `trace_ring_written`. Use [`decode::reassemble_ring`] to get
+    /// the events back in order.
+    ///
+    /// [`decode::reassemble_ring`]: crate::decode::reassemble_ring
+    pub ring_buffer: Option<u32>,
+    /// Export `r3_enable` and `r3_disable` functions and an `r3_enabled`
+    /// global so the host decides when to record. Recording starts disabled
+    /// and records written while disabled are dropped.
+    pub runtime_control: bool,
+    /// Name of a function (export name or name from the `name` section)
+    /// that enables recording while it runs: from its entry up to its
+    /// return. Implies [`runtime_control`](Self::runtime_control).
+    /// `r3_disable` stops recording until the function is entered again. A
+    /// trap skips its return, with [`trap_info`](Self::trap_info) the host's
+    /// call to `r3_trap_info` ends the run.
+    pub trigger_function: Option<String>,
+    /// Only record a sample of the `Load` and `Store` events. Calls,
+    /// returns and all other events are always recorded.
+    pub sampling: Option<Sampling>,
+    /// Only record the loads and stores whose effective address range
+    /// overlaps one of these ranges, end excluded. Calls, returns and all
+    /// other events are always recorded. The host can change the ranges at
+    /// runtime with the exported `r3_watch(start, end)` and
+    /// `r3_unwatch_all()`. Applied before [`sampling`](Self::sampling).
+    /// At most 8192 ranges are watched, more fail with
+    /// [`InstrumentError::TooManyWatchRanges`](crate::InstrumentError::TooManyWatchRanges).
+    pub watch: Vec<Range<u32>>,
+    /// Watch memory accesses even without [`watch`](Self::watch) ranges, so
+    /// the host chooses the ranges through `r3_watch`. No load or store is
+    /// recorded until it does.
+    pub runtime_watch: bool,
+    /// Run a validator on the instrumented module and fail with
+    /// [`InstrumentError::Invalid`](crate::InstrumentError::Invalid) naming
+    /// the function and instruction that broke validation.
+    pub validate: bool,
+    /// Keep a shadow stack of the running functions and export
+    /// `r3_trap_info`. After catching a trap, the host calls it to record a
+    /// trap event with the functions that were running and the offset of
+    /// the last instruction that could trap in each, and to flush the
+    /// trace.
+    pub trap_info: bool,
+    /// Record the values of the globals and the null flags of the tables at
+    /// instantiation, before the start function runs. Ignored with a
+    /// [`ring_buffer`](Self::ring_buffer), which does not keep the start of
+    /// the trace.
+    pub snapshot: bool,
+    /// Also record the non-zero parts of the first memory in the snapshot,
+    /// after the data segments were applied. Implies
+    /// [`snapshot`](Self::snapshot). The memory is left out if the trace
+    /// memory cannot grow enough to hold it.
+    pub snapshot_memory: bool,
+    /// Write a checkpoint with the globals, tables and memory before every
+    /// `n`th flush through `r3.check_mem`, so a replay can start from the
+    /// middle of the trace, see [`decode::checkpoint_before`]. Ignored with
+    /// a [`ring_buffer`](Self::ring_buffer), which is never flushed.
+    ///
+    /// [`decode::checkpoint_before`]: crate::decode::checkpoint_before
+    pub checkpoint_every: Option<u32>,
+    /// Write the site id of the instruction before the records of loads,
+    /// stores, calls, returns, globals and tables, so every event can be
+    /// traced back to the code, see [`sites`](crate::sites). The function
+    /// entry and the records of the host side have none.
+    pub sites: bool,
+}
+
+/// Which memory accesses are recorded in sampling mode. Time is counted in
+/// memory accesses, so sampling is cheap and deterministic. The count is an
+/// i32 that starts over at 0 after 2^32 accesses, which restarts the
+/// pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Record every `n`th memory access.
+    EveryNth(u32),
+    /// Record the first `window` memory accesses out of every `period`.
+    Window { window: u32, period: u32 },
+}
+
+impl Sampling {
+    pub(crate) fn window_and_period(self) -> (u32, u32) {
+        match self {
+            Sampling::EveryNth(n) => (1, n),
+            Sampling::Window { window, period } => (window, period),
+        }
+    }
+}
+
+impl Options {
+    pub(crate) fn control(&self) -> bool {
+        self.runtime_control || self.trigger_function.is_some()
+    }
+
+    pub(crate) fn snapshot(&self) -> bool {
+        self.snapshot || self.snapshot_memory
+    }
+
+    pub(crate) fn watching(&self) -> bool {
+        self.runtime_watch || !self.watch.is_empty()
+    }
+}
@@ -0,0 +1,182 @@
+//! Records a trace by running the instrumented module in an embedded
+//! wasmtime, without any JavaScript glue.
+
+use std::io::Write;
+
+use anyhow::{anyhow, bail, Context, Result};
+use wasmtime::{Caller, Engine, Extern, Instance, Linker, Module, Store, Val, ValType};
+
+use crate::{
+    decode::{self, RingState, Value},
+    instrument_wasm_with_options, Options,
+};
+
+/// Import modules of the WASI versions.
+const WASI_MODULES: [&str; 2] = ["wasi_snapshot_preview1", "wasi_unstable"];
+
+/// The outcome of [`record`].
+pub struct Recording<W> {
+    /// The writer passed to [`record`], holding the trace.
+    pub trace: W,
+    /// The results of the call, or the trap that ended it.
+    pub results: Result<Vec<Value>>,
+}
+
+/// Instruments `wasm`, calls its export `export` with `args` and writes the
+/// recorded trace to `trace`.
+///
+/// The arguments are parsed according to the parameter types of the export.
+/// Imports other than `r3.check_mem` trap when they are called, modules
+/// importing WASI are rejected: the runner does not provide it. The records
+/// written before a trap are kept, so the trace ends at the last recorded
+/// event before it, or with a trap event if [`Options::trap_info`] is set.
+pub fn record<W: Write + 'static>(
+    wasm: &[u8],
+    options: &Options,
+    export: &str,
+    args: &[String],
+    trace: W,
+) -> Result<Recording<W>> {
+    let instrumented = instrument_wasm_with_options(wasm, options)?.emit_wasm();
+    let engine = Engine::default();
+    let module = Module::new(&engine, &instrumented)?;
+    if let Some(import) = module
+        .imports()
+        .find(|i| WASI_MODULES.contains(&i.module()))
+    {
+        bail!(
+            "the module imports {}.{}, WASI is not available in the runner",
+            import.module(),
+            import.name()
+        );
+    }
+    let mut store = Store::new(&engine, trace);
+    let mut linker = Linker::new(&engine);
+    linker.func_wrap(
+        "r3",
+        "check_mem",
+        |mut caller: Caller<'_, W>| -> Result<()> {
+            let Some(Extern::Memory(memory)) = caller.get_export("trace") else {
+                bail!("the module has no trace memory");
+            };
+            let len = trace_byte_length(&mut caller)?;
+            let (data, out) = memory.data_and_store_mut(&mut caller);
+            out.write_all(&data[..len])?;
+            Ok(())
+        },
+    )?;
+    linker.define_unknown_imports_as_traps(&module)?;
+    let instance = linker.instantiate(&mut store, &module)?;
+    let func = instance
+        .get_func(&mut store, export)
+        .ok_or_else(|| anyhow!("the module has no exported function {:?}", export))?;
+    let ty = func.ty(&store);
+    if ty.params().len() != args.len() {
+        bail!(
+            "{} takes {} arguments, got {}",
+            export,
+            ty.params().len(),
+            args.len()
+        );
+    }
+    let params = ty
+        .params()
+        .zip(args)
+        .map(|(ty, arg)| parse_arg(&ty, arg).with_context(|| format!("argument {:?}", arg)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut results = vec![Val::I32(0); ty.results().len()];
+    let call = func.call(&mut store, &params, &mut results);
+    if call.is_err() && options.trap_info {
+        instance
+            .get_typed_func::<(), i32>(&mut store, "r3_trap_info")?
+            .call(&mut store, ())?;
+    }
+    drain(&mut store, &instance, options)?;
+    let results = call
+        .with_context(|| format!("calling {}", export))
+        .and_then(|_| results.iter().map(value).collect());
+    Ok(Recording {
+        trace: store.into_data(),
+        results,
+    })
+}
+
+/// Writes the records that were not passed to `r3.check_mem` yet, or the
+/// ring buffer in order.
+fn drain<W: Write>(store: &mut Store<W>, instance: &Instance, options: &Options) -> Result<()> {
+    let memory = instance
+        .get_memory(&mut *store, "trace")
+        .ok_or_else(|| anyhow!("the module has no trace memory"))?;
+    let global = |store: &mut Store<W>, name: &str| -> Result<u32> {
+        match instance.get_global(&mut *store, name).map(|g| g.get(store)) {
+            Some(Val::I32(value)) => Ok(value as u32),
+            _ => bail!("the module has no i32 global {}", name),
+        }
+    };
+    let mem_pointer = global(store, "trace_byte_length")?;
+    match options.ring_buffer {
+        Some(_) => {
+            let state = RingState {
+                mem_pointer,
+                ring_end: global(store, "trace_ring_end")?,
+                wrap_count: global(store, "trace_wrap_count")?,
+                limit: global(store, "trace_ring_limit")?,
+                size: global(store, "trace_ring_size")?,
+                written: global(store, "trace_ring_written")?,
+            };
+            let records = decode::reassemble_ring(memory.data(&*store), state)?;
+            store.data_mut().write_all(&records)?;
+        }
+        None => {
+            let (data, out) = memory.data_and_store_mut(&mut *store);
+            out.write_all(&data[..mem_pointer as usize])?;
+        }
+    }
+    store.data_mut().flush()?;
+    Ok(())
+}
+
+fn trace_byte_length<W>(caller: &mut Caller<'_, W>) -> Result<usize> {
+    match caller
+        .get_export("trace_byte_length")
+        .and_then(Extern::into_global)
+        .map(|g| g.get(&mut *caller))
+    {
+        Some(Val::I32(len)) => Ok(len as u32 as usize),
+        _ => bail!("the module has no trace_byte_length global"),
+    }
+}
+
+/// Parses a command line argument as a value of type `ty`. Integers may be
+/// given signed or unsigned.
+fn parse_arg(ty: &ValType, arg: &str) -> Result<Val> {
+    Ok(match ty {
+        ValType::I32 => Val::I32(
+            arg.parse::<i32>()
+                .or_else(|_| arg.parse::<u32>().map(|v| v as i32))?,
+        ),
+        ValType::I64 => Val::I64(
+            arg.parse::<i64>()
+                .or_else(|_| arg.parse::<u64>().map(|v| v as i64))?,
+        ),
+        ValType::F32 => Val::F32(arg.parse::<f32>()?.to_bits()),
+        ValType::F64 => Val::F64(arg.parse::<f64>()?.to_bits()),
+        ty => bail!("parameters of type {} cannot be passed", ty),
+    })
+}
+
+fn value(val: &Val) -> Result<Value> {
+    Ok(match val {
+        Val::I32(v) => Value::I32(*v),
+        Val::I64(v) => Value::I64(*v),
+        Val::F32(bits) => Value::F32(f32::from_bits(*bits)),
+        Val::F64(bits) => Value::F64(f64::from_bits(*bits)),
+        Val::FuncRef(f) => Value::Ref {
+            is_null: f.is_none(),
+        },
+        Val::ExternRef(r) => Value::Ref {
+            is_null: r.is_none(),
+        },
+        val => bail!("results of type {:?} are not supported", val),
+    })
+}
@@ -0,0 +1,498 @@
+use walrus::{
+    ir::{BinaryOp, MemArg, StoreKind, UnaryOp, Value},
+    FunctionBuilder, FunctionId, GlobalId, GlobalKind, InitExpr, InstrSeqBuilder, LocalId,
+    MemoryId, Module, ValType,
+};
+
+use crate::{InstrumentError, Options, Sampling};
+
+/// Tracer state and helper functions added to the instrumented module for
+/// the recording modes that cannot be expressed by inline code.
+#[derive(Debug)]
+pub(crate) struct Runtime {
+    /// Called with the record length after every record instead of
+    /// incrementing `mem_pointer` inline.
+    pub(crate) advance: Option<FunctionId>,
+    /// Like `advance`, but only commits a sample of the memory records.
+    pub(crate) advance_sampled: Option<FunctionId>,
+    /// Called on return instead of `r3.check_mem`.
+    pub(crate) flush: Option<FunctionId>,
+    /// Globals of the function that triggers recording.
+    pub(crate) trigger: Option<Trigger>,
+    /// Called before every flush, writes a checkpoint now and then. Set
+    /// once the snapshot helpers were added.
+    pub(crate) checkpoint: Option<FunctionId>,
+    ring: Option<Ring>,
+}
+
+#[derive(Debug)]
+struct Ring {
+    wrap_count: GlobalId,
+    ring_end: GlobalId,
+    limit: GlobalId,
+    /// End of the records of the current lap that may be longer than the
+    /// ones the limit was computed for, committed or dropped.
+    written: GlobalId,
+    size: u32,
+}
+
+#[derive(Debug)]
+struct Control {
+    enabled: GlobalId,
+    trigger: Trigger,
+}
+
+/// State of [`Options::trigger_function`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Trigger {
+    /// Nesting depth of the function, recording while it is not 0.
+    pub(crate) depth: GlobalId,
+    /// Set by `r3_disable`, stops the recording of the running function
+    /// until it is entered again from depth 0.
+    pub(crate) stopped: GlobalId,
+}
+
+impl Runtime {
+    pub(crate) fn add(
+        module: &mut Module,
+        options: &Options,
+        trace_mem_id: MemoryId,
+        mem_pointer: GlobalId,
+        check_mem_id: Option<FunctionId>,
+    ) -> Self {
+        let ring = options.ring_buffer.map(|size| Ring::add(module, size));
+        let control = options.control().then(|| Control::add(module));
+        let advance = (ring.is_some() || control.is_some()).then(|| {
+            add_advance(
+                module,
+                trace_mem_id,
+                mem_pointer,
+                ring.as_ref(),
+                control.as_ref(),
+            )
+        });
+        let advance_sampled = options
+            .sampling
+            .map(|sampling| add_advance_sampled(module, mem_pointer, sampling, advance));
+        let flush = match (&control, check_mem_id) {
+            (Some(_), Some(check_mem_id)) => Some(add_flush(module, mem_pointer, check_mem_id)),
+            _ => None,
+        };
+        Self {
+            advance,
+            advance_sampled,
+            flush,
+            trigger: control.map(|c| c.trigger),
+            checkpoint: None,
+            ring,
+        }
+    }
+
+    /// Sets the wrap limit of the ring buffer so that the longest record and
+    /// its footer always fit behind the limit.
+    pub(crate) fn finish(
+        &self,
+        module: &mut Module,
+        max_record_len: u32,
+    ) -> Result<(), InstrumentError> {
+        if let Some(ring) = &self.ring {
+            let limit = ring.size.checked_sub(max_record_len + 2).ok_or(
+                InstrumentError::RingBufferTooSmall {
+                    size: ring.size,
+                    max_record_len,
+                },
+            )?;
+            module.globals.get_mut(ring.limit).kind =
+                GlobalKind::Local(InitExpr::Value(Value::I32(limit as i32)));
+        }
+        Ok(())
+    }
+
+    /// Size of the ring buffer, records longer than that cannot be kept.
+    pub(crate) fn ring_size(&self) -> Option<u32> {
+        self.ring.as_ref().map(|ring| ring.size)
+    }
+
+    /// Makes room for a record of `len` bytes that may be longer than the
+    /// records the ring limit was computed for, by wrapping early when the
+    /// record and its footer would not fit behind `mem_pointer`. The end of
+    /// the record is kept in `$written`: it is written even when it is
+    /// dropped, and may overwrite more of the previous lap than the records
+    /// the limit allows for.
+    ///
+    /// ```wasm
+    /// global.get $mem_pointer
+    /// local.get $len
+    /// i32.add
+    /// i32.const ;; ring size - 2
+    /// i32.gt_u
+    /// if
+    ///     global.get $mem_pointer
+    ///     global.set $ring_end
+    ///     global.get $wrap_count
+    ///     i32.const 1
+    ///     i32.add
+    ///     global.set $wrap_count
+    ///     i32.const 0
+    ///     global.set $mem_pointer
+    ///     i32.const 0
+    ///     global.set $written
+    /// end
+    /// global.get $mem_pointer
+    /// local.get $len
+    /// i32.add
+    /// global.get $written
+    /// ;; the same two values again
+    /// i32.gt_u
+    /// select
+    /// global.set $written
+    /// ```
+    pub(crate) fn reserve(&self, body: &mut InstrSeqBuilder, mem_pointer: GlobalId, len: LocalId) {
+        let Some(ring) = &self.ring else {
+            return;
+        };
+        body.global_get(mem_pointer)
+            .local_get(len)
+            .binop(BinaryOp::I32Add)
+            .i32_const(ring.size.saturating_sub(2) as i32)
+            .binop(BinaryOp::I32GtU)
+            .if_else(
+                None,
+                |then| {
+                    then.global_get(mem_pointer)
+                        .global_set(ring.ring_end)
+                        .global_get(ring.wrap_count)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .global_set(ring.wrap_count)
+                        .i32_const(0)
+                        .global_set(mem_pointer)
+                        .i32_const(0)
+                        .global_set(ring.written);
+                },
+                |_| {},
+            );
+        for _ in 0..2 {
+            body.global_get(mem_pointer)
+                .local_get(len)
+                .binop(BinaryOp::I32Add)
+                .global_get(ring.written);
+        }
+        body.binop(BinaryOp::I32GtU)
+            .select(None)
+            .global_set(ring.written);
+    }
+
+    /// Forgets the running trigger function, after a trap skipped its
+    /// return.
+    pub(crate) fn reset_trigger(&self, body: &mut InstrSeqBuilder) {
+        if let Some(trigger) = self.trigger {
+            body.i32_const(0)
+                .global_set(trigger.depth)
+                .i32_const(0)
+                .global_set(trigger.stopped);
+        }
+    }
+
+    /// Commits a record written at `mem_pointer`, its length is on the
+    /// stack. Like the inline increment of the records written by the
+    /// generator.
+    pub(crate) fn commit(&self, body: &mut InstrSeqBuilder, mem_pointer: GlobalId) {
+        match self.advance {
+            Some(advance) => {
+                body.call(advance);
+            }
+            None => {
+                body.global_get(mem_pointer)
+                    .binop(BinaryOp::I32Add)
+                    .global_set(mem_pointer);
+            }
+        }
+    }
+}
+
+impl Ring {
+    fn add(module: &mut Module, size: u32) -> Self {
+        let wrap_count = add_i32_global(module, true);
+        module.exports.add("trace_wrap_count", wrap_count);
+        let ring_end = add_i32_global(module, true);
+        module.exports.add("trace_ring_end", ring_end);
+        // Patched in `Runtime::finish` once the longest record is known
+        let limit = add_i32_global(module, false);
+        module.exports.add("trace_ring_limit", limit);
+        // The `trace` memory is rounded up to whole pages, a host dumping
+        // all of it needs the size to find the end of the ring
+        let ring_size = module.globals.add_local(
+            ValType::I32,
+            false,
+            InitExpr::Value(Value::I32(size as i32)),
+        );
+        module.exports.add("trace_ring_size", ring_size);
+        let written = add_i32_global(module, true);
+        module.exports.add("trace_ring_written", written);
+        Self {
+            wrap_count,
+            ring_end,
+            limit,
+            written,
+            size,
+        }
+    }
+}
+
+impl Control {
+    /// Adds the exported `r3_enabled` global and the `r3_enable` and
+    /// `r3_disable` functions. Recording starts disabled. `r3_disable` also
+    /// stops the trigger function, but leaves its depth alone so its returns
+    /// stay balanced.
+    fn add(module: &mut Module) -> Self {
+        let enabled = add_i32_global(module, true);
+        module.exports.add("r3_enabled", enabled);
+        let trigger = Trigger {
+            depth: add_i32_global(module, true),
+            stopped: add_i32_global(module, true),
+        };
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.func_body().i32_const(1).global_set(enabled);
+        let enable = builder.finish(vec![], &mut module.funcs);
+        module.exports.add("r3_enable", enable);
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder
+            .func_body()
+            .i32_const(0)
+            .global_set(enabled)
+            .i32_const(1)
+            .global_set(trigger.stopped);
+        let disable = builder.finish(vec![], &mut module.funcs);
+        module.exports.add("r3_disable", disable);
+
+        Self { enabled, trigger }
+    }
+}
+
+fn add_i32_global(module: &mut Module, mutable: bool) -> GlobalId {
+    module
+        .globals
+        .add_local(ValType::I32, mutable, InitExpr::Value(Value::I32(0)))
+}
+
+/// Adds the `advance(len)` helper that commits a record written at
+/// `mem_pointer`.
+///
+/// ```wasm
+/// ;; with runtime control: drop the record while not recording
+/// global.get $enabled
+/// i32.const 0
+/// global.get $trigger_depth
+/// global.get $trigger_stopped
+/// select
+/// i32.or
+/// i32.eqz
+/// if
+///     return
+/// end
+/// ;; with a ring buffer: write the footer and wrap
+/// global.get $mem_pointer
+/// local.get $len
+/// i32.add
+/// local.get $len
+/// i32.store16 $trace_mem
+/// global.get $mem_pointer
+/// local.get $len
+/// i32.add
+/// i32.const 2
+/// i32.add
+/// local.tee $next
+/// global.get $limit
+/// i32.gt_u
+/// if
+///     local.get $next
+///     global.set $ring_end
+///     global.get $wrap_count
+///     i32.const 1
+///     i32.add
+///     global.set $wrap_count
+///     i32.const 0
+///     global.set $written
+///     i32.const 0
+///     local.set $next
+/// end
+/// local.get $next
+/// global.set $mem_pointer
+/// ```
+fn add_advance(
+    module: &mut Module,
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    ring: Option<&Ring>,
+    control: Option<&Control>,
+) -> FunctionId {
+    let len = module.locals.add(ValType::I32);
+    let next = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+    let mut body = builder.func_body();
+    if let Some(control) = control {
+        body.global_get(control.enabled)
+            .i32_const(0)
+            .global_get(control.trigger.depth)
+            .global_get(control.trigger.stopped)
+            .select(None)
+            .binop(BinaryOp::I32Or)
+            .unop(UnaryOp::I32Eqz)
+            .if_else(
+                None,
+                |then| {
+                    then.return_();
+                },
+                |_| {},
+            );
+    }
+    match ring {
+        Some(ring) => ring_advance(&mut body, trace_mem_id, mem_pointer, ring, len, next),
+        None => {
+            body.global_get(mem_pointer)
+                .local_get(len)
+                .binop(BinaryOp::I32Add)
+                .global_set(mem_pointer);
+        }
+    }
+    builder.finish(vec![len], &mut module.funcs)
+}
+
+/// Adds the `advance_sampled(len)` helper used by memory records.
+///
+/// The counter wraps to 0 after 2^32 accesses and the pattern restarts
+/// there, so unless `period` divides 2^32 the period before the wrap is cut
+/// short.
+///
+/// ```wasm
+/// global.get $sample_counter
+/// i32.const ;; period
+/// i32.rem_u
+/// i32.const ;; window
+/// i32.lt_u
+/// global.get $sample_counter
+/// i32.const 1
+/// i32.add
+/// global.set $sample_counter
+/// i32.eqz
+/// if
+///     return
+/// end
+/// local.get $len
+/// call $advance ;; or the inline mem_pointer increment
+/// ```
+fn add_advance_sampled(
+    module: &mut Module,
+    mem_pointer: GlobalId,
+    sampling: Sampling,
+    advance: Option<FunctionId>,
+) -> FunctionId {
+    let (window, period) = sampling.window_and_period();
+    let counter = add_i32_global(module, true);
+    let len = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+    let mut body = builder.func_body();
+    body.global_get(counter)
+        .i32_const(period.max(1) as i32)
+        .binop(BinaryOp::I32RemU)
+        .i32_const(window as i32)
+        .binop(BinaryOp::I32LtU)
+        .global_get(counter)
+        .i32_const(1)
+        .binop(BinaryOp::I32Add)
+        .global_set(counter)
+        .unop(UnaryOp::I32Eqz)
+        .if_else(
+            None,
+            |then| {
+                then.return_();
+            },
+            |_| {},
+        );
+    match advance {
+        Some(advance) => {
+            body.local_get(len).call(advance);
+        }
+        None => {
+            body.global_get(mem_pointer)
+                .local_get(len)
+                .binop(BinaryOp::I32Add)
+                .global_set(mem_pointer);
+        }
+    }
+    builder.finish(vec![len], &mut module.funcs)
+}
+
+fn ring_advance(
+    body: &mut InstrSeqBuilder,
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    ring: &Ring,
+    len: LocalId,
+    next: LocalId,
+) {
+    body.global_get(mem_pointer)
+        .local_get(len)
+        .binop(BinaryOp::I32Add)
+        .local_get(len)
+        .store(
+            trace_mem_id,
+            StoreKind::I32_16 { atomic: false },
+            MemArg {
+                align: 2,
+                offset: 0,
+            },
+        )
+        .global_get(mem_pointer)
+        .local_get(len)
+        .binop(BinaryOp::I32Add)
+        .i32_const(2)
+        .binop(BinaryOp::I32Add)
+        .local_tee(next)
+        .global_get(ring.limit)
+        .binop(BinaryOp::I32GtU)
+        .if_else(
+            None,
+            |then| {
+                then.local_get(next)
+                    .global_set(ring.ring_end)
+                    .global_get(ring.wrap_count)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .global_set(ring.wrap_count)
+                    .i32_const(0)
+                    .global_set(ring.written)
+                    .i32_const(0)
+                    .local_set(next);
+            },
+            |_| {},
+        )
+        .local_get(next)
+        .global_set(mem_pointer);
+}
+
+/// Adds the `flush()` helper that only calls `r3.check_mem` if there is
+/// something in the trace.
+///
+/// ```wasm
+/// global.get $mem_pointer
+/// if
+///     call $check_mem
+///     i32.const 0
+///     global.set $mem_pointer
+/// end
+/// ```
+fn add_flush(module: &mut Module, mem_pointer: GlobalId, check_mem_id: FunctionId) -> FunctionId {
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().global_get(mem_pointer).if_else(
+        None,
+        |then| {
+            then.call(check_mem_id).i32_const(0).global_set(mem_pointer);
+        },
+        |_| {},
+    );
+    builder.finish(vec![], &mut module.funcs)
+}
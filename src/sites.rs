@@ -0,0 +1,118 @@
+//! Site ids: where in the original module a record comes from.
+//!
+//! With [`Options::sites`](crate::Options::sites) the records of an
+//! instruction are preceded by the id of its site, the index of the
+//! instruction among all instructions of the function bodies in the order
+//! of the code section. The ids only depend on the original module, so
+//! [`Sites`] is rebuilt from it to decode a trace, and written as a JSON
+//! sidecar for other tools.
+
+use std::{collections::HashMap, fmt};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use wasmparser::{Parser, Payload, TypeRef};
+
+/// Record code of the site prefix.
+pub(crate) const SITE_CODE: u8 = 0x19;
+/// Length of the site prefix: the code and the id.
+pub(crate) const SITE_LEN: u32 = 5;
+
+/// An instruction of the original module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Site {
+    /// Function index in the original module.
+    pub func: u32,
+    /// Index of the instruction in the function body, the first one is 0.
+    pub instr: u32,
+    /// Byte offset of the instruction in the module, like the offsets of
+    /// trap frames.
+    pub offset: u32,
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "function {} instr {} @{:#x}",
+            self.func, self.instr, self.offset
+        )
+    }
+}
+
+/// Every instruction of the function bodies, by site id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sites {
+    pub sites: Vec<Site>,
+}
+
+impl Sites {
+    /// Lists the instructions of a binary module.
+    pub fn new(wasm: &[u8]) -> Result<Self> {
+        let mut sites = Vec::new();
+        let mut func = 0;
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        func += matches!(import?.ty, TypeRef::Func(_)) as u32;
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let mut reader = body.get_operators_reader()?;
+                    let mut instr = 0;
+                    while !reader.eof() {
+                        let (_, offset) = reader.read_with_offset()?;
+                        sites.push(Site {
+                            func,
+                            instr,
+                            offset: offset as u32,
+                        });
+                        instr += 1;
+                    }
+                    func += 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { sites })
+    }
+
+    /// Parses the original module, in the binary or the text format.
+    pub fn from_buffer(wasm: &[u8]) -> Result<Self> {
+        Self::new(&crate::text::parse(wasm)?)
+    }
+
+    /// The site with id `id`.
+    pub fn get(&self, id: u32) -> Option<&Site> {
+        self.sites.get(id as usize)
+    }
+
+    /// Site ids by the byte offset of their instruction.
+    pub(crate) fn ids_by_offset(&self) -> HashMap<u32, u32> {
+        self.sites
+            .iter()
+            .enumerate()
+            .map(|(id, site)| (site.offset, id as u32))
+            .collect()
+    }
+
+    /// Site ids of the final `end` of each function, where the return
+    /// appended to every function body is recorded.
+    pub(crate) fn function_ends(&self) -> HashMap<u32, u32> {
+        // Later instructions of a function overwrite the earlier ones
+        self.sites
+            .iter()
+            .enumerate()
+            .map(|(id, site)| (site.func, id as u32))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("sites serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
@@ -0,0 +1,548 @@
+//! Snapshots of the state the trace starts from and of checkpoints.
+//!
+//! A generated start function writes an initial state record (`0x17`) and
+//! then calls the original start function, if any. It runs after the data
+//! segments were applied and the imports were provided. Checkpoint records
+//! (`0x18`) are written before some flushes, so a replay can start from the
+//! middle of the trace. The records hold the
+//! value of every global (except `v128` ones), the size and null flags of
+//! every table and, optionally, the non-zero blocks of the first memory:
+//! a flag byte, then the size in pages and the runs of non-zero blocks as
+//! address, length and bytes. The trace memory is grown first so the runs
+//! fit; if it cannot grow, the record is written without the memory.
+
+use walrus::{
+    ir::{BinaryOp, LoadKind, MemArg, StoreKind, UnaryOp},
+    FunctionBuilder, FunctionId, GlobalId, InitExpr, InstrSeqBuilder, LocalId, MemoryId, Module,
+    TableId, ValType,
+};
+
+use crate::value_len;
+
+const INITIAL_STATE: i32 = 0x17;
+const CHECKPOINT: i32 = 0x18;
+/// Memory is scanned in blocks of this many bytes, runs of non-zero blocks
+/// are recorded.
+const BLOCK_LEN: u32 = 256;
+
+/// The original globals, tables and first memory, taken before the tracer
+/// adds its own.
+pub(crate) struct State {
+    globals: Vec<(GlobalId, ValType)>,
+    tables: Vec<TableId>,
+    memory: Option<MemoryId>,
+}
+
+impl State {
+    pub(crate) fn new(module: &Module) -> Self {
+        Self {
+            globals: module
+                .globals
+                .iter()
+                .filter(|g| g.ty != ValType::V128)
+                .map(|g| (g.id(), g.ty))
+                .collect(),
+            tables: module.tables.iter().map(|t| t.id()).collect(),
+            memory: module.memories.iter().next().map(|m| m.id()),
+        }
+    }
+
+    /// Adds the snapshot function and makes it the start function.
+    pub(crate) fn add_start(
+        &self,
+        module: &mut Module,
+        trace_mem_id: MemoryId,
+        mem_pointer: GlobalId,
+        snapshot_memory: bool,
+    ) {
+        let write = self.add_writer(
+            module,
+            trace_mem_id,
+            mem_pointer,
+            INITIAL_STATE,
+            snapshot_memory,
+        );
+        if let Some(start) = module.start {
+            let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+            builder.func_body().call(write).call(start);
+            module.start = Some(builder.finish(vec![], &mut module.funcs));
+        } else {
+            module.start = Some(write);
+        }
+    }
+
+    /// Adds the `checkpoint()` helper, called before every flush. Every
+    /// `every`th call writes a checkpoint record with the memory, which the
+    /// flush passes to the host with the records before it.
+    ///
+    /// ```wasm
+    /// global.get $countdown
+    /// i32.const 1
+    /// i32.sub
+    /// global.set $countdown
+    /// global.get $countdown
+    /// i32.eqz
+    /// if
+    ///     i32.const ;; every
+    ///     global.set $countdown
+    ///     call $write_checkpoint
+    /// end
+    /// ```
+    pub(crate) fn add_checkpoint(
+        &self,
+        module: &mut Module,
+        trace_mem_id: MemoryId,
+        mem_pointer: GlobalId,
+        every: u32,
+    ) -> FunctionId {
+        let write = self.add_writer(module, trace_mem_id, mem_pointer, CHECKPOINT, true);
+        let every = every.max(1) as i32;
+        let countdown = module.globals.add_local(
+            ValType::I32,
+            true,
+            InitExpr::Value(walrus::ir::Value::I32(every)),
+        );
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder
+            .func_body()
+            .global_get(countdown)
+            .i32_const(1)
+            .binop(BinaryOp::I32Sub)
+            .global_set(countdown)
+            .global_get(countdown)
+            .unop(UnaryOp::I32Eqz)
+            .if_else(
+                None,
+                |then| {
+                    then.i32_const(every).global_set(countdown).call(write);
+                },
+                |_| {},
+            );
+        builder.finish(vec![], &mut module.funcs)
+    }
+
+    /// Adds a function writing the state record with code `code`.
+    ///
+    /// The record is committed with the inline `mem_pointer` increment, so
+    /// it is kept while recording is disabled: the events recorded later
+    /// need it.
+    fn add_writer(
+        &self,
+        module: &mut Module,
+        trace_mem_id: MemoryId,
+        mem_pointer: GlobalId,
+        code: i32,
+        snapshot_memory: bool,
+    ) -> FunctionId {
+        let locals = Locals::add(module);
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        let mut body = builder.func_body();
+        body.global_get(mem_pointer)
+            .local_tee(locals.pos)
+            .i32_const(code)
+            .store(
+                trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(0),
+            );
+        let mut offset = 1;
+        for (global, ty) in &self.globals {
+            body.local_get(locals.pos).global_get(*global);
+            store_value(&mut body, trace_mem_id, *ty, offset);
+            offset += value_len(*ty);
+        }
+        advance(&mut body, locals.pos, offset);
+        for table in &self.tables {
+            self.table(&mut body, trace_mem_id, *table, &locals);
+        }
+        match self.memory.filter(|_| snapshot_memory) {
+            Some(memory) => {
+                grow_for(&mut body, trace_mem_id, memory, &locals);
+                body.if_else(
+                    None,
+                    |then| self.memory(then, trace_mem_id, memory, &locals),
+                    |else_| no_memory(else_, trace_mem_id, &locals),
+                );
+            }
+            None => no_memory(&mut body, trace_mem_id, &locals),
+        }
+        body.local_get(locals.pos).global_set(mem_pointer);
+        builder.finish(vec![], &mut module.funcs)
+    }
+
+    /// ```wasm
+    /// local.get $pos
+    /// table.size $table
+    /// local.tee $size
+    /// i32.store $trace_mem
+    /// ;; for $i below $size
+    ///     local.get $pos
+    ///     local.get $i
+    ///     i32.const 4
+    ///     i32.mul
+    ///     i32.add
+    ///     local.get $i
+    ///     table.get $table
+    ///     ref.is_null
+    ///     i32.store $trace_mem offset=4
+    /// ;; advance $pos by 4 + 4 * $size
+    /// ```
+    fn table(
+        &self,
+        body: &mut InstrSeqBuilder,
+        trace_mem_id: MemoryId,
+        table: TableId,
+        locals: &Locals,
+    ) {
+        body.local_get(locals.pos)
+            .table_size(table)
+            .local_tee(locals.size)
+            .store(trace_mem_id, StoreKind::I32 { atomic: false }, word_arg(0))
+            .i32_const(0)
+            .local_set(locals.i);
+        body.block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |next| {
+                let next_id = next.id();
+                next.local_get(locals.i)
+                    .local_get(locals.size)
+                    .binop(BinaryOp::I32GeU)
+                    .br_if(done_id)
+                    .local_get(locals.pos)
+                    .local_get(locals.i)
+                    .i32_const(4)
+                    .binop(BinaryOp::I32Mul)
+                    .binop(BinaryOp::I32Add)
+                    .local_get(locals.i)
+                    .table_get(table)
+                    .ref_is_null()
+                    .store(trace_mem_id, StoreKind::I32 { atomic: false }, word_arg(4))
+                    .local_get(locals.i)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(locals.i)
+                    .br(next_id);
+            });
+        });
+        body.local_get(locals.pos)
+            .local_get(locals.size)
+            .i32_const(4)
+            .binop(BinaryOp::I32Mul)
+            .binop(BinaryOp::I32Add)
+            .i32_const(4)
+            .binop(BinaryOp::I32Add)
+            .local_set(locals.pos);
+    }
+
+    /// Writes the flag, the size in pages and a placeholder for the run
+    /// count, then scans the memory block by block:
+    ///
+    /// ```wasm
+    /// ;; for $i up to and including $size (in blocks)
+    ///     ;; $nonzero: $i below $size and one of the i64 of block $i is not 0
+    ///     local.get $nonzero
+    ///     local.get $run_start
+    ///     i32.const -1
+    ///     i32.eq
+    ///     i32.and
+    ///     if ;; a run starts
+    ///         local.get $i
+    ///         local.set $run_start
+    ///     end
+    ///     local.get $nonzero
+    ///     i32.eqz
+    ///     local.get $run_start
+    ///     i32.const -1
+    ///     i32.ne
+    ///     i32.and
+    ///     if ;; a run ends: address, length, memory.copy
+    ///         ;; ...
+    ///         i32.const -1
+    ///         local.set $run_start
+    ///     end
+    /// ;; the run count behind the size
+    /// ```
+    fn memory(
+        &self,
+        body: &mut InstrSeqBuilder,
+        trace_mem_id: MemoryId,
+        memory: MemoryId,
+        locals: &Locals,
+    ) {
+        let blocks_per_page = 64 * 1024 / BLOCK_LEN;
+        body.local_get(locals.pos)
+            .i32_const(1)
+            .store(
+                trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                byte_arg(0),
+            )
+            .local_get(locals.pos)
+            .memory_size(memory)
+            .local_tee(locals.size)
+            .store(trace_mem_id, StoreKind::I32 { atomic: false }, word_arg(1))
+            .local_get(locals.pos)
+            .local_set(locals.header)
+            .local_get(locals.size)
+            .i32_const(blocks_per_page as i32)
+            .binop(BinaryOp::I32Mul)
+            .local_set(locals.size)
+            .i32_const(0)
+            .local_set(locals.count)
+            .i32_const(0)
+            .local_set(locals.i)
+            .i32_const(-1)
+            .local_set(locals.run_start);
+        advance(body, locals.pos, 9);
+        body.block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |next| {
+                let next_id = next.id();
+                next.local_get(locals.i)
+                    .local_get(locals.size)
+                    .binop(BinaryOp::I32GtU)
+                    .br_if(done_id);
+                next.local_get(locals.i)
+                    .local_get(locals.size)
+                    .binop(BinaryOp::I32LtU)
+                    .if_else(
+                        ValType::I32,
+                        |then| block_is_nonzero(then, memory, locals),
+                        |else_| {
+                            else_.i32_const(0);
+                        },
+                    )
+                    .local_tee(locals.nonzero)
+                    .local_get(locals.run_start)
+                    .i32_const(-1)
+                    .binop(BinaryOp::I32Eq)
+                    .binop(BinaryOp::I32And)
+                    .if_else(
+                        None,
+                        |then| {
+                            then.local_get(locals.i).local_set(locals.run_start);
+                        },
+                        |_| {},
+                    );
+                next.local_get(locals.nonzero)
+                    .unop(UnaryOp::I32Eqz)
+                    .local_get(locals.run_start)
+                    .i32_const(-1)
+                    .binop(BinaryOp::I32Ne)
+                    .binop(BinaryOp::I32And)
+                    .if_else(
+                        None,
+                        |then| write_run(then, trace_mem_id, memory, locals),
+                        |_| {},
+                    );
+                next.local_get(locals.i)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(locals.i)
+                    .br(next_id);
+            });
+        });
+        body.local_get(locals.header).local_get(locals.count).store(
+            trace_mem_id,
+            StoreKind::I32 { atomic: false },
+            word_arg(5),
+        );
+    }
+}
+
+struct Locals {
+    pos: LocalId,
+    i: LocalId,
+    size: LocalId,
+    header: LocalId,
+    count: LocalId,
+    run_start: LocalId,
+    nonzero: LocalId,
+    addr: LocalId,
+    len: LocalId,
+    acc: LocalId,
+}
+
+impl Locals {
+    fn add(module: &mut Module) -> Self {
+        let [pos, i, size, header, count, run_start, nonzero, addr, len] =
+            [(); 9].map(|_| module.locals.add(ValType::I32));
+        Self {
+            pos,
+            i,
+            size,
+            header,
+            count,
+            run_start,
+            nonzero,
+            addr,
+            len,
+            acc: module.locals.add(ValType::I64),
+        }
+    }
+}
+
+/// Writes the flag of a record without the memory.
+fn no_memory(body: &mut InstrSeqBuilder, trace_mem_id: MemoryId, locals: &Locals) {
+    body.local_get(locals.pos).i32_const(0).store(
+        trace_mem_id,
+        StoreKind::I32_8 { atomic: false },
+        byte_arg(0),
+    );
+    advance(body, locals.pos, 1);
+}
+
+/// Grows the trace memory so the snapshot of `memory` fits even if every
+/// other block is non-zero, and leaves whether it fits on the stack:
+///
+/// ```wasm
+/// ;; $pos + 17 + memory.size * (64 KiB + 1 KiB) in pages, as i64
+/// ;; 9 bytes of header, the pages and 8 bytes for each of up to
+/// ;; 128 * memory.size + 1 runs
+/// memory.size $trace_mem
+/// i64.extend_i32_u
+/// i64.sub
+/// local.tee $acc
+/// i64.const 0
+/// i64.gt_s
+/// if (result i32)
+///     local.get $acc
+///     i32.wrap_i64
+///     memory.grow $trace_mem
+///     i32.const -1
+///     i32.ne
+/// else
+///     i32.const 1
+/// end
+/// ```
+fn grow_for(body: &mut InstrSeqBuilder, trace_mem_id: MemoryId, memory: MemoryId, locals: &Locals) {
+    let page = 64 * 1024;
+    let per_page = page + 8 * (page / BLOCK_LEN as i64 / 2);
+    body.local_get(locals.pos)
+        .unop(UnaryOp::I64ExtendUI32)
+        .memory_size(memory)
+        .unop(UnaryOp::I64ExtendUI32)
+        .i64_const(per_page)
+        .binop(BinaryOp::I64Mul)
+        .binop(BinaryOp::I64Add)
+        .i64_const(17 + page - 1)
+        .binop(BinaryOp::I64Add)
+        .i64_const(16)
+        .binop(BinaryOp::I64ShrU)
+        .memory_size(trace_mem_id)
+        .unop(UnaryOp::I64ExtendUI32)
+        .binop(BinaryOp::I64Sub)
+        .local_tee(locals.acc)
+        .i64_const(0)
+        .binop(BinaryOp::I64GtS)
+        .if_else(
+            ValType::I32,
+            |then| {
+                then.local_get(locals.acc)
+                    .unop(UnaryOp::I32WrapI64)
+                    .memory_grow(trace_mem_id)
+                    .i32_const(-1)
+                    .binop(BinaryOp::I32Ne);
+            },
+            |else_| {
+                else_.i32_const(1);
+            },
+        );
+}
+
+/// Leaves whether one of the i64 of block `$i` is not 0 on the stack.
+fn block_is_nonzero(body: &mut InstrSeqBuilder, memory: MemoryId, locals: &Locals) {
+    body.local_get(locals.i)
+        .i32_const(BLOCK_LEN as i32)
+        .binop(BinaryOp::I32Mul)
+        .local_set(locals.addr)
+        .i64_const(0)
+        .local_set(locals.acc);
+    for offset in (0..BLOCK_LEN).step_by(8) {
+        body.local_get(locals.acc)
+            .local_get(locals.addr)
+            .load(
+                memory,
+                LoadKind::I64 { atomic: false },
+                MemArg { align: 8, offset },
+            )
+            .binop(BinaryOp::I64Or)
+            .local_set(locals.acc);
+    }
+    body.local_get(locals.acc)
+        .i64_const(0)
+        .binop(BinaryOp::I64Ne);
+}
+
+/// Records the blocks from `$run_start` up to `$i` and counts the run.
+fn write_run(
+    body: &mut InstrSeqBuilder,
+    trace_mem_id: MemoryId,
+    memory: MemoryId,
+    locals: &Locals,
+) {
+    body.local_get(locals.run_start)
+        .i32_const(BLOCK_LEN as i32)
+        .binop(BinaryOp::I32Mul)
+        .local_set(locals.addr)
+        .local_get(locals.i)
+        .local_get(locals.run_start)
+        .binop(BinaryOp::I32Sub)
+        .i32_const(BLOCK_LEN as i32)
+        .binop(BinaryOp::I32Mul)
+        .local_set(locals.len)
+        .local_get(locals.pos)
+        .local_get(locals.addr)
+        .store(trace_mem_id, StoreKind::I32 { atomic: false }, word_arg(0))
+        .local_get(locals.pos)
+        .local_get(locals.len)
+        .store(trace_mem_id, StoreKind::I32 { atomic: false }, word_arg(4))
+        .local_get(locals.pos)
+        .i32_const(8)
+        .binop(BinaryOp::I32Add)
+        .local_get(locals.addr)
+        .local_get(locals.len)
+        .memory_copy(memory, trace_mem_id)
+        .local_get(locals.pos)
+        .local_get(locals.len)
+        .binop(BinaryOp::I32Add)
+        .i32_const(8)
+        .binop(BinaryOp::I32Add)
+        .local_set(locals.pos)
+        .local_get(locals.count)
+        .i32_const(1)
+        .binop(BinaryOp::I32Add)
+        .local_set(locals.count)
+        .i32_const(-1)
+        .local_set(locals.run_start);
+}
+
+/// Stores the value on the stack, references as their null flag.
+fn store_value(body: &mut InstrSeqBuilder, trace_mem_id: MemoryId, ty: ValType, offset: u32) {
+    let (kind, align) = match ty {
+        ValType::I64 => (StoreKind::I64 { atomic: false }, 8),
+        ValType::F32 => (StoreKind::F32, 4),
+        ValType::F64 => (StoreKind::F64, 8),
+        ValType::Externref | ValType::Funcref => {
+            body.ref_is_null();
+            (StoreKind::I32 { atomic: false }, 4)
+        }
+        _ => (StoreKind::I32 { atomic: false }, 4),
+    };
+    body.store(trace_mem_id, kind, MemArg { align, offset });
+}
+
+fn advance(body: &mut InstrSeqBuilder, pos: LocalId, len: u32) {
+    body.local_get(pos)
+        .i32_const(len as i32)
+        .binop(BinaryOp::I32Add)
+        .local_set(pos);
+}
+
+fn byte_arg(offset: u32) -> MemArg {
+    MemArg { align: 1, offset }
+}
+
+fn word_arg(offset: u32) -> MemArg {
+    MemArg { align: 4, offset }
+}
@@ -0,0 +1,163 @@
+//! The state of the module at any event of a decoded trace.
+//!
+//! [`StateAt::at`] starts from the last initial state or checkpoint before
+//! the event and applies the stores, syscall writes, bulk memory and table
+//! instructions, `memory.grow` and global and table accesses recorded after
+//! it. [`last_write`] finds the event that wrote a byte, e.g. to see who
+//! corrupted a value.
+//!
+//! Only the first memory is tracked. With a ring buffer, the contents of a
+//! bulk instruction that did not fit into the ring are not in the trace,
+//! the state is then marked [`incomplete`](StateAt::incomplete).
+
+use std::collections::BTreeMap;
+
+use crate::decode::{Event, MemorySnapshot, Value};
+
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// The state of the module right before an event ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateAt {
+    /// Index of the event, `events.len()` for the end of the trace.
+    pub event: usize,
+    /// Index of the snapshot the state was reconstructed from, `None` if
+    /// the trace has none before the event.
+    pub snapshot: Option<usize>,
+    /// The first memory. Bytes that no event wrote since the last snapshot
+    /// with the memory are zero, and the memory only extends up to the last
+    /// byte written if there is no such snapshot.
+    pub memory: Vec<u8>,
+    /// Global values by global idx. Globals neither in a snapshot nor
+    /// accessed since are missing.
+    pub globals: BTreeMap<u32, Value>,
+    /// The elements of the tables as far as they are known: null flags,
+    /// or the functions of the snapshot the decoder could resolve.
+    pub tables: Vec<Vec<Value>>,
+    /// Whether a bulk instruction since the snapshot wrote a range that was
+    /// not recorded, so parts of the memory or the tables are unknown.
+    pub incomplete: bool,
+}
+
+impl StateAt {
+    /// Reconstructs the state right before event `index`, `index` is capped
+    /// at the end of the trace.
+    pub fn at(events: &[Event], index: usize) -> Self {
+        let index = index.min(events.len());
+        let snapshot = events[..index]
+            .iter()
+            .rposition(|e| matches!(e, Event::InitialState { .. } | Event::Checkpoint { .. }));
+        let mut state = Self {
+            event: index,
+            snapshot,
+            memory: Vec::new(),
+            globals: BTreeMap::new(),
+            tables: Vec::new(),
+            incomplete: false,
+        };
+        // The memory comes from the last snapshot that has it, a checkpoint
+        // always does
+        let last_memory = events[..index]
+            .iter()
+            .rposition(|e| snapshot_memory(e).is_some());
+        if let Some(memory) = last_memory.and_then(|i| snapshot_memory(&events[i])) {
+            state.memory = memory.bytes();
+        }
+        let start = snapshot.min(last_memory).unwrap_or(0);
+        for (i, event) in events.iter().enumerate().take(index).skip(start) {
+            match event {
+                Event::InitialState {
+                    globals, tables, ..
+                }
+                | Event::Checkpoint {
+                    globals, tables, ..
+                } => {
+                    state.globals = globals.iter().cloned().collect();
+                    state.tables = tables.clone();
+                }
+                Event::GlobalGet { global, value } | Event::GlobalSet { global, value } => {
+                    state.globals.insert(*global, *value);
+                }
+                Event::TableGet {
+                    table,
+                    index,
+                    value,
+                }
+                | Event::TableSet {
+                    table,
+                    index,
+                    value,
+                } => state.set_element(*table, *index, *value),
+                Event::BulkTable {
+                    table,
+                    index,
+                    values: Some(values),
+                    ..
+                } => {
+                    for (i, value) in values.iter().enumerate() {
+                        state.set_element(*table, index + i as u32, *value);
+                    }
+                }
+                Event::BulkTable { count, .. } => state.incomplete |= *count > 0,
+                // Writes before the snapshot of the memory are in it
+                _ if last_memory.is_some_and(|last| i <= last) => {}
+                Event::Store { .. } | Event::Syscall { .. } => {
+                    for write in event.memory_writes() {
+                        write.apply(&mut state.memory);
+                    }
+                }
+                Event::BulkMemory { bytes, len, .. } => match event.memory_writes().first() {
+                    Some(write) => write.apply(&mut state.memory),
+                    None => state.incomplete |= bytes.is_none() && *len > 0,
+                },
+                Event::MemoryGrow { delta, result } if *result != -1 => {
+                    let pages = *result as usize + *delta as usize;
+                    if state.memory.len() < pages * PAGE_SIZE {
+                        state.memory.resize(pages * PAGE_SIZE, 0);
+                    }
+                }
+                _ => {}
+            }
+        }
+        state
+    }
+
+    /// The `len` bytes at `addr`, `None` if they are not all in the memory.
+    pub fn read(&self, addr: u32, len: u32) -> Option<&[u8]> {
+        self.memory.get(addr as usize..addr as usize + len as usize)
+    }
+
+    fn set_element(&mut self, table: u32, index: u32, value: Value) {
+        let table = table as usize;
+        if self.tables.len() <= table {
+            self.tables.resize(table + 1, Vec::new());
+        }
+        let elements = &mut self.tables[table];
+        if elements.len() <= index as usize {
+            elements.resize(index as usize + 1, Value::Ref { is_null: true });
+        }
+        elements[index as usize] = value;
+    }
+}
+
+/// Index of the last event before `before` that wrote the byte at `addr`,
+/// `None` if no event did: the byte then comes from a snapshot or the data
+/// segments. Bulk memory instructions count even if their bytes were not
+/// recorded.
+pub fn last_write(events: &[Event], addr: u32, before: usize) -> Option<usize> {
+    events[..before.min(events.len())]
+        .iter()
+        .rposition(|e| match e {
+            Event::BulkMemory {
+                addr: start, len, ..
+            } => (*start as u64..*start as u64 + *len as u64).contains(&(addr as u64)),
+            e => e.memory_writes().iter().any(|w| w.covers(addr)),
+        })
+}
+
+fn snapshot_memory(event: &Event) -> Option<&MemorySnapshot> {
+    match event {
+        Event::InitialState { memory, .. } | Event::Checkpoint { memory, .. } => memory.as_ref(),
+        _ => None,
+    }
+}
@@ -0,0 +1,373 @@
+//! Names and source locations for the indices and offsets in a trace.
+//!
+//! Function names come from the `name` section, demangled if they are Rust
+//! or C++ symbols, then from the exports and imports. Parameters and globals
+//! are named from the `name` section, and offsets are located in the source
+//! through the DWARF line table. Indices without a name are printed as they
+//! are, so the output of a module without names is the one of
+//! [`Event`](crate::decode::Event)'s `Display`.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use anyhow::{Context, Result};
+use walrus::Module;
+use wasmparser::{Name, NameSectionReader, Parser, Payload};
+
+use crate::{
+    call_tree::{CallTree, FrameKind},
+    decode::{self, Event, Value},
+    dwarf::{Location, SourceMap},
+    metadata::Metadata,
+    sites::Site,
+};
+
+/// The names and the line table of the original module.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// Function names by function index.
+    functions: Vec<Option<String>>,
+    /// Parameter names by function and local index.
+    params: HashMap<(u32, u32), String>,
+    globals: Vec<Option<String>>,
+    /// Function index and name of each function export.
+    exports: Vec<(u32, String)>,
+    source: SourceMap,
+}
+
+impl Symbols {
+    /// Reads the names and the DWARF of a binary module.
+    pub fn new(wasm: &[u8]) -> Result<Self> {
+        let module = Module::from_buffer(wasm)?;
+        let metadata = Metadata::new(&module);
+        let functions = module
+            .funcs
+            .iter()
+            .enumerate()
+            .map(|(func, f)| {
+                let func = func as u32;
+                if let Some(name) = &f.name {
+                    return Some(demangle(name));
+                }
+                if let Some(export) = metadata.exports.iter().find(|e| e.func == func) {
+                    return Some(export.name.clone());
+                }
+                metadata
+                    .import(func)
+                    .map(|import| format!("{}.{}", import.module, import.name))
+            })
+            .collect();
+        Ok(Self {
+            functions,
+            params: local_names(wasm)?,
+            globals: module.globals.iter().map(|g| g.name.clone()).collect(),
+            exports: metadata
+                .exports
+                .into_iter()
+                .map(|e| (e.func, e.name))
+                .collect(),
+            source: SourceMap::from_buffer(wasm).context("reading the DWARF sections")?,
+        })
+    }
+
+    /// Parses the original module, in the binary or the text format.
+    pub fn from_buffer(wasm: &[u8]) -> Result<Self> {
+        Self::new(&crate::text::parse(wasm)?)
+    }
+
+    /// Number of functions, imports included.
+    pub fn function_count(&self) -> u32 {
+        self.functions.len() as u32
+    }
+
+    /// The name of function `func`, if it has one.
+    pub fn function(&self, func: u32) -> Option<&str> {
+        self.functions.get(func as usize)?.as_deref()
+    }
+
+    /// The name of function `func`, or `function <func>`.
+    pub fn function_name(&self, func: u32) -> String {
+        match self.function(func) {
+            Some(name) => name.to_string(),
+            None => format!("function {}", func),
+        }
+    }
+
+    /// The index of the function named `name`.
+    pub fn function_index(&self, name: &str) -> Option<u32> {
+        let func = self
+            .functions
+            .iter()
+            .position(|n| n.as_deref() == Some(name))?;
+        Some(func as u32)
+    }
+
+    /// The name of parameter `param` of function `func`.
+    pub fn param(&self, func: u32, param: u32) -> Option<&str> {
+        self.params.get(&(func, param)).map(String::as_str)
+    }
+
+    /// The name of global `global`.
+    pub fn global(&self, global: u32) -> Option<&str> {
+        self.globals.get(global as usize)?.as_deref()
+    }
+
+    /// The name of the function export `export`.
+    pub fn export(&self, export: u32) -> Option<&str> {
+        Some(&self.exports.get(export as usize)?.1)
+    }
+
+    /// The source location of the instruction at byte offset `offset` in
+    /// the module.
+    pub fn locate(&self, offset: u32) -> Option<Location> {
+        self.source.locate(offset)
+    }
+
+    /// The source location of function `func`.
+    pub fn locate_function(&self, func: u32) -> Option<Location> {
+        self.source.locate_function(func)
+    }
+
+    /// Displays an event with names instead of indices.
+    pub fn event<'a>(&'a self, event: &'a Event) -> impl Display + 'a {
+        SymbolizedEvent {
+            symbols: self,
+            event,
+        }
+    }
+
+    /// Displays a site with the name of its function and its source
+    /// location.
+    pub fn site(&self, site: &Site) -> String {
+        let mut text = format!(
+            "{} instr {} @{:#x}",
+            self.function_name(site.func),
+            site.instr,
+            site.offset
+        );
+        if let Some(location) = self.locate(site.offset) {
+            text.push_str(&format!(" ({})", location));
+        }
+        text
+    }
+
+    /// Displays a frame kind with the names of its function or export.
+    pub fn frame_kind(&self, kind: &FrameKind) -> String {
+        let (prefix, name) = match *kind {
+            FrameKind::Export { export } => ("export", self.export(export)),
+            FrameKind::Function { func } => ("function", self.function(func)),
+            FrameKind::Call { func } => ("call", self.function(func)),
+            FrameKind::Import { func } => ("host import", self.function(func)),
+            FrameKind::CallIndirect { .. } => ("", None),
+        };
+        match name {
+            Some(name) => format!("{} {}", prefix, name),
+            None => kind.to_string(),
+        }
+    }
+
+    /// Displays a call tree with names, see [`Symbols::frame_kind`].
+    pub fn call_tree<'a>(&'a self, tree: &'a CallTree) -> impl Display + 'a {
+        SymbolizedTree {
+            symbols: self,
+            tree,
+        }
+    }
+
+    fn write_function(&self, f: &mut fmt::Formatter<'_>, func: u32) -> fmt::Result {
+        match self.function(func) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", func),
+        }
+    }
+
+    fn write_global(&self, f: &mut fmt::Formatter<'_>, global: u32) -> fmt::Result {
+        match self.global(global) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", global),
+        }
+    }
+
+    /// Writes the args or params of a call of `func`, named if they are.
+    fn write_params(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        func: Option<u32>,
+        values: &[Value],
+    ) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match func.and_then(|func| self.param(func, i as u32)) {
+                Some(name) => write!(f, "{}: {}", name, value)?,
+                None => write!(f, "{}", value)?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+/// The local names of the `name` section, walrus reads it before the locals
+/// of the function bodies exist and drops them.
+fn local_names(wasm: &[u8]) -> Result<HashMap<(u32, u32), String>> {
+    let mut names = HashMap::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        let Payload::CustomSection(section) = payload? else {
+            continue;
+        };
+        if section.name() != "name" {
+            continue;
+        }
+        for subsection in NameSectionReader::new(section.data(), section.data_offset()) {
+            let Name::Local(functions) = subsection? else {
+                continue;
+            };
+            for function in functions {
+                let function = function?;
+                for naming in function.names {
+                    let naming = naming?;
+                    if !naming.name.is_empty() {
+                        names.insert((function.index, naming.index), naming.name.to_string());
+                    }
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Demangles a Rust or C++ symbol, other names are returned as they are.
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // Without the hash
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_string()
+}
+
+struct SymbolizedEvent<'a> {
+    symbols: &'a Symbols,
+    event: &'a Event,
+}
+
+impl Display for SymbolizedEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbols = self.symbols;
+        match self.event {
+            Event::FunctionEntry { func, params } => {
+                write!(f, "enter ")?;
+                symbols.write_function(f, *func)?;
+                write!(f, " ")?;
+                symbols.write_params(f, Some(*func), params)
+            }
+            Event::Return { func, results } => {
+                write!(f, "return ")?;
+                symbols.write_function(f, *func)?;
+                write!(f, " ")?;
+                symbols.write_params(f, None, results)
+            }
+            Event::Call { func, args } => {
+                write!(f, "call ")?;
+                symbols.write_function(f, *func)?;
+                write!(f, " ")?;
+                symbols.write_params(f, Some(*func), args)
+            }
+            Event::ImportCall { func, args } => {
+                write!(f, "call import ")?;
+                symbols.write_function(f, *func)?;
+                write!(f, " ")?;
+                symbols.write_params(f, None, args)
+            }
+            Event::ExportCall { export, args } => {
+                match symbols.exports.get(*export as usize) {
+                    Some((_, name)) => write!(f, "export call {} ", name)?,
+                    None => write!(f, "export call {} ", export)?,
+                }
+                let func = symbols.exports.get(*export as usize).map(|(func, _)| *func);
+                symbols.write_params(f, func, args)
+            }
+            Event::ExportReturn { export, results } => {
+                match symbols.export(*export) {
+                    Some(name) => write!(f, "export return {} ", name)?,
+                    None => write!(f, "export return {} ", export)?,
+                }
+                symbols.write_params(f, None, results)
+            }
+            Event::GlobalGet { global, value } => {
+                write!(f, "global.get ")?;
+                symbols.write_global(f, *global)?;
+                write!(f, " -> {}", value)
+            }
+            Event::GlobalSet { global, value } => {
+                write!(f, "global.set ")?;
+                symbols.write_global(f, *global)?;
+                write!(f, " <- {}", value)
+            }
+            Event::Syscall {
+                func,
+                errno,
+                writes,
+            } => {
+                write!(f, "syscall ")?;
+                symbols.write_function(f, *func)?;
+                write!(f, " -> errno {} ", errno)?;
+                decode::write_writes(f, writes)
+            }
+            Event::Trap { depth, frames } => {
+                write!(f, "trap depth {} [", depth)?;
+                for (i, frame) in frames.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    symbols.write_function(f, frame.func)?;
+                    write!(f, " @{:#x}", frame.offset)?;
+                    if let Some(location) = symbols.locate(frame.offset) {
+                        write!(f, " ({})", location)?;
+                    }
+                }
+                write!(f, "]")
+            }
+            Event::InitialState {
+                globals,
+                tables,
+                memory,
+            } => {
+                write!(f, "initial state ")?;
+                let global = |f: &mut fmt::Formatter<'_>, g| symbols.write_global(f, g);
+                decode::write_state(f, globals, tables, memory.as_ref(), &global)
+            }
+            Event::Checkpoint {
+                globals,
+                tables,
+                memory,
+            } => {
+                write!(f, "checkpoint ")?;
+                let global = |f: &mut fmt::Formatter<'_>, g| symbols.write_global(f, g);
+                decode::write_state(f, globals, tables, memory.as_ref(), &global)
+            }
+            event => write!(f, "{}", event),
+        }
+    }
+}
+
+struct SymbolizedTree<'a> {
+    symbols: &'a Symbols,
+    tree: &'a CallTree,
+}
+
+impl Display for SymbolizedTree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tree.fmt_with(f, &|kind| self.symbols.frame_kind(kind))
+    }
+}
@@ -0,0 +1,69 @@
+//! WebAssembly text format input and output.
+
+use std::{borrow::Cow, collections::HashSet, fmt::Write};
+
+use anyhow::Result;
+use walrus::{FunctionId, FunctionKind, Module};
+use wasmparser::{Parser, Payload};
+
+use crate::validate::{emit_with_func_order, flatten};
+
+/// Comment appended to the instructions inserted by the tracer.
+const INSERTED: &str = ";; r3";
+
+/// Converts WebAssembly text to a binary module. Binary modules are returned
+/// unchanged.
+pub fn parse(buffer: &[u8]) -> Result<Cow<'_, [u8]>> {
+    Ok(wat::parse_bytes(buffer)?)
+}
+
+/// Prints the module in the text format. Instructions inserted by the tracer
+/// are followed by a `;; r3` comment, so they stand out from the original
+/// code.
+pub fn emit_wat(module: &mut Module) -> Result<String> {
+    let (bytes, order) = emit_with_func_order(module);
+    let inserted = inserted_offsets(module, &order, &bytes)?;
+    let mut printer = wasmprinter::Printer::new();
+    let mut wat = String::new();
+    for (offset, line) in printer.offsets_and_lines(&bytes)? {
+        match offset {
+            Some(offset) if inserted.contains(&offset) => {
+                writeln!(wat, "{}  {}", line.trim_end(), INSERTED)?
+            }
+            _ => wat.push_str(line),
+        }
+    }
+    Ok(wat)
+}
+
+/// Byte offsets in the emitted module of the instructions the tracer
+/// inserted, including all instructions of the helper functions it added.
+fn inserted_offsets(module: &Module, order: &[FunctionId], bytes: &[u8]) -> Result<HashSet<usize>> {
+    let mut locals = order
+        .iter()
+        .filter_map(|id| match &module.funcs.get(*id).kind {
+            FunctionKind::Local(f) => Some(flatten(f)),
+            _ => None,
+        });
+    let mut offsets = HashSet::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        if let Payload::CodeSectionEntry(body) = payload? {
+            let locs = locals.next().unwrap_or_default();
+            let mut reader = body.get_operators_reader()?;
+            let mut index = 0;
+            while !reader.eof() {
+                let (_, offset) = reader.read_with_offset()?;
+                if locs
+                    .get(index)
+                    .copied()
+                    .flatten()
+                    .is_some_and(|loc| loc.is_default())
+                {
+                    offsets.insert(offset);
+                }
+                index += 1;
+            }
+        }
+    }
+    Ok(offsets)
+}
@@ -0,0 +1,249 @@
+//! Shadow call stack that tells where a trapping module died.
+//!
+//! Every function entry pushes a frame of 8 bytes (func idx, instruction
+//! offset) to a memory of its own and every return pops it. Before an
+//! instruction that may trap, the offset of the running frame is updated. A
+//! trap leaves the stack as it was, so the host can call the exported
+//! `r3_trap_info` afterwards: it writes a trap record (`0x16`: depth, frame
+//! count, then func idx and offset of each frame, innermost first), flushes
+//! the trace, resets the stack and the depth of a trigger function the trap
+//! left, and returns the depth.
+//!
+//! The stack pointer wraps around within one page, deeper stacks only keep
+//! their innermost frames.
+
+use walrus::{
+    ir::{BinaryOp, Instr, LoadKind, MemArg, StoreKind, UnaryOp},
+    FunctionBuilder, FunctionId, GlobalId, InitExpr, LocalId, MemoryId, Module, ValType,
+};
+
+use crate::{exports, runtime::Runtime};
+
+const CODE: i32 = 0x16;
+const HEADER_LEN: u32 = 9;
+const FRAME_LEN: u32 = 8;
+/// Frames the stack keeps, one page of them.
+const FRAMES: u32 = 64 * 1024 / FRAME_LEN;
+/// Masks the stack pointer to an address in the page.
+pub(crate) const ADDR_MASK: i32 = 64 * 1024 - 1;
+
+/// The stack memory and pointer, see the module documentation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ShadowStack {
+    pub(crate) memory: MemoryId,
+    /// Byte offset of the running frame, 0 when the stack is empty.
+    pub(crate) sp: GlobalId,
+    /// Scratch local saving `sp` across calls that may enter the host.
+    pub(crate) saved_sp: LocalId,
+}
+
+impl ShadowStack {
+    pub(crate) fn add(module: &mut Module) -> Self {
+        let memory = module.memories.add_local(false, 1, None);
+        let sp = module.globals.add_local(
+            ValType::I32,
+            true,
+            InitExpr::Value(walrus::ir::Value::I32(0)),
+        );
+        Self {
+            memory,
+            sp,
+            saved_sp: module.locals.add(ValType::I32),
+        }
+    }
+
+    /// Adds and exports `r3_trap_info`.
+    ///
+    /// ```wasm
+    /// global.get $sp
+    /// i32.const 3
+    /// i32.shr_u
+    /// local.set $depth
+    /// ;; $count: $depth capped at the frames the stack (or ring) keeps
+    /// ;; code, depth and count at $mem_pointer
+    /// block $done
+    ///     loop $next
+    ///         ;; br_if $done after $count frames
+    ///         local.get $pos
+    ///         local.get $addr
+    ///         i32.const 0xffff
+    ///         i32.and
+    ///         i64.load $shadow
+    ///         i64.store $trace_mem
+    ///         ;; advance $pos by 8, move $addr 8 down, increment $i
+    ///         br $next
+    ///     end
+    /// end
+    /// local.get $count
+    /// i32.const 8
+    /// i32.mul
+    /// i32.const 9
+    /// i32.add
+    /// call $advance ;; or the inline mem_pointer increment
+    /// i32.const 0
+    /// global.set $sp
+    /// ;; with a trigger function: 0 to $trigger_depth and $trigger_stopped
+    /// call $flush ;; or check_mem and the reset, nothing with a ring buffer
+    /// local.get $depth
+    /// ```
+    pub(crate) fn add_trap_info(
+        &self,
+        module: &mut Module,
+        trace_mem_id: MemoryId,
+        mem_pointer: GlobalId,
+        runtime: &Runtime,
+        check_mem_id: Option<FunctionId>,
+    ) {
+        let [depth, count, total, pos, addr, i] = [(); 6].map(|_| module.locals.add(ValType::I32));
+        let max_frames = match runtime.ring_size() {
+            Some(size) => FRAMES.min(size.saturating_sub(HEADER_LEN + 2) / FRAME_LEN),
+            None => FRAMES,
+        };
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        let mut body = builder.func_body();
+        body.global_get(self.sp)
+            .i32_const(3)
+            .binop(BinaryOp::I32ShrU)
+            .local_tee(depth)
+            .i32_const(max_frames as i32)
+            .local_get(depth)
+            .i32_const(max_frames as i32)
+            .binop(BinaryOp::I32LtU)
+            .select(None)
+            .local_tee(count)
+            .i32_const(FRAME_LEN as i32)
+            .binop(BinaryOp::I32Mul)
+            .i32_const(HEADER_LEN as i32)
+            .binop(BinaryOp::I32Add)
+            .local_set(total);
+        runtime.reserve(&mut body, mem_pointer, total);
+        let i32_store = StoreKind::I32 { atomic: false };
+        body.global_get(mem_pointer)
+            .i32_const(CODE)
+            .store(
+                trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                MemArg {
+                    align: 1,
+                    offset: 0,
+                },
+            )
+            .global_get(mem_pointer)
+            .local_get(depth)
+            .store(trace_mem_id, i32_store, mem_arg(1))
+            .global_get(mem_pointer)
+            .local_get(count)
+            .store(trace_mem_id, i32_store, mem_arg(5))
+            .global_get(mem_pointer)
+            .i32_const(HEADER_LEN as i32)
+            .binop(BinaryOp::I32Add)
+            .local_set(pos)
+            .global_get(self.sp)
+            .local_set(addr)
+            .i32_const(0)
+            .local_set(i);
+        body.block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |next| {
+                let next_id = next.id();
+                next.local_get(i)
+                    .local_get(count)
+                    .binop(BinaryOp::I32GeU)
+                    .br_if(done_id)
+                    .local_get(pos)
+                    .local_get(addr)
+                    .i32_const(ADDR_MASK)
+                    .binop(BinaryOp::I32And)
+                    .load(
+                        self.memory,
+                        LoadKind::I64 { atomic: false },
+                        MemArg {
+                            align: 8,
+                            offset: 0,
+                        },
+                    )
+                    .store(
+                        trace_mem_id,
+                        StoreKind::I64 { atomic: false },
+                        MemArg {
+                            align: 8,
+                            offset: 0,
+                        },
+                    )
+                    .local_get(pos)
+                    .i32_const(FRAME_LEN as i32)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(pos)
+                    .local_get(addr)
+                    .i32_const(FRAME_LEN as i32)
+                    .binop(BinaryOp::I32Sub)
+                    .local_set(addr)
+                    .local_get(i)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(i)
+                    .br(next_id);
+            });
+        });
+        body.local_get(total);
+        runtime.commit(&mut body, mem_pointer);
+        body.i32_const(0).global_set(self.sp);
+        runtime.reset_trigger(&mut body);
+        exports::flush(&mut body, mem_pointer, runtime, check_mem_id);
+        body.local_get(depth);
+        let trap_info = builder.finish(vec![], &mut module.funcs);
+        module.exports.add("r3_trap_info", trap_info);
+    }
+}
+
+/// Whether the instruction may trap, so the offset of the running frame is
+/// updated before it.
+pub(crate) fn may_trap(instr: &Instr) -> bool {
+    match instr {
+        // Atomic loads and stores too, they also trap when misaligned
+        Instr::Load(_)
+        | Instr::Store(_)
+        | Instr::AtomicRmw(_)
+        | Instr::Cmpxchg(_)
+        | Instr::AtomicNotify(_)
+        | Instr::AtomicWait(_)
+        | Instr::Call(_)
+        | Instr::CallIndirect(_)
+        | Instr::Unreachable(_)
+        | Instr::MemoryCopy(_)
+        | Instr::MemoryFill(_)
+        | Instr::MemoryInit(_)
+        | Instr::TableGet(_)
+        | Instr::TableSet(_)
+        | Instr::TableCopy(_)
+        | Instr::TableFill(_)
+        | Instr::TableInit(_) => true,
+        Instr::Binop(binop) => matches!(
+            binop.op,
+            BinaryOp::I32DivS
+                | BinaryOp::I32DivU
+                | BinaryOp::I32RemS
+                | BinaryOp::I32RemU
+                | BinaryOp::I64DivS
+                | BinaryOp::I64DivU
+                | BinaryOp::I64RemS
+                | BinaryOp::I64RemU
+        ),
+        Instr::Unop(unop) => matches!(
+            unop.op,
+            UnaryOp::I32TruncSF32
+                | UnaryOp::I32TruncUF32
+                | UnaryOp::I32TruncSF64
+                | UnaryOp::I32TruncUF64
+                | UnaryOp::I64TruncSF32
+                | UnaryOp::I64TruncUF32
+                | UnaryOp::I64TruncSF64
+                | UnaryOp::I64TruncUF64
+        ),
+        _ => false,
+    }
+}
+
+fn mem_arg(offset: u32) -> MemArg {
+    MemArg { align: 4, offset }
+}
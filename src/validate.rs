@@ -0,0 +1,206 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::{self, Display},
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use walrus::{
+    ir::{self, Instr, InstrLocId, InstrSeq, Visitor},
+    CustomSection, FunctionId, FunctionKind, IdsToIndices, LocalFunction, Module,
+};
+use wasmparser::{Parser, Payload, Validator, WasmFeatures};
+
+/// The instrumented module does not validate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Message of the validator.
+    pub message: String,
+    /// Byte offset of the error in the instrumented module.
+    pub offset: usize,
+    /// The instruction that broke validation, if the error is in a function
+    /// body.
+    pub instr: Option<InvalidInstr>,
+}
+
+/// Location of the instruction that broke validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidInstr {
+    /// Index of the function in the instrumented module.
+    pub func_index: u32,
+    /// Index of the function in the original module, `None` for helper
+    /// functions added by the tracer.
+    pub original_func_index: Option<u32>,
+    /// Index of the instruction in the function body.
+    pub instr_index: usize,
+    /// The instruction as read by the validator.
+    pub instr: String,
+    /// Whether the instruction was inserted by the tracer.
+    pub inserted: bool,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "instrumented module is invalid: {} (at offset {:#x})",
+            self.message, self.offset
+        )?;
+        if let Some(instr) = &self.instr {
+            write!(f, " in function {}", instr.func_index)?;
+            match instr.original_func_index {
+                Some(index) => write!(f, " (original function {})", index)?,
+                None => write!(f, " (added by the tracer)")?,
+            }
+            write!(f, ", instruction {} `{}`", instr.instr_index, instr.instr)?;
+            if instr.inserted {
+                write!(f, " inserted by the tracer")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Emits the module and runs the validator on the bytes. This is what
+/// [`Options::validate`](crate::Options::validate) does after instrumenting.
+///
+/// `func_indices` maps the functions of the original module to their index.
+pub fn validate(
+    module: &mut Module,
+    func_indices: &HashMap<FunctionId, u32>,
+) -> Result<(), ValidationError> {
+    let (bytes, order) = emit_with_func_order(module);
+    let error = match Validator::new_with_features(WasmFeatures {
+        multi_memory: true,
+        ..WasmFeatures::default()
+    })
+    .validate_all(&bytes)
+    {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    let offset = error.offset();
+    let instr = find_instr(module, &order, &bytes, offset).map(|(func, instr_index, instr)| {
+        let inserted = match &module.funcs.get(func).kind {
+            FunctionKind::Local(f) => match flatten(f).get(instr_index) {
+                Some(loc) => loc.is_some_and(|loc| loc.is_default()),
+                None => true,
+            },
+            _ => false,
+        };
+        InvalidInstr {
+            func_index: order.iter().position(|id| *id == func).unwrap_or_default() as u32,
+            original_func_index: func_indices.get(&func).copied(),
+            instr_index,
+            instr,
+            inserted,
+        }
+    });
+    Err(ValidationError {
+        message: error.message().to_string(),
+        offset,
+        instr,
+    })
+}
+
+/// Finds the function and the instruction in the emitted bytes that contain
+/// `offset`, `order` are the function ids by index.
+fn find_instr(
+    module: &Module,
+    order: &[FunctionId],
+    bytes: &[u8],
+    offset: usize,
+) -> Option<(FunctionId, usize, String)> {
+    let imported = order
+        .iter()
+        .take_while(|id| matches!(module.funcs.get(**id).kind, FunctionKind::Import(_)))
+        .count();
+    let mut code_index = 0;
+    for payload in Parser::new(0).parse_all(bytes) {
+        if let Payload::CodeSectionEntry(body) = payload.ok()? {
+            let range = body.range();
+            if range.contains(&offset) {
+                let mut reader = body.get_operators_reader().ok()?;
+                let mut found = None;
+                let mut index = 0;
+                while !reader.eof() {
+                    let (op, op_offset) = reader.read_with_offset().ok()?;
+                    if op_offset > offset {
+                        break;
+                    }
+                    found = Some((index, format!("{:?}", op)));
+                    index += 1;
+                }
+                let (index, op) = found?;
+                return Some((*order.get(imported + code_index)?, index, op));
+            }
+            code_index += 1;
+        }
+    }
+    None
+}
+
+/// Emits the module and returns the function ids by the index walrus
+/// assigned them.
+///
+/// walrus passes its id to index table to the custom sections it emits, a
+/// temporary one reads the function indices from it. It is emitted empty,
+/// and like all custom sections dropped by `emit_wasm`.
+pub(crate) fn emit_with_func_order(module: &mut Module) -> (Vec<u8>, Vec<FunctionId>) {
+    let funcs: Vec<FunctionId> = module.funcs.iter().map(|f| f.id()).collect();
+    let indices = Arc::new(Mutex::new(Vec::new()));
+    module.customs.add(FuncIndices {
+        funcs: funcs.clone(),
+        indices: indices.clone(),
+    });
+    let bytes = module.emit_wasm();
+    let indices = mem::take(&mut *indices.lock().unwrap());
+    let mut order: Vec<(u32, FunctionId)> = indices.into_iter().zip(funcs).collect();
+    order.sort();
+    (bytes, order.into_iter().map(|(_, id)| id).collect())
+}
+
+#[derive(Debug)]
+struct FuncIndices {
+    funcs: Vec<FunctionId>,
+    /// Index of each of `funcs`, set on emit.
+    indices: Arc<Mutex<Vec<u32>>>,
+}
+
+impl CustomSection for FuncIndices {
+    fn name(&self) -> &str {
+        "r3.func_indices"
+    }
+
+    fn data(&self, ids_to_indices: &IdsToIndices) -> Cow<'_, [u8]> {
+        *self.indices.lock().unwrap() = self
+            .funcs
+            .iter()
+            .map(|f| ids_to_indices.get_func_index(*f))
+            .collect();
+        Cow::Borrowed(&[])
+    }
+}
+
+/// Locations of the instructions of a function in the order walrus emits
+/// them. The implicit `else` and `end` of blocks have no location of their
+/// own and are `None`.
+pub(crate) fn flatten(func: &LocalFunction) -> Vec<Option<InstrLocId>> {
+    let mut flatten = Flatten(Vec::new());
+    ir::dfs_in_order(&mut flatten, func, func.entry_block());
+    flatten.0
+}
+
+struct Flatten(Vec<Option<InstrLocId>>);
+
+impl<'instr> Visitor<'instr> for Flatten {
+    fn end_instr_seq(&mut self, _: &'instr InstrSeq) {
+        // Either `end` or the `else` that opens the alternative
+        self.0.push(None);
+    }
+
+    fn visit_instr(&mut self, _: &'instr Instr, loc: &'instr InstrLocId) {
+        self.0.push(Some(*loc));
+    }
+}
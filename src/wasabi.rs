@@ -0,0 +1,132 @@
+//! A text dump of a trace in the style of a Wasabi analysis that logs its
+//! hooks, to diff our traces against the ones of Wasabi.
+//!
+//! One line per hook, with indices instead of names like Wasabi:
+//!
+//! ```text
+//! begin_function func[3] (i32:5, f32:1.0)
+//! call func[3] (i32:5, f32:1.0) -> f64:2.0
+//! i32.load @0x1000 = 42
+//! ```
+//!
+//! A call is printed before the events of the callee with the results of its
+//! call end. Addresses are effective addresses. The host side of the trace,
+//! export calls and returns, syscalls, traps, initial states and
+//! checkpoints, has no Wasabi hook and is left out.
+
+use std::collections::HashMap;
+
+use crate::{
+    call_tree::{CallTree, FrameKind},
+    decode::{opcode_name, Event, Value},
+};
+
+/// The lines of the events.
+pub fn dump(events: &[Event]) -> Vec<String> {
+    // The call end of each call, by the event of the call
+    let tree = CallTree::new(events);
+    let ends: HashMap<usize, usize> = tree
+        .frames()
+        .into_iter()
+        .filter(|(_, frame)| !matches!(frame.kind, FrameKind::Function { .. }))
+        .filter_map(|(_, frame)| Some((frame.start, frame.end?)))
+        .collect();
+    events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, event)| {
+            let results = || match ends.get(&i).map(|end| &events[*end]) {
+                Some(Event::CallEnd { results, .. }) => format!(" -> {}", results_text(results)),
+                _ => String::new(),
+            };
+            Some(match event {
+                Event::FunctionEntry { func, params } => {
+                    format!("begin_function func[{}] {}", func, values(params))
+                }
+                Event::Return { func, results } => {
+                    format!("return func[{}] {}", func, values(results))
+                }
+                Event::Call { func, args } | Event::ImportCall { func, args } => {
+                    format!("call func[{}] {}{}", func, values(args), results())
+                }
+                Event::CallIndirect {
+                    table,
+                    args,
+                    elem_index,
+                    ..
+                } => {
+                    // The callee is known if it is a function of the module
+                    let target = match events.get(i + 1) {
+                        Some(Event::FunctionEntry { func, .. }) => format!("func[{}]", func),
+                        _ => format!("table[{}][{}]", table, elem_index),
+                    };
+                    format!("call_indirect {} {}{}", target, values(args), results())
+                }
+                Event::Load {
+                    opcode,
+                    addr,
+                    value,
+                }
+                | Event::Store {
+                    opcode,
+                    addr,
+                    value,
+                } => format!("{} @{:#x} = {}", opcode_name(*opcode), addr, number(value)),
+                Event::GlobalGet { global, value } => {
+                    format!("global.get global[{}] = {}", global, typed(value))
+                }
+                Event::GlobalSet { global, value } => {
+                    format!("global.set global[{}] = {}", global, typed(value))
+                }
+                Event::TableGet {
+                    table,
+                    index,
+                    value,
+                } => format!("table.get table[{}][{}] = {}", table, index, typed(value)),
+                Event::TableSet {
+                    table,
+                    index,
+                    value,
+                } => format!("table.set table[{}][{}] = {}", table, index, typed(value)),
+                _ => return None,
+            })
+        })
+        .collect()
+}
+
+/// A value with its type, like `i32:5` or `f32:1.0`.
+fn typed(value: &Value) -> String {
+    match value {
+        Value::I32(_) => format!("i32:{}", number(value)),
+        Value::I64(_) => format!("i64:{}", number(value)),
+        Value::F32(_) => format!("f32:{}", number(value)),
+        Value::F64(_) => format!("f64:{}", number(value)),
+        Value::Ref { .. } | Value::Func(_) => format!("ref:{}", number(value)),
+    }
+}
+
+/// A value without its type. Floats keep their fraction, like `1.0`.
+fn number(value: &Value) -> String {
+    match value {
+        Value::I32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+        Value::F32(v) => format!("{:?}", v),
+        Value::F64(v) => format!("{:?}", v),
+        Value::Ref { is_null: true } => "null".to_string(),
+        Value::Ref { is_null: false } => "nonnull".to_string(),
+        Value::Func(func) => format!("func{}", func),
+    }
+}
+
+fn values(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(typed).collect();
+    format!("({})", values.join(", "))
+}
+
+/// A single result without parentheses, like `f64:2.0`.
+fn results_text(results: &[Value]) -> String {
+    match results {
+        [result] => typed(result),
+        results => values(results),
+    }
+}
@@ -0,0 +1,601 @@
+//! Recording of the memory the host writes during WASI calls, so a replay
+//! does not need the host.
+//!
+//! Every call to a `wasi_snapshot_preview1` import with memory side effects
+//! is followed by a syscall record, written by a helper function added for
+//! that import: the func idx, the errno result, the number of writes and
+//! each write as address, length and the written bytes. Failed calls write
+//! nothing. Modules importing a preview1 function that is not in the table
+//! of [`writes`] are rejected, a replay would miss its writes.
+
+use std::collections::HashMap;
+
+use walrus::{
+    ir::{BinaryOp, LoadKind, MemArg, StoreKind, UnaryOp, Value},
+    FunctionBuilder, FunctionId, GlobalId, ImportKind, InitExpr, InstrSeqBuilder, LocalId,
+    MemoryId, Module, ValType,
+};
+
+use crate::{runtime::Runtime, InstrumentError};
+
+/// Module name of the WASI imports.
+pub(crate) const MODULE: &str = "wasi_snapshot_preview1";
+
+/// Record code of a syscall.
+const CODE: i32 = 0x12;
+/// Code, func idx, errno and the number of writes.
+const HEADER_LEN: i32 = 13;
+/// Address and length in front of the written bytes.
+const WRITE_HEADER_LEN: i32 = 8;
+
+/// Memory the host writes during a successful call, described by the
+/// parameters of the import.
+#[derive(Debug, Clone, Copy)]
+enum Write {
+    /// `len` bytes at the pointer in parameter `ptr`.
+    Fixed { ptr: usize, len: u32 },
+    /// The buffer at parameter `ptr` with its length in parameter `len`.
+    Buffer { ptr: usize, len: usize },
+    /// `scale` times the size returned by the last `*_sizes_get` call.
+    Sized { ptr: usize, size: Size, scale: u32 },
+    /// `scale` times the count the host stored at the pointer in `count`,
+    /// like the bytes `fd_readdir` used or the events of `poll_oneoff`.
+    Counted {
+        ptr: usize,
+        count: usize,
+        scale: u32,
+    },
+    /// The buffers of the iovec array at parameter `iovs`, filled in order
+    /// up to the byte count the host stored at the pointer in `nread`.
+    Iovecs {
+        iovs: usize,
+        iovs_len: usize,
+        nread: usize,
+    },
+}
+
+/// Sizes returned by `args_sizes_get` and `environ_sizes_get`, needed to
+/// know how much `args_get` and `environ_get` write.
+#[derive(Debug, Clone, Copy)]
+enum Size {
+    Argc,
+    ArgvBuf,
+    EnvironCount,
+    EnvironBuf,
+}
+
+/// The memory each preview1 function writes, empty for the functions
+/// without memory side effects, `None` for unknown functions.
+fn writes(name: &str) -> Option<&'static [Write]> {
+    use Write::*;
+    Some(match name {
+        "fd_read" => &[
+            Fixed { ptr: 3, len: 4 },
+            Iovecs {
+                iovs: 1,
+                iovs_len: 2,
+                nread: 3,
+            },
+        ],
+        "fd_pread" => &[
+            Fixed { ptr: 4, len: 4 },
+            Iovecs {
+                iovs: 1,
+                iovs_len: 2,
+                nread: 4,
+            },
+        ],
+        "fd_write" => &[Fixed { ptr: 3, len: 4 }],
+        "fd_pwrite" | "sock_send" => &[Fixed { ptr: 4, len: 4 }],
+        "fd_seek" => &[Fixed { ptr: 3, len: 8 }],
+        "fd_tell" => &[Fixed { ptr: 1, len: 8 }],
+        "fd_fdstat_get" => &[Fixed { ptr: 1, len: 24 }],
+        "fd_filestat_get" => &[Fixed { ptr: 1, len: 64 }],
+        "path_filestat_get" => &[Fixed { ptr: 4, len: 64 }],
+        "fd_prestat_get" => &[Fixed { ptr: 1, len: 8 }],
+        "fd_prestat_dir_name" => &[Buffer { ptr: 1, len: 2 }],
+        "fd_readdir" => &[
+            Fixed { ptr: 4, len: 4 },
+            Counted {
+                ptr: 1,
+                count: 4,
+                scale: 1,
+            },
+        ],
+        "path_open" => &[Fixed { ptr: 8, len: 4 }],
+        "path_readlink" => &[
+            Fixed { ptr: 5, len: 4 },
+            Counted {
+                ptr: 3,
+                count: 5,
+                scale: 1,
+            },
+        ],
+        "poll_oneoff" => &[
+            Fixed { ptr: 3, len: 4 },
+            // Events of 32 bytes
+            Counted {
+                ptr: 1,
+                count: 3,
+                scale: 32,
+            },
+        ],
+        "sock_accept" => &[Fixed { ptr: 2, len: 4 }],
+        "sock_recv" => &[
+            Fixed { ptr: 4, len: 4 },
+            Fixed { ptr: 5, len: 2 },
+            Iovecs {
+                iovs: 1,
+                iovs_len: 2,
+                nread: 4,
+            },
+        ],
+        "clock_res_get" => &[Fixed { ptr: 1, len: 8 }],
+        "clock_time_get" => &[Fixed { ptr: 2, len: 8 }],
+        "random_get" => &[Buffer { ptr: 0, len: 1 }],
+        "args_sizes_get" | "environ_sizes_get" => {
+            &[Fixed { ptr: 0, len: 4 }, Fixed { ptr: 1, len: 4 }]
+        }
+        "args_get" => &[
+            Sized {
+                ptr: 0,
+                size: Size::Argc,
+                scale: 4,
+            },
+            Sized {
+                ptr: 1,
+                size: Size::ArgvBuf,
+                scale: 1,
+            },
+        ],
+        "environ_get" => &[
+            Sized {
+                ptr: 0,
+                size: Size::EnvironCount,
+                scale: 4,
+            },
+            Sized {
+                ptr: 1,
+                size: Size::EnvironBuf,
+                scale: 1,
+            },
+        ],
+        "fd_advise"
+        | "fd_allocate"
+        | "fd_close"
+        | "fd_datasync"
+        | "fd_fdstat_set_flags"
+        | "fd_fdstat_set_rights"
+        | "fd_filestat_set_size"
+        | "fd_filestat_set_times"
+        | "fd_renumber"
+        | "fd_sync"
+        | "path_create_directory"
+        | "path_filestat_set_times"
+        | "path_link"
+        | "path_remove_directory"
+        | "path_rename"
+        | "path_symlink"
+        | "path_unlink_file"
+        | "proc_exit"
+        | "proc_raise"
+        | "sched_yield"
+        | "sock_shutdown" => &[],
+        _ => return None,
+    })
+}
+
+/// The sizes a `*_sizes_get` call stores at its two pointers.
+fn saved_sizes(name: &str) -> Option<[Size; 2]> {
+    match name {
+        "args_sizes_get" => Some([Size::Argc, Size::ArgvBuf]),
+        "environ_sizes_get" => Some([Size::EnvironCount, Size::EnvironBuf]),
+        _ => None,
+    }
+}
+
+/// Adds a helper for every WASI import with memory side effects. Returns
+/// the helper of each import, called after the call end record with the
+/// arguments and the errno of the call. Fails on unknown preview1 imports
+/// and on imports with memory side effects but an unexpected signature.
+pub(crate) fn add_helpers(
+    module: &mut Module,
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    runtime: &Runtime,
+    func_indices: &HashMap<FunctionId, u32>,
+) -> Result<HashMap<FunctionId, FunctionId>, InstrumentError> {
+    let mut imports: Vec<(FunctionId, String)> = Vec::new();
+    for import in module.imports.iter().filter(|i| i.module == MODULE) {
+        let ImportKind::Function(func) = import.kind else {
+            continue;
+        };
+        let unknown = || InstrumentError::UnknownWasiImport(import.name.clone());
+        let writes = writes(&import.name).ok_or_else(unknown)?;
+        if writes.is_empty() {
+            continue;
+        }
+        let ty = module.types.get(module.funcs.get(func).ty());
+        if ty.results() != [ValType::I32] || !writes.iter().all(|w| params_are_i32(ty.params(), w))
+        {
+            return Err(unknown());
+        }
+        imports.push((func, import.name.clone()));
+    }
+    // WASI writes to the first memory, the one the module exports
+    let Some(memory) = module
+        .memories
+        .iter()
+        .map(|m| m.id())
+        .find(|id| *id != trace_mem_id)
+    else {
+        return Ok(HashMap::new());
+    };
+    if imports.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let sizes = [(); 4].map(|_| {
+        module
+            .globals
+            .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)))
+    });
+    Ok(imports
+        .into_iter()
+        .map(|(func, name)| {
+            let params = module
+                .types
+                .get(module.funcs.get(func).ty())
+                .params()
+                .to_vec();
+            let helper = Helper {
+                memory,
+                trace_mem_id,
+                mem_pointer,
+                sizes,
+                args: params.iter().map(|t| module.locals.add(*t)).collect(),
+                errno: module.locals.add(ValType::I32),
+                write: module.locals.add(ValType::I32),
+                pos: module.locals.add(ValType::I32),
+                count: module.locals.add(ValType::I32),
+                total: module.locals.add(ValType::I32),
+                addr: module.locals.add(ValType::I32),
+                len: module.locals.add(ValType::I32),
+                i: module.locals.add(ValType::I32),
+                remaining: module.locals.add(ValType::I32),
+            };
+            let helper = helper.add(module, &name, &params, func_indices[&func], runtime);
+            (func, helper)
+        })
+        .collect())
+}
+
+fn params_are_i32(params: &[ValType], write: &Write) -> bool {
+    let indices = match *write {
+        Write::Fixed { ptr, .. } | Write::Sized { ptr, .. } => vec![ptr],
+        Write::Counted { ptr, count, .. } => vec![ptr, count],
+        Write::Buffer { ptr, len } => vec![ptr, len],
+        Write::Iovecs {
+            iovs,
+            iovs_len,
+            nread,
+        } => vec![iovs, iovs_len, nread],
+    };
+    indices
+        .into_iter()
+        .all(|i| params.get(i) == Some(&ValType::I32))
+}
+
+/// Locals of a syscall helper.
+struct Helper {
+    memory: MemoryId,
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    sizes: [GlobalId; 4],
+    args: Vec<LocalId>,
+    errno: LocalId,
+    /// Whether the writes are recorded.
+    write: LocalId,
+    /// Where the next write goes in the trace.
+    pos: LocalId,
+    count: LocalId,
+    total: LocalId,
+    addr: LocalId,
+    len: LocalId,
+    i: LocalId,
+    remaining: LocalId,
+}
+
+impl Helper {
+    /// ```wasm
+    /// local.get $errno
+    /// i32.eqz
+    /// local.set $write
+    /// ;; with a ring buffer: measure the record, drop the writes if it is
+    /// ;; longer than the ring and wrap early if it does not fit
+    /// global.get $mem_pointer
+    /// i32.const 13
+    /// i32.add
+    /// local.set $pos
+    /// local.get $write
+    /// if
+    ///     ;; for each write: address, length and the bytes
+    ///     local.get $pos
+    ///     local.get $addr
+    ///     i32.store $trace_mem
+    ///     local.get $pos
+    ///     local.get $len
+    ///     i32.store $trace_mem offset=4
+    ///     local.get $pos
+    ///     i32.const 8
+    ///     i32.add
+    ///     local.get $addr
+    ///     local.get $len
+    ///     memory.copy $trace_mem $memory
+    ///     ;; ...
+    /// end
+    /// ;; code, func idx, errno and the number of writes at $mem_pointer
+    /// local.get $pos
+    /// global.get $mem_pointer
+    /// i32.sub
+    /// call $advance ;; or the inline mem_pointer increment
+    /// ```
+    fn add(
+        &self,
+        module: &mut Module,
+        name: &str,
+        params: &[ValType],
+        func_index: u32,
+        runtime: &Runtime,
+    ) -> FunctionId {
+        let writes = writes(name).unwrap_or_default();
+        let mut builder =
+            FunctionBuilder::new(&mut module.types, &[params, &[ValType::I32]].concat(), &[]);
+        let mut body = builder.func_body();
+        body.local_get(self.errno)
+            .unop(UnaryOp::I32Eqz)
+            .local_set(self.write);
+        if let Some(sizes) = saved_sizes(name) {
+            body.local_get(self.write).if_else(
+                None,
+                |then| {
+                    for (arg, size) in self.args.iter().zip(sizes) {
+                        then.local_get(*arg)
+                            .load(self.memory, LoadKind::I32 { atomic: false }, mem_arg(0))
+                            .global_set(self.sizes[size as usize]);
+                    }
+                },
+                |_| {},
+            );
+        }
+        if let Some(size) = runtime.ring_size() {
+            body.i32_const(HEADER_LEN).local_set(self.total);
+            body.local_get(self.write).if_else(
+                None,
+                |then| self.writes(then, writes, true),
+                |_| {},
+            );
+            body.local_get(self.total)
+                .i32_const(size as i32 - 2)
+                .binop(BinaryOp::I32GtU)
+                .if_else(
+                    None,
+                    |then| {
+                        then.i32_const(0)
+                            .local_set(self.write)
+                            .i32_const(HEADER_LEN)
+                            .local_set(self.total);
+                    },
+                    |_| {},
+                );
+            runtime.reserve(&mut body, self.mem_pointer, self.total);
+        }
+        body.global_get(self.mem_pointer)
+            .i32_const(HEADER_LEN)
+            .binop(BinaryOp::I32Add)
+            .local_set(self.pos)
+            .i32_const(0)
+            .local_set(self.count);
+        body.local_get(self.write)
+            .if_else(None, |then| self.writes(then, writes, false), |_| {});
+        let i32_store = StoreKind::I32 { atomic: false };
+        body.global_get(self.mem_pointer)
+            .i32_const(CODE)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32_8 { atomic: false },
+                MemArg {
+                    align: 1,
+                    offset: 0,
+                },
+            )
+            .global_get(self.mem_pointer)
+            .i32_const(func_index as i32)
+            .store(self.trace_mem_id, i32_store, mem_arg(1))
+            .global_get(self.mem_pointer)
+            .local_get(self.errno)
+            .store(self.trace_mem_id, i32_store, mem_arg(5))
+            .global_get(self.mem_pointer)
+            .local_get(self.count)
+            .store(self.trace_mem_id, i32_store, mem_arg(9));
+        body.local_get(self.pos)
+            .global_get(self.mem_pointer)
+            .binop(BinaryOp::I32Sub);
+        runtime.commit(&mut body, self.mem_pointer);
+        let args = [self.args.as_slice(), &[self.errno]].concat();
+        builder.finish(args, &mut module.funcs)
+    }
+
+    /// Records the writes, or only adds up their length in `$total` when
+    /// measuring.
+    fn writes(&self, body: &mut InstrSeqBuilder, writes: &[Write], measure: bool) {
+        for write in writes {
+            match *write {
+                Write::Fixed { ptr, len } => {
+                    body.local_get(self.args[ptr])
+                        .local_set(self.addr)
+                        .i32_const(len as i32)
+                        .local_set(self.len);
+                    self.write(body, measure);
+                }
+                Write::Buffer { ptr, len } => {
+                    body.local_get(self.args[ptr])
+                        .local_set(self.addr)
+                        .local_get(self.args[len])
+                        .local_set(self.len);
+                    self.write(body, measure);
+                }
+                Write::Sized { ptr, size, scale } => {
+                    body.local_get(self.args[ptr])
+                        .local_set(self.addr)
+                        .global_get(self.sizes[size as usize])
+                        .i32_const(scale as i32)
+                        .binop(BinaryOp::I32Mul)
+                        .local_set(self.len);
+                    self.write(body, measure);
+                }
+                Write::Counted { ptr, count, scale } => {
+                    body.local_get(self.args[ptr])
+                        .local_set(self.addr)
+                        .local_get(self.args[count])
+                        .load(self.memory, LoadKind::I32 { atomic: false }, mem_arg(0))
+                        .i32_const(scale as i32)
+                        .binop(BinaryOp::I32Mul)
+                        .local_set(self.len);
+                    self.write(body, measure);
+                }
+                Write::Iovecs {
+                    iovs,
+                    iovs_len,
+                    nread,
+                } => self.iovecs(body, iovs, iovs_len, nread, measure),
+            }
+        }
+    }
+
+    /// ```wasm
+    /// local.get $nread
+    /// i32.load $memory
+    /// local.set $remaining
+    /// i32.const 0
+    /// local.set $i
+    /// block $done
+    ///     loop $next
+    ///         local.get $i
+    ///         local.get $iovs_len
+    ///         i32.ge_u
+    ///         local.get $remaining
+    ///         i32.eqz
+    ///         i32.or
+    ///         br_if $done
+    ///         ;; $addr and $len of iovec $i, $len capped at $remaining
+    ///         ;; record the write
+    ///         ;; subtract $len from $remaining, increment $i
+    ///         br $next
+    ///     end
+    /// end
+    /// ```
+    fn iovecs(
+        &self,
+        body: &mut InstrSeqBuilder,
+        iovs: usize,
+        iovs_len: usize,
+        nread: usize,
+        measure: bool,
+    ) {
+        body.local_get(self.args[nread])
+            .load(self.memory, LoadKind::I32 { atomic: false }, mem_arg(0))
+            .local_set(self.remaining)
+            .i32_const(0)
+            .local_set(self.i);
+        body.block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |next| {
+                let next_id = next.id();
+                next.local_get(self.i)
+                    .local_get(self.args[iovs_len])
+                    .binop(BinaryOp::I32GeU)
+                    .local_get(self.remaining)
+                    .unop(UnaryOp::I32Eqz)
+                    .binop(BinaryOp::I32Or)
+                    .br_if(done_id);
+                // iovec $i is { buf: u32, buf_len: u32 }
+                next.local_get(self.args[iovs])
+                    .local_get(self.i)
+                    .i32_const(8)
+                    .binop(BinaryOp::I32Mul)
+                    .binop(BinaryOp::I32Add)
+                    .local_tee(self.addr)
+                    .load(self.memory, LoadKind::I32 { atomic: false }, mem_arg(4))
+                    .local_set(self.len)
+                    .local_get(self.addr)
+                    .load(self.memory, LoadKind::I32 { atomic: false }, mem_arg(0))
+                    .local_set(self.addr)
+                    .local_get(self.len)
+                    .local_get(self.remaining)
+                    .local_get(self.len)
+                    .local_get(self.remaining)
+                    .binop(BinaryOp::I32LtU)
+                    .select(None)
+                    .local_set(self.len);
+                self.write(next, measure);
+                next.local_get(self.remaining)
+                    .local_get(self.len)
+                    .binop(BinaryOp::I32Sub)
+                    .local_set(self.remaining)
+                    .local_get(self.i)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(self.i)
+                    .br(next_id);
+            });
+        });
+    }
+
+    /// Records the `$len` bytes at `$addr`, or adds the length of the write
+    /// to `$total` when measuring.
+    fn write(&self, body: &mut InstrSeqBuilder, measure: bool) {
+        if measure {
+            body.local_get(self.total)
+                .local_get(self.len)
+                .binop(BinaryOp::I32Add)
+                .i32_const(WRITE_HEADER_LEN)
+                .binop(BinaryOp::I32Add)
+                .local_set(self.total);
+            return;
+        }
+        body.local_get(self.pos)
+            .local_get(self.addr)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                mem_arg(0),
+            )
+            .local_get(self.pos)
+            .local_get(self.len)
+            .store(
+                self.trace_mem_id,
+                StoreKind::I32 { atomic: false },
+                mem_arg(4),
+            )
+            .local_get(self.pos)
+            .i32_const(WRITE_HEADER_LEN)
+            .binop(BinaryOp::I32Add)
+            .local_get(self.addr)
+            .local_get(self.len)
+            .memory_copy(self.memory, self.trace_mem_id)
+            .local_get(self.pos)
+            .local_get(self.len)
+            .binop(BinaryOp::I32Add)
+            .i32_const(WRITE_HEADER_LEN)
+            .binop(BinaryOp::I32Add)
+            .local_set(self.pos)
+            .local_get(self.count)
+            .i32_const(1)
+            .binop(BinaryOp::I32Add)
+            .local_set(self.count);
+    }
+}
+
+fn mem_arg(offset: u32) -> MemArg {
+    MemArg { align: 4, offset }
+}
@@ -0,0 +1,270 @@
+//! Watchpoints: only the loads and stores that access a watched address
+//! range are recorded.
+//!
+//! The ranges are `(start, end)` pairs of i32, the end excluded, in a memory
+//! of their own. Memory records are written as usual, then
+//! `advance_watched(len, size)` only commits the ones whose access,
+//! `size` bytes from the recorded effective address, overlaps one of the
+//! ranges. The host adds ranges with the
+//! exported `r3_watch(start, end)`, which returns the number of ranges or -1
+//! when the table is full, and removes all of them with `r3_unwatch_all`.
+
+use std::ops::Range;
+
+use walrus::{
+    ir::{BinaryOp, LoadKind, MemArg, StoreKind, UnaryOp, Value},
+    ActiveData, ActiveDataLocation, DataKind, FunctionBuilder, FunctionId, GlobalId, InitExpr,
+    MemoryId, Module, ValType,
+};
+
+use crate::{runtime::Runtime, InstrumentError};
+
+const RANGE_LEN: u32 = 8;
+/// Ranges the table holds, one page of them.
+const MAX_RANGES: u32 = 64 * 1024 / RANGE_LEN;
+
+/// Adds the range table initialized with `ranges`, the exported functions
+/// that change it and `advance_watched`, which it returns. Fails when the
+/// table cannot hold `ranges`. `addr_offset` is the offset of the address
+/// in the memory records.
+pub(crate) fn add(
+    module: &mut Module,
+    ranges: &[Range<u32>],
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    runtime: &Runtime,
+    addr_offset: u32,
+) -> Result<FunctionId, InstrumentError> {
+    if ranges.len() > MAX_RANGES as usize {
+        return Err(InstrumentError::TooManyWatchRanges {
+            count: ranges.len(),
+            max: MAX_RANGES,
+        });
+    }
+    let table = module.memories.add_local(false, 1, None);
+    if !ranges.is_empty() {
+        let bytes = ranges
+            .iter()
+            .flat_map(|range| [range.start.to_le_bytes(), range.end.to_le_bytes()])
+            .flatten()
+            .collect();
+        module.data.add(
+            DataKind::Active(ActiveData {
+                memory: table,
+                location: ActiveDataLocation::Absolute(0),
+            }),
+            bytes,
+        );
+    }
+    let count = module.globals.add_local(
+        ValType::I32,
+        true,
+        InitExpr::Value(Value::I32(ranges.len() as i32)),
+    );
+    add_watch(module, table, count);
+    add_unwatch_all(module, count);
+    Ok(add_advance_watched(
+        module,
+        trace_mem_id,
+        mem_pointer,
+        runtime,
+        table,
+        count,
+        addr_offset,
+    ))
+}
+
+/// Adds and exports `r3_watch(start, end) -> i32`.
+///
+/// ```wasm
+/// global.get $count
+/// i32.const ;; max ranges
+/// i32.ge_u
+/// if
+///     i32.const -1
+///     return
+/// end
+/// global.get $count
+/// i32.const 3
+/// i32.shl
+/// local.get $start
+/// i32.store $table
+/// ;; the same for $end at offset=4
+/// global.get $count
+/// i32.const 1
+/// i32.add
+/// global.set $count
+/// global.get $count
+/// ```
+fn add_watch(module: &mut Module, table: MemoryId, count: GlobalId) {
+    let [start, end] = [(); 2].map(|_| module.locals.add(ValType::I32));
+    let mut builder = FunctionBuilder::new(
+        &mut module.types,
+        &[ValType::I32, ValType::I32],
+        &[ValType::I32],
+    );
+    let mut body = builder.func_body();
+    body.global_get(count)
+        .i32_const(MAX_RANGES as i32)
+        .binop(BinaryOp::I32GeU)
+        .if_else(
+            None,
+            |then| {
+                then.i32_const(-1).return_();
+            },
+            |_| {},
+        );
+    for (local, offset) in [(start, 0), (end, 4)] {
+        body.global_get(count)
+            .i32_const(3)
+            .binop(BinaryOp::I32Shl)
+            .local_get(local)
+            .store(
+                table,
+                StoreKind::I32 { atomic: false },
+                MemArg { align: 4, offset },
+            );
+    }
+    body.global_get(count)
+        .i32_const(1)
+        .binop(BinaryOp::I32Add)
+        .global_set(count)
+        .global_get(count);
+    let watch = builder.finish(vec![start, end], &mut module.funcs);
+    module.exports.add("r3_watch", watch);
+}
+
+/// Adds and exports `r3_unwatch_all()`, which sets `$count` to 0.
+fn add_unwatch_all(module: &mut Module, count: GlobalId) {
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().i32_const(0).global_set(count);
+    let unwatch_all = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("r3_unwatch_all", unwatch_all);
+}
+
+/// Adds the `advance_watched(len, size)` helper used by memory
+/// records. The end of the access is computed in i64, so an access that
+/// wraps around the address space does not overlap ranges at the start.
+///
+/// ```wasm
+/// global.get $mem_pointer
+/// i32.load $trace_mem offset=1 ;; the recorded address, offset=6 behind a site
+/// local.set $addr
+/// block $done
+///     loop $next
+///         ;; br_if $done after $count ranges
+///         local.get $addr
+///         local.get $i
+///         i32.load $table offset=4
+///         i32.lt_u
+///         local.get $i
+///         i32.load $table
+///         i64.extend_i32_u
+///         local.get $addr
+///         i64.extend_i32_u
+///         local.get $size
+///         i64.extend_i32_u
+///         i64.add
+///         i64.lt_u
+///         i32.and
+///         if
+///             local.get $len
+///             call $advance_sampled ;; or $advance or the inline mem_pointer increment
+///             return
+///         end
+///         ;; advance $i by 8
+///         br $next
+///     end
+/// end
+/// ```
+fn add_advance_watched(
+    module: &mut Module,
+    trace_mem_id: MemoryId,
+    mem_pointer: GlobalId,
+    runtime: &Runtime,
+    table: MemoryId,
+    count: GlobalId,
+    addr_offset: u32,
+) -> FunctionId {
+    let [len, size, addr, i] = [(); 4].map(|_| module.locals.add(ValType::I32));
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32, ValType::I32], &[]);
+    let mut body = builder.func_body();
+    let i32_load = LoadKind::I32 { atomic: false };
+    body.global_get(mem_pointer)
+        .load(
+            trace_mem_id,
+            i32_load,
+            MemArg {
+                align: 1,
+                offset: addr_offset,
+            },
+        )
+        .local_set(addr)
+        .i32_const(0)
+        .local_set(i);
+    let advance = runtime.advance_sampled.or(runtime.advance);
+    body.block(None, |done| {
+        let done_id = done.id();
+        done.loop_(None, |next| {
+            let next_id = next.id();
+            next.local_get(i)
+                .global_get(count)
+                .i32_const(3)
+                .binop(BinaryOp::I32Shl)
+                .binop(BinaryOp::I32GeU)
+                .br_if(done_id)
+                .local_get(addr)
+                .local_get(i)
+                .load(
+                    table,
+                    i32_load,
+                    MemArg {
+                        align: 4,
+                        offset: 4,
+                    },
+                )
+                .binop(BinaryOp::I32LtU)
+                .local_get(i)
+                .load(
+                    table,
+                    i32_load,
+                    MemArg {
+                        align: 4,
+                        offset: 0,
+                    },
+                )
+                .unop(UnaryOp::I64ExtendUI32)
+                .local_get(addr)
+                .unop(UnaryOp::I64ExtendUI32)
+                .local_get(size)
+                .unop(UnaryOp::I64ExtendUI32)
+                .binop(BinaryOp::I64Add)
+                .binop(BinaryOp::I64LtU)
+                .binop(BinaryOp::I32And)
+                .if_else(
+                    None,
+                    |then| {
+                        match advance {
+                            Some(advance) => {
+                                then.local_get(len).call(advance);
+                            }
+                            None => {
+                                then.global_get(mem_pointer)
+                                    .local_get(len)
+                                    .binop(BinaryOp::I32Add)
+                                    .global_set(mem_pointer);
+                            }
+                        }
+                        then.return_();
+                    },
+                    |_| {},
+                )
+                .local_get(i)
+                .i32_const(RANGE_LEN as i32)
+                .binop(BinaryOp::I32Add)
+                .local_set(i)
+                .br(next_id);
+        });
+    });
+    builder.finish(vec![len, size], &mut module.funcs)
+}
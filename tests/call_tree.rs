@@ -0,0 +1,206 @@
+//! Call trees of hand written event sequences.
+
+use r3_tracer::{
+    call_tree::{CallTree, FrameKind, Issue},
+    decode::Event,
+};
+
+fn export_call(export: u32) -> Event {
+    Event::ExportCall {
+        export,
+        args: vec![],
+    }
+}
+
+fn export_return(export: u32) -> Event {
+    Event::ExportReturn {
+        export,
+        results: vec![],
+    }
+}
+
+fn enter(func: u32) -> Event {
+    Event::FunctionEntry {
+        func,
+        params: vec![],
+    }
+}
+
+fn ret(func: u32) -> Event {
+    Event::Return {
+        func,
+        results: vec![],
+    }
+}
+
+fn call(func: u32) -> Event {
+    Event::Call { func, args: vec![] }
+}
+
+fn import_call(func: u32) -> Event {
+    Event::ImportCall { func, args: vec![] }
+}
+
+fn call_end() -> Event {
+    Event::CallEnd {
+        type_index: 0,
+        results: vec![],
+    }
+}
+
+#[test]
+fn nests_host_and_wasm_frames() {
+    let tree = CallTree::new(&[
+        export_call(0),
+        enter(2),
+        import_call(0),
+        export_call(1),
+        enter(3),
+        call(2),
+        enter(2),
+        ret(2),
+        call_end(),
+        ret(3),
+        export_return(1),
+        call_end(),
+        ret(2),
+        export_return(0),
+    ]);
+    assert_eq!(tree.issues, []);
+    assert_eq!(
+        tree.to_string(),
+        "\
+export 0 @0..13
+  function 2 @1..12
+    host import 0 @2..11
+      export 1 @3..10
+        function 3 @4..9
+          call 2 @5..8
+            function 2 @6..7
+"
+    );
+    let host: Vec<_> = tree
+        .frames()
+        .iter()
+        .filter(|(_, f)| f.kind.is_host())
+        .map(|(depth, _)| *depth)
+        .collect();
+    assert_eq!(host, [2]);
+}
+
+#[test]
+fn unwinds_the_frames_of_a_trap() {
+    // The first call traps inside function 3, then the host calls again
+    let tree = CallTree::new(&[
+        export_call(0),
+        enter(2),
+        call(3),
+        enter(3),
+        export_call(0),
+        enter(2),
+        ret(2),
+        export_return(0),
+    ]);
+    assert_eq!(tree.roots.len(), 2);
+    assert_eq!(tree.roots[0].end, None);
+    assert_eq!(tree.roots[1].end, Some(7));
+    assert_eq!(
+        tree.issues,
+        [
+            Issue::Unterminated {
+                kind: FrameKind::Function { func: 3 },
+                start: 3,
+                at: Some(4),
+            },
+            Issue::Unterminated {
+                kind: FrameKind::Call { func: 3 },
+                start: 2,
+                at: Some(4),
+            },
+            Issue::Unterminated {
+                kind: FrameKind::Function { func: 2 },
+                start: 1,
+                at: Some(4),
+            },
+            Issue::Unterminated {
+                kind: FrameKind::Export { export: 0 },
+                start: 0,
+                at: Some(4),
+            },
+        ]
+    );
+}
+
+#[test]
+fn unwinds_at_a_trap_event() {
+    let tree = CallTree::new(&[
+        export_call(0),
+        enter(2),
+        Event::Trap {
+            depth: 1,
+            frames: vec![],
+        },
+    ]);
+    assert_eq!(tree.roots[0].end, None);
+    assert_eq!(
+        tree.issues,
+        [
+            Issue::Unterminated {
+                kind: FrameKind::Function { func: 2 },
+                start: 1,
+                at: Some(2),
+            },
+            Issue::Unterminated {
+                kind: FrameKind::Export { export: 0 },
+                start: 0,
+                at: Some(2),
+            },
+        ]
+    );
+}
+
+#[test]
+fn unwinds_a_trapping_callback_at_the_end_of_the_import() {
+    // The host catches the trap of export 1 and returns from the import
+    let tree = CallTree::new(&[
+        export_call(0),
+        enter(2),
+        import_call(0),
+        export_call(1),
+        enter(3),
+        call_end(),
+        ret(2),
+        export_return(0),
+    ]);
+    assert_eq!(
+        tree.issues
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>(),
+        [
+            "function 3 entered at event 4 was left at event 5 without returning",
+            "export 1 entered at event 3 was left at event 5 without returning",
+        ]
+    );
+    assert_eq!(tree.roots[0].end, Some(7));
+}
+
+#[test]
+fn reports_unmatched_and_unterminated_frames() {
+    // A ring buffer that starts inside function 1 and ends inside function 4
+    let tree = CallTree::new(&[ret(1), call_end(), enter(4), call(5), enter(6)]);
+    assert_eq!(
+        tree.issues
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>(),
+        [
+            "event 0 closes a frame that was never opened",
+            "event 1 closes a frame that was never opened",
+            "event 4 enters function 6 but function 5 was called",
+            "function 6 entered at event 4 has not returned when the trace ends",
+            "call 5 entered at event 3 has not returned when the trace ends",
+            "function 4 entered at event 2 has not returned when the trace ends",
+        ]
+    );
+}
@@ -0,0 +1,108 @@
+//! Checks on modules generated by `wasm-smith`, shared by the `generated`
+//! test and the cargo-fuzz target in `fuzz/`.
+
+use arbitrary::Unstructured;
+use r3_tracer::{decode::Decoder, instrument_wasm_with_options, InstrumentError, Options};
+use wasm_smith::Config;
+use wasmi::{ExternType, Val};
+
+use super::{show, Runner, EXHAUSTED};
+
+/// Instructions the original module may execute per instantiation.
+const FUEL: u64 = 100_000;
+/// The instrumented module executes many instructions per event.
+const INSTRUMENTED_FUEL: u64 = 100 * FUEL;
+
+/// Features the tracer and its walrus version can read.
+pub fn config() -> Config {
+    Config {
+        // Stubbed by the runner
+        max_imports: 4,
+        min_funcs: 3,
+        max_memories: 1,
+        // Memories are copied to compare them after every call
+        max_memory32_bytes: 1 << 20,
+        export_everything: true,
+        exceptions_enabled: false,
+        gc_enabled: false,
+        memory64_enabled: false,
+        relaxed_simd_enabled: false,
+        simd_enabled: false,
+        tail_call_enabled: false,
+        threads_enabled: false,
+        wide_arithmetic_enabled: false,
+        extended_const_enabled: false,
+        compact_imports_enabled: false,
+        ..Config::default()
+    }
+}
+
+/// Generates a module from `data`, instruments it and checks that
+///
+/// - instrumenting does not panic and the result validates,
+/// - every export behaves like the original: same results or trap, same
+///   exported globals and memories,
+/// - the recorded trace decodes.
+pub fn check(data: &[u8]) {
+    let Ok(module) = wasm_smith::Module::new(config(), &mut Unstructured::new(data)) else {
+        return;
+    };
+    let original = module.to_bytes();
+    let options = Options {
+        validate: true,
+        trap_info: true,
+        snapshot_memory: true,
+        checkpoint_every: Some(3),
+        ..Options::default()
+    };
+    let instrumented = match instrument_wasm_with_options(&original, &options) {
+        Ok(mut module) => module.emit_wasm(),
+        // Features walrus cannot parse and values the tracer cannot record
+        Err(InstrumentError::Parse(_) | InstrumentError::Unsupported { .. }) => return,
+        Err(e) => panic!("{}", e),
+    };
+    let Ok(mut expected) = Runner::with_fuel(&original, Some(FUEL)) else {
+        return;
+    };
+    let mut actual = Runner::with_fuel(&instrumented, Some(INSTRUMENTED_FUEL))
+        .unwrap_or_else(|e| panic!("instrumented module failed to start: {}", e));
+    let exports = expected.exports();
+    for (name, ty) in &exports {
+        let ExternType::Func(func) = ty else {
+            continue;
+        };
+        let args: Vec<Val> = func.params().iter().map(|t| Val::default(*t)).collect();
+        let result = expected.call(name, &args);
+        let actual_result = actual.call(name, &args);
+        // Either one running out of fuel or stack is not compared
+        if [&result, &actual_result]
+            .iter()
+            .any(|r| r.as_ref().is_err_and(|e| e == EXHAUSTED))
+        {
+            return;
+        }
+        assert_eq!(result, actual_result, "results of {}", name);
+        for (name, ty) in &exports {
+            match ty {
+                ExternType::Global(_) => assert_eq!(
+                    expected.global(name).map(|v| show(&v)),
+                    actual.global(name).map(|v| show(&v)),
+                    "global {}",
+                    name
+                ),
+                ExternType::Memory(_) => {
+                    assert!(
+                        expected.memory(name) == actual.memory(name),
+                        "memory {}",
+                        name
+                    )
+                }
+                _ => {}
+            }
+        }
+    }
+    Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(actual.trace())
+        .unwrap();
+}
@@ -0,0 +1,357 @@
+//! Runs modules in an embedded interpreter, so the instrumented module can be
+//! compared with the original.
+
+#![allow(dead_code)]
+
+pub mod generated;
+
+use std::collections::HashSet;
+
+use wasmi::{
+    core::{TrapCode, ValType},
+    Caller, Config, Engine, Extern, ExternType, Func, Global, Instance, Linker, Memory, Module,
+    Store, Table, Val,
+};
+
+/// Trap message of calls that ran out of fuel or stack. The instrumented
+/// module needs more of both, so these are not compared.
+pub const EXHAUSTED: &str = "exhausted";
+/// Module of the imports the tests link to host functions.
+const HOST: &str = "host";
+
+/// An instance of a module with a stub `r3.check_mem` that collects the
+/// flushed trace, deterministic stubs of some WASI functions, `host.disable`
+/// and stubs of any other import.
+pub struct Runner {
+    store: Store<Vec<u8>>,
+    instance: Instance,
+}
+
+impl Runner {
+    pub fn new(wasm: &[u8]) -> Result<Self, String> {
+        Self::with_fuel(wasm, None)
+    }
+
+    /// Limits the number of instructions the instance may execute.
+    pub fn with_fuel(wasm: &[u8], fuel: Option<u64>) -> Result<Self, String> {
+        let mut config = Config::default();
+        config.wasm_multi_memory(true);
+        config.consume_fuel(fuel.is_some());
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|e| e.to_string())?;
+        let mut store = Store::new(&engine, Vec::new());
+        if let Some(fuel) = fuel {
+            store.set_fuel(fuel).unwrap();
+        }
+        let mut linker = Linker::<Vec<u8>>::new(&engine);
+        linker
+            .func_wrap("r3", "check_mem", |mut caller: Caller<'_, Vec<u8>>| {
+                let len = match caller.get_export("trace_byte_length") {
+                    Some(Extern::Global(g)) => g.get(&caller).i32().unwrap() as usize,
+                    _ => panic!("trace_byte_length is not exported"),
+                };
+                let chunk = match caller.get_export("trace") {
+                    Some(Extern::Memory(m)) => m.data(&caller)[..len].to_vec(),
+                    _ => panic!("trace is not exported"),
+                };
+                caller.data_mut().extend(chunk);
+            })
+            .unwrap();
+        // Calls back into the instrumented module
+        linker
+            .func_wrap(HOST, "disable", |mut caller: Caller<'_, Vec<u8>>| {
+                if let Some(Extern::Func(disable)) = caller.get_export("r3_disable") {
+                    disable.call(&mut caller, &[], &mut []).unwrap();
+                }
+            })
+            .unwrap();
+        add_wasi_stubs(&mut linker);
+        add_import_stubs(&mut linker, &mut store, &module);
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(error_message)?;
+        Ok(Self { store, instance })
+    }
+
+    /// Calls an export, a trap is returned as its message.
+    pub fn call(&mut self, name: &str, args: &[Val]) -> Result<Vec<String>, String> {
+        let func = self
+            .instance
+            .get_func(&self.store, name)
+            .ok_or_else(|| format!("no function {}", name))?;
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+        func.call(&mut self.store, args, &mut results)
+            .map_err(error_message)?;
+        Ok(results.iter().map(show).collect())
+    }
+
+    /// Names and types of the exports.
+    pub fn exports(&self) -> Vec<(String, ExternType)> {
+        self.instance
+            .exports(&self.store)
+            .map(|e| (e.name().to_string(), e.into_extern().ty(&self.store)))
+            .collect()
+    }
+
+    pub fn memory(&self, name: &str) -> Option<Vec<u8>> {
+        self.instance
+            .get_memory(&self.store, name)
+            .map(|m| m.data(&self.store).to_vec())
+    }
+
+    pub fn global(&self, name: &str) -> Option<Val> {
+        self.instance
+            .get_global(&self.store, name)
+            .map(|g| g.get(&self.store))
+    }
+
+    pub fn global_u32(&self, name: &str) -> u32 {
+        self.global(name).and_then(|v| v.i32()).unwrap() as u32
+    }
+
+    /// The chunks passed to `r3.check_mem` so far.
+    pub fn trace(&self) -> &[u8] {
+        self.store.data()
+    }
+}
+
+/// Arguments passed by the `args_get` stub.
+pub const ARGS: [&str; 2] = ["prog", "-v"];
+/// Bytes returned by the `fd_read` stub.
+pub const INPUT: &[u8] = b"hello";
+/// Time returned by the `clock_time_get` stub.
+pub const TIME: u64 = 1_700_000_000_000_000_000;
+/// Most bytes the `fd_write` stub writes in one call, so callers loop on
+/// `nwritten`.
+pub const MAX_WRITE: u32 = 4;
+/// Name of the directory preopened as fd 3.
+pub const PREOPEN: &[u8] = b"/tmp";
+/// Directory entries returned by the `fd_readdir` stub.
+pub const DIRENT: &[u8] = b"dirent bytes";
+
+const WASI: &str = "wasi_snapshot_preview1";
+
+/// WASI functions with memory side effects, returning the same on every
+/// run: the original and the instrumented module have to behave the same.
+fn add_wasi_stubs(linker: &mut Linker<Vec<u8>>) {
+    fn write(caller: &mut Caller<'_, Vec<u8>>, addr: i32, bytes: &[u8]) {
+        let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+            panic!("memory is not exported");
+        };
+        memory.write(caller, addr as u32 as usize, bytes).unwrap();
+    }
+    fn read_u32(caller: &mut Caller<'_, Vec<u8>>, addr: i32) -> u32 {
+        let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+            panic!("memory is not exported");
+        };
+        let mut bytes = [0; 4];
+        memory
+            .read(&caller, addr as u32 as usize, &mut bytes)
+            .unwrap();
+        u32::from_le_bytes(bytes)
+    }
+    linker
+        .func_wrap(
+            WASI,
+            "args_sizes_get",
+            |mut caller: Caller<'_, Vec<u8>>, argc: i32, buf_size: i32| {
+                let size: usize = ARGS.iter().map(|a| a.len() + 1).sum();
+                write(&mut caller, argc, &(ARGS.len() as u32).to_le_bytes());
+                write(&mut caller, buf_size, &(size as u32).to_le_bytes());
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "args_get",
+            |mut caller: Caller<'_, Vec<u8>>, argv: i32, buf: i32| {
+                let mut pos = buf;
+                for (i, arg) in ARGS.iter().enumerate() {
+                    write(&mut caller, argv + 4 * i as i32, &pos.to_le_bytes());
+                    write(&mut caller, pos, &[arg.as_bytes(), &[0]].concat());
+                    pos += arg.len() as i32 + 1;
+                }
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "clock_time_get",
+            |mut caller: Caller<'_, Vec<u8>>, _id: i32, _precision: i64, time: i32| {
+                write(&mut caller, time, &TIME.to_le_bytes());
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "random_get",
+            |mut caller: Caller<'_, Vec<u8>>, buf: i32, len: i32| {
+                let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                write(&mut caller, buf, &bytes);
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "fd_read",
+            |mut caller: Caller<'_, Vec<u8>>, fd: i32, iovs: i32, iovs_len: i32, nread: i32| {
+                if fd != 0 {
+                    // EBADF
+                    return 8;
+                }
+                let mut input = INPUT;
+                for i in 0..iovs_len {
+                    let buf = read_u32(&mut caller, iovs + 8 * i) as i32;
+                    let len = read_u32(&mut caller, iovs + 8 * i + 4) as usize;
+                    let (chunk, rest) = input.split_at(len.min(input.len()));
+                    write(&mut caller, buf, chunk);
+                    input = rest;
+                }
+                write(
+                    &mut caller,
+                    nread,
+                    &((INPUT.len() - input.len()) as u32).to_le_bytes(),
+                );
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "fd_write",
+            |mut caller: Caller<'_, Vec<u8>>, _fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| {
+                let total: u32 = (0..iovs_len)
+                    .map(|i| read_u32(&mut caller, iovs + 8 * i + 4))
+                    .sum();
+                write(&mut caller, nwritten, &total.min(MAX_WRITE).to_le_bytes());
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "fd_seek",
+            |mut caller: Caller<'_, Vec<u8>>, _fd: i32, offset: i64, _whence: i32, new: i32| {
+                write(&mut caller, new, &(offset as u64 + 100).to_le_bytes());
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "fd_prestat_get",
+            |mut caller: Caller<'_, Vec<u8>>, fd: i32, buf: i32| {
+                if fd != 3 {
+                    // EBADF
+                    return 8;
+                }
+                let len = PREOPEN.len() as u32;
+                write(&mut caller, buf, &[[0; 4], len.to_le_bytes()].concat());
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "fd_prestat_dir_name",
+            |mut caller: Caller<'_, Vec<u8>>, _fd: i32, path: i32, _len: i32| {
+                write(&mut caller, path, PREOPEN);
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            WASI,
+            "fd_readdir",
+            |mut caller: Caller<'_, Vec<u8>>,
+             _fd: i32,
+             buf: i32,
+             len: i32,
+             _cookie: i64,
+             used: i32| {
+                let bytes = &DIRENT[..DIRENT.len().min(len as usize)];
+                write(&mut caller, buf, bytes);
+                write(&mut caller, used, &(bytes.len() as u32).to_le_bytes());
+                0
+            },
+        )
+        .unwrap()
+        .func_wrap(WASI, "fd_close", |_: Caller<'_, Vec<u8>>, _fd: i32| 0)
+        .unwrap();
+}
+
+/// Defines the imports not linked yet, like the ones of generated modules:
+/// functions return zeros, globals, memories and tables are created empty.
+fn add_import_stubs(linker: &mut Linker<Vec<u8>>, store: &mut Store<Vec<u8>>, module: &Module) {
+    let mut stubbed = HashSet::new();
+    for import in module.imports() {
+        let (module, name) = (import.module(), import.name());
+        // Linked above, or imported twice: one stub has to do
+        if module == "r3" || module == WASI || module == HOST || !stubbed.insert((module, name)) {
+            continue;
+        }
+        let stub: Extern = match import.ty() {
+            ExternType::Func(ty) => {
+                let results: Vec<ValType> = ty.results().to_vec();
+                Func::new(&mut *store, ty.clone(), move |_, _, out| {
+                    for (out, ty) in out.iter_mut().zip(&results) {
+                        *out = Val::default(*ty);
+                    }
+                    Ok(())
+                })
+                .into()
+            }
+            ExternType::Global(ty) => {
+                Global::new(&mut *store, Val::default(ty.content()), ty.mutability()).into()
+            }
+            ExternType::Memory(ty) => Memory::new(&mut *store, *ty).unwrap().into(),
+            ExternType::Table(ty) => Table::new(&mut *store, *ty, Val::default(ty.element()))
+                .unwrap()
+                .into(),
+        };
+        linker.define(module, name, stub).unwrap();
+    }
+}
+
+fn error_message(error: wasmi::Error) -> String {
+    match error.as_trap_code() {
+        Some(TrapCode::OutOfFuel | TrapCode::StackOverflow) => EXHAUSTED.to_string(),
+        _ => error.to_string(),
+    }
+}
+
+/// A value for comparison. References differ between instances, only
+/// whether they are null is compared.
+pub fn show(value: &Val) -> String {
+    match value {
+        Val::FuncRef(r) => format!("funcref null={}", r.is_null()),
+        Val::ExternRef(r) => format!("externref null={}", r.is_null()),
+        value => format!("{:?}", value),
+    }
+}
+
+/// Calls the export in the original and the instrumented module and checks
+/// that both return the same and leave the same exported `memory`. Returns
+/// the runner of the instrumented module.
+pub fn run_both(original: &[u8], instrumented: &[u8], calls: &[(&str, &[Val])]) -> Runner {
+    let mut expected = Runner::new(original).unwrap();
+    let mut actual = Runner::new(instrumented).unwrap();
+    for (name, args) in calls {
+        assert_eq!(
+            expected.call(name, args),
+            actual.call(name, args),
+            "results of {}",
+            name
+        );
+    }
+    assert!(
+        expected.memory("memory") == actual.memory("memory"),
+        "memory differs"
+    );
+    actual
+}
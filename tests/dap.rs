@@ -0,0 +1,428 @@
+//! Debug sessions on hand written traces.
+
+use std::io::{Cursor, Write};
+
+use gimli::{
+    write::{Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections},
+    Encoding, Format, LineEncoding, LittleEndian,
+};
+use r3_tracer::{
+    dap::Session,
+    decode::{Event, TrapFrame, Value},
+    dwarf::SourceMap,
+};
+use serde_json::{json, Value as Json};
+use wasmparser::{Parser, Payload};
+
+const WAT: &str = r#"
+(module
+  (import "env" "log" (func $log (param i32)))
+  (memory 1)
+  (func $inner (param i32) (result i32)
+    (i32.store (local.get 0) (i32.const 5))
+    (i32.load (local.get 0)))
+  (func (export "main") (result i32)
+    (call $log (i32.const 1))
+    (call $inner (i32.const 16))))
+"#;
+
+/// `main` logs and calls `inner`, which stores 5 at 16 and loads it.
+fn events() -> Vec<Event> {
+    vec![
+        Event::ExportCall {
+            export: 0,
+            args: vec![],
+        },
+        Event::FunctionEntry {
+            func: 2,
+            params: vec![],
+        },
+        Event::ImportCall {
+            func: 0,
+            args: vec![Value::I32(1)],
+        },
+        Event::CallEnd {
+            type_index: 0,
+            results: vec![],
+        },
+        Event::Call {
+            func: 1,
+            args: vec![Value::I32(16)],
+        },
+        Event::FunctionEntry {
+            func: 1,
+            params: vec![Value::I32(16)],
+        },
+        Event::Store {
+            opcode: 0x36,
+            addr: 16,
+            value: Value::I32(5),
+        },
+        Event::Load {
+            opcode: 0x28,
+            addr: 16,
+            value: Value::I32(5),
+        },
+        Event::Return {
+            func: 1,
+            results: vec![Value::I32(5)],
+        },
+        Event::CallEnd {
+            type_index: 1,
+            results: vec![Value::I32(5)],
+        },
+        Event::Return {
+            func: 2,
+            results: vec![Value::I32(5)],
+        },
+        Event::ExportReturn {
+            export: 0,
+            results: vec![Value::I32(5)],
+        },
+    ]
+}
+
+/// Runs a session on the requests and returns the messages it sent.
+fn serve(wasm: &[u8], events: Vec<Event>, requests: &[(&str, Json)]) -> Vec<Json> {
+    let mut input = Vec::new();
+    for (seq, (command, arguments)) in requests.iter().enumerate() {
+        let request = json!({
+            "seq": seq + 1,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(
+            input,
+            "Content-Length: {}\r\n\r\n{}",
+            request.len(),
+            request
+        )
+        .unwrap();
+    }
+    let mut output = Vec::new();
+    Session::new(wasm, events)
+        .unwrap()
+        .serve(Cursor::new(input), &mut output)
+        .unwrap();
+    let mut messages = Vec::new();
+    let mut rest = &output[..];
+    while !rest.is_empty() {
+        let header_end = rest.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let header = std::str::from_utf8(&rest[..header_end]).unwrap();
+        let len: usize = header["Content-Length: ".len()..].parse().unwrap();
+        let body = &rest[header_end + 4..header_end + 4 + len];
+        messages.push(serde_json::from_slice(body).unwrap());
+        rest = &rest[header_end + 4 + len..];
+    }
+    messages
+}
+
+/// The events the session stopped at, with the reasons.
+fn stops(messages: &[Json]) -> Vec<(usize, String)> {
+    messages
+        .iter()
+        .filter(|m| m["event"] == "stopped")
+        .map(|m| {
+            let text = m["body"]["text"].as_str().unwrap();
+            let event = text["event ".len()..text.find(':').unwrap()]
+                .parse()
+                .unwrap();
+            (event, m["body"]["reason"].as_str().unwrap().to_string())
+        })
+        .collect()
+}
+
+fn response<'a>(messages: &'a [Json], command: &str) -> &'a Json {
+    messages
+        .iter()
+        .find(|m| m["type"] == "response" && m["command"] == command)
+        .unwrap()
+}
+
+#[test]
+fn steps_over_into_and_out_of_calls() {
+    let wasm = wat::parse_str(WAT).unwrap();
+    let messages = serve(
+        &wasm,
+        events(),
+        &[
+            ("initialize", json!({})),
+            ("configurationDone", json!({})),
+            ("stepIn", json!({})),
+            ("next", json!({})),
+            ("next", json!({})),
+            ("next", json!({})),
+            ("next", json!({})),
+            ("stepBack", json!({})),
+            ("stepIn", json!({})),
+            ("next", json!({})),
+            ("stepOut", json!({})),
+            ("next", json!({})),
+            ("next", json!({})),
+            ("disconnect", json!({})),
+        ],
+    );
+    assert_eq!(messages[0]["body"]["supportsStepBack"], true);
+    assert_eq!(messages[1]["event"], "initialized");
+    let events: Vec<usize> = stops(&messages).into_iter().map(|(i, _)| i).collect();
+    assert_eq!(events, [0, 1, 2, 3, 4, 9, 4, 5, 6, 9, 11, 11]);
+    let last = messages.iter().rfind(|m| m["event"] == "stopped").unwrap();
+    assert_eq!(last["body"]["description"], "end of the trace");
+}
+
+#[test]
+fn shows_the_stack_and_the_memory_at_a_breakpoint() {
+    let wasm = wat::parse_str(WAT).unwrap();
+    let messages = serve(
+        &wasm,
+        events(),
+        &[
+            ("initialize", json!({})),
+            (
+                "setFunctionBreakpoints",
+                json!({ "breakpoints": [{ "name": "inner" }, { "name": "missing" }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("continue", json!({ "threadId": 1 })),
+            ("next", json!({})),
+            ("next", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("variables", json!({ "variablesReference": 1 })),
+            (
+                "readMemory",
+                json!({ "memoryReference": "0x10", "offset": -2, "count": 8 }),
+            ),
+            ("continue", json!({ "threadId": 1 })),
+        ],
+    );
+    let breakpoints = &response(&messages, "setFunctionBreakpoints")["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+    assert_eq!(
+        stops(&messages),
+        [
+            (0, "entry".to_string()),
+            (5, "function breakpoint".to_string()),
+            (6, "step".to_string()),
+            (7, "step".to_string()),
+            (11, "step".to_string()),
+        ]
+    );
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    let names: Vec<&str> = frames
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["inner", "main"]);
+    let variables = &response(&messages, "variables")["body"]["variables"];
+    let value = |name: &str| {
+        variables
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|v| v["name"] == name)
+            .unwrap()["value"]
+            .clone()
+    };
+    assert_eq!(value("address"), "0x10");
+    assert_eq!(value("last write"), "event 6: i32.store 0x10 <- i32 5");
+    assert_eq!(value("memory"), "20 bytes");
+    let memory = &response(&messages, "readMemory")["body"];
+    assert_eq!(memory["address"], "0xe");
+    // 0 0 5 0 0 0, then 2 bytes behind the replayed memory
+    assert_eq!(memory["data"], "AAAFAAAA");
+    assert_eq!(memory["unreadableBytes"], 2);
+}
+
+#[test]
+fn reads_memory_out_of_range() {
+    let wasm = wat::parse_str(WAT).unwrap();
+    let messages = serve(
+        &wasm,
+        events(),
+        &[
+            ("initialize", json!({})),
+            ("configurationDone", json!({})),
+            (
+                "readMemory",
+                json!({ "memoryReference": "0x10", "count": u64::MAX }),
+            ),
+            (
+                "readMemory",
+                json!({ "memoryReference": "0x7fffffffffffffff", "offset": i64::MAX, "count": 4 }),
+            ),
+        ],
+    );
+    let reads: Vec<&Json> = messages
+        .iter()
+        .filter(|m| m["command"] == "readMemory")
+        .map(|m| &m["body"])
+        .collect();
+    // The count is capped at what a 32 bit memory holds
+    assert_eq!(reads[0]["address"], "0x10");
+    assert_eq!(reads[0]["unreadableBytes"], u32::MAX as u64);
+    assert_eq!(reads[1]["address"], format!("{:#x}", i64::MAX));
+    assert_eq!(reads[1]["data"], "");
+    assert_eq!(reads[1]["unreadableBytes"], 4);
+}
+
+#[test]
+fn rejects_bad_headers() {
+    let wasm = wat::parse_str(WAT).unwrap();
+    for (input, message) in [
+        (
+            "Content-Length: 99999999999\r\n\r\n",
+            "message of 99999999999 bytes is longer than the limit of 4194304 bytes",
+        ),
+        (
+            "Content-Type: json\r\n\r\n{}",
+            "message without a Content-Length header",
+        ),
+    ] {
+        let error = Session::new(&wasm, events())
+            .unwrap()
+            .serve(Cursor::new(input), Vec::new())
+            .unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+}
+
+#[test]
+fn stops_at_a_trap_with_its_source_lines() {
+    let wasm = wat::parse_str(WAT).unwrap();
+    let (code_start, bodies) = code_ranges(&wasm);
+    let inner = bodies[0].0 - code_start;
+    let main = bodies[1].0 - code_start;
+    let wasm = with_line_table(
+        &wasm,
+        &[(inner, 10), (inner + 3, 11), (main, 20)],
+        bodies[1].1 - code_start,
+    );
+    let source = SourceMap::from_buffer(&wasm).unwrap();
+    assert_eq!(source.locate(code_start - 1), None);
+    assert_eq!(
+        source.locate(bodies[0].0 + 4).unwrap().to_string(),
+        "/src/lib.c:11"
+    );
+    assert_eq!(source.locate_function(2).unwrap().line, 20);
+    let mut events = events()[..6].to_vec();
+    events.push(Event::Trap {
+        depth: 2,
+        frames: vec![
+            TrapFrame {
+                func: 1,
+                offset: bodies[0].0 + 3,
+            },
+            TrapFrame { func: 2, offset: 0 },
+        ],
+    });
+    let messages = serve(
+        &wasm,
+        events,
+        &[
+            ("initialize", json!({})),
+            ("configurationDone", json!({})),
+            ("continue", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+        ],
+    );
+    assert_eq!(stops(&messages)[1], (6, "exception".to_string()));
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "inner");
+    assert_eq!(frames[0]["line"], 11);
+    assert_eq!(frames[0]["source"]["path"], "/src/lib.c");
+    assert_eq!(frames[1]["name"], "main");
+    assert_eq!(frames[1]["line"], 20);
+}
+
+/// The start of the code section contents and the byte ranges of the
+/// function bodies.
+fn code_ranges(wasm: &[u8]) -> (u32, Vec<(u32, u32)>) {
+    let mut code_start = 0;
+    let mut bodies = Vec::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.unwrap() {
+            Payload::CodeSectionStart { range, .. } => code_start = range.start as u32,
+            Payload::CodeSectionEntry(body) => {
+                bodies.push((body.range().start as u32, body.range().end as u32))
+            }
+            _ => {}
+        }
+    }
+    (code_start, bodies)
+}
+
+/// Appends the DWARF sections of one sequence of `lib.c` lines by address.
+fn with_line_table(wasm: &[u8], rows: &[(u32, u64)], end: u32) -> Vec<u8> {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 4,
+    };
+    let mut dwarf = DwarfUnit::new(encoding);
+    let mut program = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(b"/src".to_vec()),
+        None,
+        LineString::String(b"lib.c".to_vec()),
+        None,
+    );
+    let file = program.add_file(
+        LineString::String(b"lib.c".to_vec()),
+        program.default_directory(),
+        None,
+    );
+    program.begin_sequence(Some(Address::Constant(0)));
+    for &(address, line) in rows {
+        program.row().address_offset = address as u64;
+        program.row().file = file;
+        program.row().line = line;
+        program.generate_row();
+    }
+    program.end_sequence(end as u64);
+    dwarf.unit.line_program = program;
+    let root = dwarf.unit.root();
+    dwarf.unit.get_mut(root).set(
+        gimli::DW_AT_comp_dir,
+        AttributeValue::String(b"/src".to_vec()),
+    );
+    let mut sections = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+    let mut wasm = wasm.to_vec();
+    sections
+        .for_each(|id, data| {
+            if !data.slice().is_empty() {
+                custom_section(&mut wasm, id.name(), data.slice());
+            }
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+    wasm
+}
+
+fn custom_section(wasm: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let mut contents = Vec::new();
+    leb128(&mut contents, name.len() as u32);
+    contents.extend_from_slice(name.as_bytes());
+    contents.extend_from_slice(data);
+    wasm.push(0);
+    leb128(wasm, contents.len() as u32);
+    wasm.extend_from_slice(&contents);
+}
+
+fn leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,379 @@
+//! Records encoded with `Event::encode` decode to the same events.
+
+use r3_tracer::decode::{
+    reassemble_ring, Decoder, Event, MemorySnapshot, MemoryWrite, RingState, TrapFrame, Value,
+};
+
+const WAT: &str = r#"
+(module
+  (type $t (func (param f32 f64) (result i64)))
+  (import "wasi_snapshot_preview1" "fd_read" (func (param i32 i32 i32 i32) (result i32)))
+  (memory 1)
+  (global (mut i32) (i32.const 0))
+  (global (mut i64) (i64.const 0))
+  (global f32 (f32.const 0))
+  (global (mut f64) (f64.const 0))
+  (table 2 funcref)
+  (func $f (type $t) (i64.const 0))
+  (func (export "run") (param i32) (result f64) (f64.const 0)))
+"#;
+
+fn decoder() -> Decoder {
+    Decoder::from_buffer(WAT.as_bytes()).unwrap()
+}
+
+fn encode(events: &[Event]) -> Vec<u8> {
+    let mut trace = Vec::new();
+    for event in events {
+        event.encode(&mut trace);
+    }
+    trace
+}
+
+#[test]
+fn round_trip() {
+    let write = |addr, bytes: &[u8]| MemoryWrite {
+        addr,
+        bytes: bytes.to_vec(),
+    };
+    let state = (
+        vec![
+            (0, Value::I32(-1)),
+            (1, Value::I64(i64::MIN)),
+            (2, Value::F32(1.5)),
+            (3, Value::F64(-0.25)),
+        ],
+        vec![vec![
+            Value::Ref { is_null: true },
+            Value::Ref { is_null: false },
+        ]],
+    );
+    let events = vec![
+        Event::ExportCall {
+            export: 0,
+            args: vec![Value::I32(7)],
+        },
+        Event::FunctionEntry {
+            func: 2,
+            params: vec![Value::I32(7)],
+        },
+        Event::Call {
+            func: 1,
+            args: vec![Value::F32(2.0), Value::F64(3.0)],
+        },
+        Event::FunctionEntry {
+            func: 1,
+            params: vec![Value::F32(2.0), Value::F64(3.0)],
+        },
+        Event::Return {
+            func: 1,
+            results: vec![Value::I64(0)],
+        },
+        Event::CallEnd {
+            type_index: 0,
+            results: vec![Value::I64(0)],
+        },
+        Event::CallIndirect {
+            type_index: 0,
+            table: 0,
+            args: vec![Value::F32(-1.0), Value::F64(f64::MAX)],
+            elem_index: 1,
+        },
+        Event::ImportCall {
+            func: 0,
+            args: vec![Value::I32(0), Value::I32(16), Value::I32(1), Value::I32(32)],
+        },
+        Event::Syscall {
+            func: 0,
+            errno: 0,
+            writes: vec![write(32, &[3, 0, 0, 0]), write(64, b"abc")],
+        },
+        Event::Syscall {
+            func: 0,
+            errno: -1,
+            writes: vec![],
+        },
+        Event::Load {
+            opcode: 0x2C,
+            addr: 0x10,
+            value: Value::I32(200),
+        },
+        Event::Store {
+            opcode: 0x37,
+            addr: 0xfff8,
+            value: Value::I64(-2),
+        },
+        Event::Store {
+            opcode: 0x38,
+            addr: 4,
+            value: Value::F32(0.5),
+        },
+        Event::GlobalGet {
+            global: 2,
+            value: Value::F32(0.0),
+        },
+        Event::GlobalSet {
+            global: 3,
+            value: Value::F64(1e300),
+        },
+        Event::TableGet {
+            table: 0,
+            index: 1,
+            value: Value::Ref { is_null: false },
+        },
+        Event::TableSet {
+            table: 0,
+            index: 0,
+            value: Value::Ref { is_null: true },
+        },
+        Event::BulkMemory {
+            opcode: 0x0B,
+            addr: 16,
+            len: 3,
+            bytes: Some(vec![1, 2, 3]),
+        },
+        Event::BulkMemory {
+            opcode: 0x0A,
+            addr: 16,
+            len: 1 << 20,
+            bytes: None,
+        },
+        Event::MemoryGrow {
+            delta: 1,
+            result: -1,
+        },
+        Event::BulkTable {
+            opcode: 0x11,
+            table: 0,
+            index: 1,
+            count: 1,
+            values: Some(vec![Value::Ref { is_null: true }]),
+        },
+        Event::BulkTable {
+            opcode: 0x0F,
+            table: 0,
+            index: 2,
+            count: 100,
+            values: None,
+        },
+        Event::Trap {
+            depth: 3,
+            frames: vec![
+                TrapFrame {
+                    func: 1,
+                    offset: 0x40,
+                },
+                TrapFrame { func: 2, offset: 0 },
+            ],
+        },
+        Event::InitialState {
+            globals: state.0.clone(),
+            tables: state.1.clone(),
+            memory: None,
+        },
+        Event::Checkpoint {
+            globals: state.0,
+            tables: state.1,
+            memory: Some(MemorySnapshot {
+                pages: 1,
+                runs: vec![write(256, &[1; 256])],
+            }),
+        },
+        Event::ExportReturn {
+            export: 0,
+            results: vec![Value::F64(0.0)],
+        },
+    ];
+    let trace = encode(&events);
+    assert_eq!(decoder().decode(&trace).unwrap(), events);
+}
+
+#[test]
+fn record_layout() {
+    // Code, func idx, then the args in stack order
+    let call = Event::Call {
+        func: 1,
+        args: vec![Value::F32(1.0), Value::F64(2.0)],
+    };
+    assert_eq!(
+        encode(&[call]),
+        [
+            &[0x10, 1, 0, 0, 0][..],
+            &1f32.to_le_bytes(),
+            &2f64.to_le_bytes()
+        ]
+        .concat()
+    );
+    // Code, effective address, value
+    let store = Event::Store {
+        opcode: 0x3A,
+        addr: 0x20,
+        value: Value::I32(5),
+    };
+    assert_eq!(encode(&[store]), [0x3A, 0x20, 0, 0, 0, 5, 0, 0, 0]);
+}
+
+#[test]
+fn invalid_records() {
+    let decoder = decoder();
+    let error = |trace: &[u8]| format!("{:#}", decoder.decode(trace).unwrap_err());
+    assert_eq!(
+        error(&[0x01]),
+        "invalid record at offset 0x0: unknown record code 0x1"
+    );
+    let mut trace = encode(&[Event::GlobalGet {
+        global: 1,
+        value: Value::I64(1),
+    }]);
+    trace.pop();
+    assert_eq!(
+        error(&trace),
+        "invalid record at offset 0x0: trace ends inside the record"
+    );
+    assert_eq!(
+        error(&[0x23, 9, 0, 0, 0]),
+        "invalid record at offset 0x0: unknown global 9"
+    );
+}
+
+#[test]
+fn resolves_table_elements() {
+    let wat = r#"
+(module
+  (table $fixed 3 funcref)
+  (table $written 2 funcref)
+  (elem (table $fixed) (i32.const 1) func $a $b)
+  (elem (table $written) (i32.const 0) func $b)
+  (func $a)
+  (func $b (table.set $written (i32.const 1) (ref.func $a))))
+"#;
+    let decoder = Decoder::from_buffer(wat.as_bytes()).unwrap();
+    let set = Value::Ref { is_null: false };
+    let null = Value::Ref { is_null: true };
+    let tables = vec![vec![null, set, set], vec![set, set]];
+    let state = |initial| {
+        let (globals, tables, memory) = (Vec::new(), tables.clone(), None);
+        match initial {
+            true => Event::InitialState {
+                globals,
+                tables,
+                memory,
+            },
+            false => Event::Checkpoint {
+                globals,
+                tables,
+                memory,
+            },
+        }
+    };
+    let events = decoder
+        .decode(&encode(&[state(true), state(false)]))
+        .unwrap();
+    // A table the module writes may hold other functions by a checkpoint
+    let resolved = |e: &Event| match e {
+        Event::InitialState { tables, .. } | Event::Checkpoint { tables, .. } => tables.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        resolved(&events[0]),
+        [
+            vec![null, Value::Func(0), Value::Func(1)],
+            vec![Value::Func(1), set]
+        ]
+    );
+    assert_eq!(
+        resolved(&events[1]),
+        [vec![null, Value::Func(0), Value::Func(1)], vec![set, set]]
+    );
+}
+
+#[test]
+fn rejects_memory_runs_outside_the_pages() {
+    let snapshot = |addr, len| Event::Checkpoint {
+        globals: vec![
+            (0, Value::I32(0)),
+            (1, Value::I64(0)),
+            (2, Value::F32(0.0)),
+            (3, Value::F64(0.0)),
+        ],
+        tables: vec![vec![Value::Ref { is_null: true }; 2]],
+        memory: Some(MemorySnapshot {
+            pages: 1,
+            runs: vec![MemoryWrite {
+                addr,
+                bytes: vec![1; len],
+            }],
+        }),
+    };
+    let decoder = decoder();
+    assert!(decoder.decode(&encode(&[snapshot(0xff00, 256)])).is_ok());
+    assert_eq!(
+        format!(
+            "{:#}",
+            decoder
+                .decode(&encode(&[snapshot(0xff01, 256)]))
+                .unwrap_err()
+        ),
+        "invalid record at offset 0x0: memory run of 256 bytes at 0xff01 ends behind the 1 pages"
+    );
+    // Built by hand, the memory is extended instead
+    let Event::Checkpoint {
+        memory: Some(memory),
+        ..
+    } = snapshot(0xfff0, 32)
+    else {
+        unreachable!()
+    };
+    assert_eq!(memory.bytes().len(), 0x10010);
+}
+
+#[test]
+fn reassembles_ring_from_whole_memory() {
+    let load = |addr| Event::Load {
+        opcode: 0x28,
+        addr,
+        value: Value::I32(addr as i32),
+    };
+    // Records of 9 bytes and their footers in a 48 byte ring that wraps
+    // behind 37: four records in the previous lap, one in the current
+    let record = |addr| {
+        let mut bytes = encode(&[load(addr)]);
+        bytes.extend(9u16.to_le_bytes());
+        bytes
+    };
+    let mut memory = [record(0), record(1), record(2), record(3)].concat();
+    memory[..11].copy_from_slice(&record(4));
+    memory.resize(48, 0);
+    // The rest of the page holds garbage
+    memory.resize(64 * 1024, 0xff);
+    let state = RingState {
+        mem_pointer: 11,
+        ring_end: 44,
+        wrap_count: 1,
+        limit: 37,
+        size: 48,
+        written: 0,
+    };
+    let trace = reassemble_ring(&memory, state).unwrap();
+    // The first record after mem_pointer may have been overwritten
+    assert_eq!(
+        decoder().decode(&trace).unwrap(),
+        [load(2), load(3), load(4)]
+    );
+    // A longer record written at mem_pointer reached into the third one
+    let written = RingState {
+        written: 23,
+        ..state
+    };
+    assert_eq!(
+        decoder()
+            .decode(&reassemble_ring(&memory, written).unwrap())
+            .unwrap(),
+        [load(3), load(4)]
+    );
+    let error = reassemble_ring(&memory[..40], state).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the trace of 40 bytes is shorter than the 48 byte ring"
+    );
+}
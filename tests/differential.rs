@@ -0,0 +1,1186 @@
+//! Runs the original and the instrumented module side by side and checks the
+//! decoded trace.
+
+mod common;
+
+use common::{run_both, Runner};
+use r3_tracer::{
+    decode::{checkpoint_before, reassemble_ring, Decoder, Event, RingState},
+    instrument_wasm_with_options,
+    metadata::{Export, Metadata},
+    sites::Sites,
+    wasabi, Options, Sampling,
+};
+use walrus::{
+    ir::{self, Const, Instr, Visitor},
+    InstrLocId,
+};
+use wasmi::Val;
+
+const MEMORY: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 16) "\2a\00\00\00")
+  (func (export "copy") (param $from i32) (param $to i32)
+    (i64.store8 (local.get $to) (i64.load8_u (local.get $from)))
+    ;; the effective addresses are recorded
+    (i32.store offset=4 (local.get $to) (i32.load (local.get $from))))
+  (func (export "store_same") (param i32)
+    ;; address and value of the same type
+    (i32.store (local.get 0) (local.get 0))
+    (f64.store offset=8 (i32.const 56) (f64.const 1.5))))
+"#;
+
+const CALLS: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $g (mut i64) (i64.const 7))
+  (table 2 funcref)
+  (elem (i32.const 0) $add $add)
+  (type $t (func (param i32 i32) (result i32)))
+  (func $add (param i32 i32) (result i32)
+    (i32.store (local.get 0) (local.get 1))
+    (i32.add (local.get 0) (local.get 1)))
+  (func (export "run") (param i32) (result i32)
+    (global.set $g (i64.add (global.get $g) (i64.const 1)))
+    (drop (call $add (i32.const 8) (i32.const 42)))
+    (call_indirect (type $t) (i32.const 100) (local.get 0) (i32.const 1))))
+"#;
+
+const LOOP: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "run") (param $n i32)
+    (local $i i32)
+    (loop $l
+      (i32.store (i32.mul (local.get $i) (i32.const 4)) (local.get $i))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $l (i32.lt_u (local.get $i) (local.get $n))))))
+"#;
+
+fn instrument(wat: &str, options: &Options) -> (Vec<u8>, Vec<u8>) {
+    let original = wat::parse_str(wat).unwrap();
+    let instrumented = instrument_wasm_with_options(&original, options)
+        .unwrap()
+        .emit_wasm();
+    (original, instrumented)
+}
+
+fn events(original: &[u8], trace: &[u8]) -> Vec<String> {
+    Decoder::from_buffer(original)
+        .unwrap()
+        .decode(trace)
+        .unwrap()
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+#[test]
+fn memory_accesses() {
+    let (original, instrumented) = instrument(MEMORY, &Options::default());
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[
+            ("copy", &[Val::I32(16), Val::I32(32)]),
+            ("store_same", &[Val::I32(128)]),
+        ],
+    );
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "export call 0 [i32 16, i32 32]",
+            "enter 0 [i32 16, i32 32]",
+            "i64.load8 0x10 -> i64 42",
+            "i64.store8 0x20 <- i64 42",
+            "i32.load 0x10 -> i32 42",
+            "i32.store 0x24 <- i32 42",
+            "return 0 []",
+            "export return 0 []",
+            "export call 1 [i32 128]",
+            "enter 1 [i32 128]",
+            "i32.store 0x80 <- i32 128",
+            "f64.store 0x40 <- f64 1.5",
+            "return 1 []",
+            "export return 1 []",
+        ]
+    );
+}
+
+#[test]
+fn calls() {
+    let (original, instrumented) = instrument(CALLS, &Options::default());
+    let runner = run_both(&original, &instrumented, &[("run", &[Val::I32(5)])]);
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "export call 0 [i32 5]",
+            "enter 1 [i32 5]",
+            "global.get 0 -> i64 7",
+            "global.set 0 <- i64 8",
+            "call 0 [i32 8, i32 42]",
+            "enter 0 [i32 8, i32 42]",
+            "i32.store 0x8 <- i32 42",
+            "return 0 [i32 50]",
+            "call_end (type 0) [i32 50]",
+            "call_indirect (type 0) table 0[1] [i32 100, i32 5]",
+            "enter 0 [i32 100, i32 5]",
+            "i32.store 0x64 <- i32 5",
+            "return 0 [i32 105]",
+            "call_end (type 0) [i32 105]",
+            "return 1 [i32 105]",
+            "export return 0 [i32 105]",
+        ]
+    );
+}
+
+#[test]
+fn traps() {
+    let (original, instrumented) = instrument(MEMORY, &Options::default());
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[("copy", &[Val::I32(16), Val::I32(0x10000)])],
+    );
+    // The trace is only flushed on return
+    assert!(runner.trace().is_empty());
+}
+
+#[test]
+fn ring_buffer() {
+    let size = 128;
+    let (original, instrumented) = instrument(
+        LOOP,
+        &Options {
+            ring_buffer: Some(size),
+            ..Options::default()
+        },
+    );
+    let runner = run_both(&original, &instrumented, &[("run", &[Val::I32(20)])]);
+    let state = RingState {
+        mem_pointer: runner.global_u32("trace_byte_length"),
+        ring_end: runner.global_u32("trace_ring_end"),
+        wrap_count: runner.global_u32("trace_wrap_count"),
+        limit: runner.global_u32("trace_ring_limit"),
+        size: runner.global_u32("trace_ring_size"),
+        written: runner.global_u32("trace_ring_written"),
+    };
+    assert!(state.wrap_count > 0);
+    // The whole memory, rounded up to a page
+    let memory = runner.memory("trace").unwrap();
+    assert!(memory.len() > size as usize);
+    let events = events(&original, &reassemble_ring(&memory, state).unwrap());
+    let last = events.len() - 3;
+    assert_eq!(
+        events[last..],
+        [
+            "i32.store 0x4c <- i32 19",
+            "return 0 []",
+            "export return 0 []"
+        ]
+    );
+}
+
+#[test]
+fn runtime_control() {
+    let (original, instrumented) = instrument(
+        LOOP,
+        &Options {
+            runtime_control: true,
+            ..Options::default()
+        },
+    );
+    let mut runner = run_both(&original, &instrumented, &[("run", &[Val::I32(2)])]);
+    assert!(runner.trace().is_empty());
+    runner.call("r3_enable", &[]).unwrap();
+    runner.call("run", &[Val::I32(1)]).unwrap();
+    runner.call("r3_disable", &[]).unwrap();
+    runner.call("run", &[Val::I32(3)]).unwrap();
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "export call 0 [i32 1]",
+            "enter 0 [i32 1]",
+            "i32.store 0x0 <- i32 0",
+            "return 0 []",
+            "export return 0 []",
+        ]
+    );
+}
+
+#[test]
+fn sampling() {
+    for (sampling, recorded) in [
+        (Sampling::EveryNth(3), &[0, 3, 6, 9][..]),
+        (
+            Sampling::Window {
+                window: 2,
+                period: 4,
+            },
+            &[0, 1, 4, 5, 8, 9],
+        ),
+    ] {
+        let (original, instrumented) = instrument(
+            LOOP,
+            &Options {
+                sampling: Some(sampling),
+                ..Options::default()
+            },
+        );
+        let runner = run_both(&original, &instrumented, &[("run", &[Val::I32(10)])]);
+        let stores: Vec<String> = recorded
+            .iter()
+            .map(|i| format!("i32.store {:#x} <- i32 {}", i * 4, i))
+            .collect();
+        let events = events(&original, runner.trace());
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| e.contains("store"))
+                .collect::<Vec<_>>(),
+            stores.iter().collect::<Vec<_>>(),
+            "{:?}",
+            sampling
+        );
+        // Calls and returns are always recorded
+        assert_eq!(events.len(), stores.len() + 4);
+    }
+}
+
+#[test]
+fn trigger_function() {
+    let (original, instrumented) = instrument(
+        r#"
+(module
+  (memory (export "memory") 1)
+  (func $store (param i32)
+    (i32.store (local.get 0) (local.get 0)))
+  (func $inner (param i32)
+    (call $store (local.get 0)))
+  (func (export "run")
+    (call $store (i32.const 4))
+    (call $inner (i32.const 8))
+    (call $store (i32.const 12))))
+"#,
+        &Options {
+            trigger_function: Some("inner".to_string()),
+            ..Options::default()
+        },
+    );
+    let mut runner = run_both(&original, &instrumented, &[("run", &[])]);
+    assert_eq!(runner.global_u32("r3_enabled"), 0);
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "enter 1 [i32 8]",
+            "call 0 [i32 8]",
+            "enter 0 [i32 8]",
+            "i32.store 0x8 <- i32 8",
+            "return 0 []",
+            "call_end (type 0) []",
+            "return 1 []",
+        ]
+    );
+    runner.call("r3_enable", &[]).unwrap();
+    assert_eq!(runner.global_u32("r3_enabled"), 1);
+}
+
+const TRIGGER: &str = r#"
+(module
+  (import "host" "disable" (func $disable))
+  (memory (export "memory") 1)
+  (func (export "t") (param $p i32) (param $trap i32)
+    (i32.store (local.get $p) (local.get $p))
+    (if (local.get $trap) (then unreachable))
+    (call $disable)
+    (i32.store offset=4 (local.get $p) (local.get $p)))
+  (func (export "other")
+    (i32.store (i32.const 100) (i32.const 1))))
+"#;
+
+#[test]
+fn disable_inside_the_trigger_function() {
+    let (original, instrumented) = instrument(
+        TRIGGER,
+        &Options {
+            trigger_function: Some("t".to_string()),
+            ..Options::default()
+        },
+    );
+    let t = |p| [Val::I32(p), Val::I32(0)];
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[("t", &t(8)), ("other", &[]), ("t", &t(16)), ("other", &[])],
+    );
+    assert_eq!(runner.global_u32("r3_enabled"), 0);
+    // Each run is recorded up to the call that disables recording
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "enter 1 [i32 8, i32 0]",
+            "i32.store 0x8 <- i32 8",
+            "call import 0 []",
+            "enter 1 [i32 16, i32 0]",
+            "i32.store 0x10 <- i32 16",
+            "call import 0 []",
+        ]
+    );
+}
+
+#[test]
+fn trap_inside_the_trigger_function() {
+    let (original, instrumented) = instrument(
+        TRIGGER,
+        &Options {
+            trigger_function: Some("t".to_string()),
+            trap_info: true,
+            ..Options::default()
+        },
+    );
+    let mut runner = run_both(
+        &original,
+        &instrumented,
+        &[("t", &[Val::I32(8), Val::I32(1)])],
+    );
+    runner.call("r3_trap_info", &[]).unwrap();
+    runner.call("other", &[]).unwrap();
+    let events = events(&original, runner.trace());
+    assert_eq!(events.len(), 3, "{:?}", events);
+    assert_eq!(
+        events[..2],
+        ["enter 1 [i32 8, i32 1]", "i32.store 0x8 <- i32 8"]
+    );
+    assert!(events[2].starts_with("trap"), "{}", events[2]);
+}
+
+#[test]
+fn corpus_validates_and_runs() {
+    let tests = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    for name in [
+        "block.wat",
+        "call_indirect.wat",
+        "load.wat",
+        "table_set.wat",
+    ] {
+        let wasm = wat::parse_file(tests.join(name)).unwrap();
+        let instrumented = instrument_wasm_with_options(&wasm, &Options::default())
+            .unwrap()
+            .emit_wasm();
+        Runner::new(&instrumented).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    }
+}
+
+#[test]
+fn branch_to_function_label() {
+    let (original, instrumented) = instrument(
+        r#"
+(module
+  (func (export "run") (param i32) (result i32)
+    (br_if 0 (i32.const 1) (local.get 0))
+    (br 0 (i32.const 2))))
+"#,
+        &Options::default(),
+    );
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[("run", &[Val::I32(1)]), ("run", &[Val::I32(0)])],
+    );
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "export call 0 [i32 1]",
+            "enter 0 [i32 1]",
+            "return 0 [i32 1]",
+            "export return 0 [i32 1]",
+            "export call 0 [i32 0]",
+            "enter 0 [i32 0]",
+            "return 0 [i32 2]",
+            "export return 0 [i32 2]",
+        ]
+    );
+}
+
+#[test]
+fn nested_branches_to_function_label() {
+    // br_table and a branch out of a nested block also reach the return
+    // record appended to the body
+    let (original, instrumented) = instrument(
+        r#"
+(module
+  (func (export "run") (param i32) (result i32)
+    (drop (block (result i32)
+      (br_table 0 1 (i32.const 3) (local.get 0))))
+    (block
+      (drop (br_if 1 (i32.const 4) (i32.eqz (local.get 0)))))
+    (i32.const 5)))
+"#,
+        &Options::default(),
+    );
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[("run", &[Val::I32(0)]), ("run", &[Val::I32(1)])],
+    );
+    let events = events(&original, runner.trace());
+    assert_eq!(
+        events,
+        [
+            "export call 0 [i32 0]",
+            "enter 0 [i32 0]",
+            "return 0 [i32 4]",
+            "export return 0 [i32 4]",
+            "export call 0 [i32 1]",
+            "enter 0 [i32 1]",
+            "return 0 [i32 3]",
+            "export return 0 [i32 3]",
+        ]
+    );
+}
+
+const WASI: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "run") (result i32)
+    (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+    (drop (call $args_get (i32.const 16) (i32.const 32)))
+    (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 48)))
+    (drop (call $random_get (i32.const 56) (i32.const 3)))
+    ;; two iovecs of 2 and 8 bytes
+    (i32.store (i32.const 64) (i32.const 100))
+    (i32.store (i32.const 68) (i32.const 2))
+    (i32.store (i32.const 72) (i32.const 110))
+    (i32.store (i32.const 76) (i32.const 8))
+    (drop (call $fd_read (i32.const 0) (i32.const 64) (i32.const 2) (i32.const 80)))
+    ;; fails, the host writes nothing
+    (call $fd_read (i32.const 1) (i32.const 64) (i32.const 2) (i32.const 80))))
+"#;
+
+/// Func idx, errno and the writes as address and bytes.
+type Syscall = (u32, i32, Vec<(u32, Vec<u8>)>);
+
+fn syscalls(original: &[u8], trace: &[u8]) -> Vec<Syscall> {
+    Decoder::from_buffer(original)
+        .unwrap()
+        .decode(trace)
+        .unwrap()
+        .into_iter()
+        .filter_map(|e| match e {
+            Event::Syscall {
+                func,
+                errno,
+                writes,
+            } => Some((
+                func,
+                errno,
+                writes.into_iter().map(|w| (w.addr, w.bytes)).collect(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn wasi_writes() {
+    let (original, instrumented) = instrument(WASI, &Options::default());
+    let runner = run_both(&original, &instrumented, &[("run", &[])]);
+    let le = |v: u32| v.to_le_bytes().to_vec();
+    assert_eq!(
+        syscalls(&original, runner.trace()),
+        [
+            (0, 0, vec![(0, le(2)), (4, le(8))]),
+            (
+                1,
+                0,
+                vec![
+                    (16, [le(32), le(37)].concat()),
+                    (32, b"prog\0-v\0".to_vec())
+                ]
+            ),
+            (2, 0, vec![(48, common::TIME.to_le_bytes().to_vec())]),
+            (3, 0, vec![(56, vec![0, 1, 2])]),
+            (
+                4,
+                0,
+                vec![(80, le(5)), (100, b"he".to_vec()), (110, b"llo".to_vec())]
+            ),
+            (4, 8, vec![]),
+        ]
+    );
+}
+
+#[test]
+fn wasi_writes_in_ring_buffer() {
+    // Wraps several times, the syscall records are longer than the others
+    let size = 128;
+    let (original, instrumented) = instrument(
+        WASI,
+        &Options {
+            ring_buffer: Some(size),
+            ..Options::default()
+        },
+    );
+    let runner = run_both(&original, &instrumented, &[("run", &[])]);
+    let state = RingState {
+        mem_pointer: runner.global_u32("trace_byte_length"),
+        ring_end: runner.global_u32("trace_ring_end"),
+        wrap_count: runner.global_u32("trace_wrap_count"),
+        limit: runner.global_u32("trace_ring_limit"),
+        size: runner.global_u32("trace_ring_size"),
+        written: runner.global_u32("trace_ring_written"),
+    };
+    let trace = reassemble_ring(&runner.memory("trace").unwrap(), state).unwrap();
+    assert_eq!(
+        events(&original, &trace).last().unwrap(),
+        "export return 0 [i32 8]"
+    );
+    assert_eq!(syscalls(&original, &trace).last().unwrap(), &(4, 8, vec![]));
+}
+
+#[test]
+fn dropped_syscall_in_ring_buffer() {
+    let original = wat::parse_str(
+        r#"
+(module
+  (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "store") (param i32)
+    (i32.store (local.get 0) (local.get 0)))
+  (func (export "random") (param i32)
+    (drop (call $random_get (i32.const 0) (local.get 0)))))
+"#,
+    )
+    .unwrap();
+    let instrumented = instrument_wasm_with_options(
+        &original,
+        &Options {
+            ring_buffer: Some(256),
+            runtime_control: true,
+            ..Options::default()
+        },
+    )
+    .unwrap()
+    .emit_wasm();
+    let mut runner = Runner::new(&instrumented).unwrap();
+    runner.call("r3_enable", &[]).unwrap();
+    let mut i = 0;
+    // Into the second lap, far enough that the dropped record does not wrap
+    while runner.global_u32("trace_wrap_count") == 0 || runner.global_u32("trace_byte_length") < 40
+    {
+        runner.call("store", &[Val::I32(i * 4)]).unwrap();
+        i += 1;
+    }
+    runner.call("r3_disable", &[]).unwrap();
+    runner.call("random", &[Val::I32(100)]).unwrap();
+    let state = RingState {
+        mem_pointer: runner.global_u32("trace_byte_length"),
+        ring_end: runner.global_u32("trace_ring_end"),
+        wrap_count: runner.global_u32("trace_wrap_count"),
+        limit: runner.global_u32("trace_ring_limit"),
+        size: runner.global_u32("trace_ring_size"),
+        written: runner.global_u32("trace_ring_written"),
+    };
+    // The dropped record reaches further into the previous lap than any
+    // other record
+    assert_eq!(state.wrap_count, 1);
+    assert!(state.written > state.mem_pointer + (state.size - state.limit));
+    let trace = reassemble_ring(&runner.memory("trace").unwrap(), state).unwrap();
+    let events = events(&original, &trace);
+    assert!(events.len() > 5, "{:?}", events);
+    assert!(events.iter().all(|e| !e.starts_with("syscall")));
+    assert_eq!(
+        events[events.len() - 3..],
+        [
+            format!("i32.store {:#x} <- i32 {}", (i - 1) * 4, (i - 1) * 4),
+            "return 1 []".to_string(),
+            "export return 0 []".to_string()
+        ]
+    );
+}
+
+const WASI_FILES: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_prestat_get" (func $fd_prestat_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $fd_prestat_dir_name (param i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_readdir" (func $fd_readdir (param i32 i32 i32 i64 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "hello world")
+  (func (export "run") (result i32)
+    ;; write_all: one iovec advanced by nwritten until it is empty
+    (i32.store (i32.const 0) (i32.const 100))
+    (i32.store (i32.const 4) (i32.const 11))
+    (block $done
+      (loop $again
+        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+        (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.load (i32.const 8))))
+        (i32.store (i32.const 4) (i32.sub (i32.load (i32.const 4)) (i32.load (i32.const 8))))
+        (br_if $again (i32.load (i32.const 4)))))
+    (drop (call $fd_seek (i32.const 3) (i64.const 5) (i32.const 0) (i32.const 16)))
+    (drop (call $fd_prestat_get (i32.const 3) (i32.const 24)))
+    (drop (call $fd_prestat_dir_name (i32.const 3) (i32.const 32) (i32.const 4)))
+    (drop (call $fd_readdir (i32.const 3) (i32.const 40) (i32.const 6) (i64.const 0) (i32.const 48)))
+    (call $fd_close (i32.const 3))))
+"#;
+
+#[test]
+fn wasi_file_writes() {
+    let (original, instrumented) = instrument(WASI_FILES, &Options::default());
+    let runner = run_both(&original, &instrumented, &[("run", &[])]);
+    let le = |v: u32| v.to_le_bytes().to_vec();
+    assert_eq!(
+        syscalls(&original, runner.trace()),
+        [
+            (0, 0, vec![(8, le(4))]),
+            (0, 0, vec![(8, le(4))]),
+            (0, 0, vec![(8, le(3))]),
+            (1, 0, vec![(16, 105u64.to_le_bytes().to_vec())]),
+            (2, 0, vec![(24, [le(0), le(4)].concat())]),
+            (3, 0, vec![(32, b"/tmp".to_vec())]),
+            (4, 0, vec![(48, le(6)), (40, b"dirent".to_vec())]),
+        ]
+    );
+}
+
+#[test]
+fn unknown_wasi_import() {
+    let wat = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_frobnicate" (func (param i32) (result i32))))
+"#;
+    let error = instrument_wasm_with_options(wat.as_bytes(), &Options::default()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "unknown import wasi_snapshot_preview1.fd_frobnicate, the memory it writes cannot be recorded"
+    );
+}
+
+const BULK: &str = r#"
+(module
+  (memory (export "memory") 1 3)
+  (data $d "hello")
+  (table $t 4 8 funcref)
+  (elem $e func $f $f)
+  (func $f)
+  (func (export "run") (param $len i32)
+    (memory.fill (i32.const 16) (i32.const 0xab) (local.get $len))
+    (memory.copy (i32.const 32) (i32.const 14) (i32.const 4))
+    (memory.init $d (i32.const 48) (i32.const 1) (i32.const 3))
+    (drop (memory.grow (i32.const 1)))
+    (i32.store (i32.const 70000) (i32.const 1))
+    (table.init $t $e (i32.const 0) (i32.const 0) (i32.const 2))
+    (table.copy (i32.const 2) (i32.const 0) (i32.const 1))
+    (table.fill (i32.const 1) (ref.null func) (i32.const 1))
+    (drop (table.grow (ref.func $f) (i32.const 2)))
+    ;; beyond the maximum, does not grow
+    (drop (table.grow (ref.null func) (i32.const 10)))))
+"#;
+
+#[test]
+fn bulk_instructions() {
+    let (original, instrumented) = instrument(BULK, &Options::default());
+    let runner = run_both(&original, &instrumented, &[("run", &[Val::I32(8)])]);
+    let events = events(&original, runner.trace());
+    assert_eq!(
+        events[2..events.len() - 2],
+        [
+            "memory.fill 0x10 <- 8 bytes",
+            "memory.copy 0x20 <- 4 bytes",
+            "memory.init 0x30 <- 3 bytes",
+            "memory.grow 1 -> 1",
+            "i32.store 0x11170 <- i32 1",
+            "table.init 0[0] <- [ref, ref]",
+            "table.copy 0[2] <- [ref]",
+            "table.fill 0[1] <- [ref null]",
+            "table.grow 0[4] <- [ref, ref]",
+            "table.grow 0[4294967295] <- []",
+        ]
+    );
+    let decoded = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    let Event::BulkMemory {
+        bytes: Some(copied),
+        ..
+    } = &decoded[3]
+    else {
+        panic!("memory.copy is not recorded");
+    };
+    // The zeros in front of the fill and its start
+    assert_eq!(copied, &[0, 0, 0xab, 0xab]);
+}
+
+#[test]
+fn bulk_instructions_in_ring_buffer() {
+    let (original, instrumented) = instrument(
+        BULK,
+        &Options {
+            ring_buffer: Some(256),
+            ..Options::default()
+        },
+    );
+    let runner = run_both(&original, &instrumented, &[("run", &[Val::I32(1000)])]);
+    let state = RingState {
+        mem_pointer: runner.global_u32("trace_byte_length"),
+        ring_end: runner.global_u32("trace_ring_end"),
+        wrap_count: runner.global_u32("trace_wrap_count"),
+        limit: runner.global_u32("trace_ring_limit"),
+        size: runner.global_u32("trace_ring_size"),
+        written: runner.global_u32("trace_ring_written"),
+    };
+    let trace = reassemble_ring(&runner.memory("trace").unwrap(), state).unwrap();
+    let events = events(&original, &trace);
+    // Too long for the ring, only the range is recorded
+    assert!(events.contains(&"memory.fill 0x10 <- 1000 bytes (not recorded)".to_string()));
+    assert!(events.contains(&"memory.copy 0x20 <- 4 bytes".to_string()));
+}
+
+#[test]
+fn import_calls() {
+    let (original, instrumented) = instrument(WASI, &Options::default());
+    let runner = run_both(&original, &instrumented, &[("run", &[])]);
+    let events = events(&original, runner.trace());
+    assert_eq!(events[0], "export call 0 []");
+    assert_eq!(events[2], "call import 0 [i32 0, i32 4]");
+    assert_eq!(events[3], "call_end (type 0) [i32 0]");
+    assert_eq!(events.last().unwrap(), "export return 0 [i32 8]");
+    let metadata = Metadata::from_buffer(&original).unwrap();
+    let import = metadata.import(4).unwrap();
+    assert_eq!(
+        (&*import.module, &*import.name),
+        ("wasi_snapshot_preview1", "fd_read")
+    );
+    assert_eq!(
+        metadata.exports,
+        [Export {
+            func: 5,
+            name: "run".to_string()
+        }]
+    );
+    assert_eq!(Metadata::from_json(&metadata.to_json()).unwrap(), metadata);
+}
+
+#[test]
+fn export_calls() {
+    let (original, instrumented) = instrument(
+        r#"
+(module
+  (func $inner (export "inner") (param i64) (result i64)
+    (i64.add (local.get 0) (i64.const 1)))
+  (func (export "outer") (result i64)
+    (call $inner (i64.const 41))))
+"#,
+        &Options::default(),
+    );
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[("outer", &[]), ("inner", &[Val::I64(1)])],
+    );
+    // Only entries from the host are export calls
+    assert_eq!(
+        events(&original, runner.trace()),
+        [
+            "export call 1 []",
+            "enter 1 []",
+            "call 0 [i64 41]",
+            "enter 0 [i64 41]",
+            "return 0 [i64 42]",
+            "call_end (type 0) [i64 42]",
+            "return 1 [i64 42]",
+            "export return 1 [i64 42]",
+            "export call 0 [i64 1]",
+            "enter 0 [i64 1]",
+            "return 0 [i64 2]",
+            "export return 0 [i64 2]",
+        ]
+    );
+}
+
+#[test]
+fn trap_info() {
+    let wat = r#"
+(module
+  (func $div (param i32) (result i32)
+    (i32.div_u (i32.const 12) (local.get 0)))
+  (func (export "run") (param i32) (result i32)
+    (i32.add (i32.const 1) (call $div (local.get 0)))))
+"#;
+    let options = Options {
+        trap_info: true,
+        ..Options::default()
+    };
+    let (original, instrumented) = instrument(wat, &options);
+    let mut runner = run_both(&original, &instrumented, &[("run", &[Val::I32(0)])]);
+    assert_eq!(runner.call("r3_trap_info", &[]).unwrap(), ["I32(2)"]);
+    // The shadow stack is reset, the next trap only has its own frames
+    runner.call("run", &[Val::I32(4)]).unwrap();
+    runner.call("run", &[Val::I32(0)]).unwrap_err();
+    assert_eq!(runner.call("r3_trap_info", &[]).unwrap(), ["I32(2)"]);
+    let events = events(&original, runner.trace());
+    // Innermost first: the i32.div_u in $div and the call in run
+    assert_eq!(events[4], "trap depth 2 [0 @0x27, 1 @0x2f]");
+    assert_eq!(events[12], "export return 0 [i32 4]");
+    assert_eq!(events[17], "trap depth 2 [0 @0x27, 1 @0x2f]");
+    assert_eq!(events.len(), 18);
+}
+
+/// Collects the instructions in the order they are emitted.
+struct Instrs(Vec<(Instr, InstrLocId)>);
+
+impl<'instr> Visitor<'instr> for Instrs {
+    fn visit_instr(&mut self, instr: &'instr Instr, loc: &'instr InstrLocId) {
+        self.0.push((instr.clone(), *loc));
+    }
+}
+
+#[test]
+fn trap_info_before_atomics() {
+    let wat = r#"
+(module
+  (memory 1)
+  (func (export "run") (param i32)
+    (drop (i32.atomic.load (local.get 0)))
+    (i32.atomic.store (local.get 0) (i32.const 1))
+    (drop (i32.atomic.rmw.add (local.get 0) (i32.const 1)))
+    (drop (i32.atomic.rmw.cmpxchg (local.get 0) (i32.const 1) (i32.const 2)))
+    (drop (memory.atomic.notify (local.get 0) (i32.const 1)))
+    (drop (memory.atomic.wait32 (local.get 0) (i32.const 1) (i64.const 0)))))
+"#;
+    let options = Options {
+        trap_info: true,
+        ..Options::default()
+    };
+    let module = instrument_wasm_with_options(&wat::parse_str(wat).unwrap(), &options).unwrap();
+    // The original function comes first
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let mut instrs = Instrs(Vec::new());
+    ir::dfs_in_order(&mut instrs, func, func.entry_block());
+    let atomic = |instr: &Instr| match instr {
+        Instr::Load(load) => load.kind.atomic(),
+        Instr::Store(store) => store.kind.atomic(),
+        Instr::AtomicRmw(_) | Instr::Cmpxchg(_) | Instr::AtomicNotify(_) | Instr::AtomicWait(_) => {
+            true
+        }
+        _ => false,
+    };
+    // The shadow stack is the memory added behind the original and `trace`
+    let shadow = module.memories.iter().nth(2).unwrap().id();
+    let mut atomics = 0;
+    for (i, (instr, loc)) in instrs.0.iter().enumerate() {
+        if !atomic(instr) || loc.is_default() {
+            continue;
+        }
+        atomics += 1;
+        // The last update of the frame offset before it
+        let update = instrs.0[..i].windows(2).rev().find_map(|pair| match pair {
+            [(
+                Instr::Const(Const {
+                    value: ir::Value::I32(offset),
+                }),
+                _,
+            ), (Instr::Store(store), _)]
+                if store.memory == shadow =>
+            {
+                Some(*offset as u32)
+            }
+            _ => None,
+        });
+        assert_eq!(update, Some(loc.data()), "{:?}", instr);
+    }
+    assert_eq!(atomics, 6);
+}
+
+#[test]
+fn initial_state() {
+    let wat = r#"
+(module
+  (global $a (mut i32) (i32.const 7))
+  (global $b f64 (f64.const 1.5))
+  (global $f funcref (ref.null func))
+  (table 3 funcref)
+  (elem (i32.const 1) $start)
+  (memory (export "memory") 2)
+  (data (i32.const 16) "\2a\00\00\00")
+  (data (i32.const 1000) "\01")
+  (func $start
+    (i32.store (i32.const 2000) (i32.const 5))
+    (global.set $a (i32.const 8)))
+  (start $start)
+  (func (export "get") (result i32)
+    (global.get $a)))
+"#;
+    let options = Options {
+        snapshot_memory: true,
+        ..Options::default()
+    };
+    let (original, instrumented) = instrument(wat, &options);
+    let runner = run_both(&original, &instrumented, &[("get", &[])]);
+    let events = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    // Taken before the start function runs
+    assert_eq!(
+        events[0].to_string(),
+        "initial state globals [0: i32 7, 1: f64 1.5, 2: ref null] tables [3 elements] \
+         memory 2 pages [0x0 <- 256 bytes, 0x300 <- 256 bytes]"
+    );
+    assert_eq!(events[1].to_string(), "enter 0 []");
+    let Event::InitialState {
+        tables,
+        memory: Some(memory),
+        ..
+    } = &events[0]
+    else {
+        panic!("no memory snapshot");
+    };
+    assert_eq!(
+        tables[0].iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+        ["ref null", "ref func 0", "ref null"]
+    );
+    let bytes = memory.bytes();
+    assert_eq!(bytes.len(), 2 * 64 * 1024);
+    assert_eq!(&bytes[16..20], [0x2a, 0, 0, 0]);
+    assert_eq!(bytes[1000], 1);
+    assert_eq!(bytes[2000], 0);
+}
+
+#[test]
+fn initial_state_of_a_memory_larger_than_the_trace() {
+    // Even with every other block non-zero, the 65000 pages need more than
+    // the 64 Ki pages the trace memory can grow to
+    let wat = r#"
+(module
+  (memory 65000)
+  (data (i32.const 16) "\2a")
+  (func (export "get") (result i32)
+    (i32.load8_u (i32.const 16))))
+"#;
+    let options = Options {
+        snapshot_memory: true,
+        ..Options::default()
+    };
+    let (original, instrumented) = instrument(wat, &options);
+    let runner = run_both(&original, &instrumented, &[("get", &[])]);
+    let events = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    assert_eq!(events[0].to_string(), "initial state globals [] tables []");
+    assert_eq!(events[1].to_string(), "export call 0 []");
+}
+
+#[test]
+fn checkpoints() {
+    let wat = r#"
+(module
+  (global $count (mut i32) (i32.const 0))
+  (memory (export "memory") 1)
+  (func (export "set") (param i32)
+    (i32.store (i32.const 512) (local.get 0))
+    (global.set $count (i32.add (global.get $count) (i32.const 1)))))
+"#;
+    let options = Options {
+        checkpoint_every: Some(2),
+        ..Options::default()
+    };
+    let (original, instrumented) = instrument(wat, &options);
+    let calls: Vec<[Val; 1]> = (1..=4).map(|i| [Val::I32(i)]).collect();
+    let calls: Vec<(&str, &[Val])> = calls.iter().map(|args| ("set", &args[..])).collect();
+    let runner = run_both(&original, &instrumented, &calls);
+    let events = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    // Every call flushes on the return and in the export wrapper
+    let checkpoints: Vec<(usize, String)> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| matches!(e, Event::Checkpoint { .. }))
+        .map(|(i, e)| (i, e.to_string()))
+        .collect();
+    assert_eq!(
+        checkpoints,
+        [
+            (
+                7,
+                "checkpoint globals [0: i32 1] tables [] memory 1 pages [0x200 <- 256 bytes]"
+                    .to_string()
+            ),
+            (
+                15,
+                "checkpoint globals [0: i32 2] tables [] memory 1 pages [0x200 <- 256 bytes]"
+                    .to_string()
+            ),
+            (
+                23,
+                "checkpoint globals [0: i32 3] tables [] memory 1 pages [0x200 <- 256 bytes]"
+                    .to_string()
+            ),
+            (
+                31,
+                "checkpoint globals [0: i32 4] tables [] memory 1 pages [0x200 <- 256 bytes]"
+                    .to_string()
+            ),
+        ]
+    );
+    assert_eq!(checkpoint_before(&events, 30), Some(23));
+    assert_eq!(checkpoint_before(&events, 5), None);
+    let Event::Checkpoint {
+        memory: Some(memory),
+        ..
+    } = &events[23]
+    else {
+        unreachable!()
+    };
+    assert_eq!(&memory.bytes()[512..516], 3i32.to_le_bytes());
+}
+
+#[test]
+fn watchpoints() {
+    let wat = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "run") (param $p i32)
+    (i32.store offset=8 (local.get $p) (i32.const 1))
+    (i32.store8 (local.get $p) (i32.const 2))
+    (drop (i64.load offset=4 (local.get $p)))))
+"#;
+    let options = Options {
+        watch: vec![4096..4100, 110..111],
+        ..Options::default()
+    };
+    let (original, instrumented) = instrument(wat, &options);
+    // The store covers 108..112, the load 104..112, the i32.store8 only 100
+    let mut runner = run_both(&original, &instrumented, &[("run", &[Val::I32(100)])]);
+    runner.call("r3_unwatch_all", &[]).unwrap();
+    runner.call("run", &[Val::I32(100)]).unwrap();
+    assert_eq!(
+        runner.call("r3_watch", &[Val::I32(100), Val::I32(101)]),
+        Ok(vec!["I32(1)".to_string()])
+    );
+    runner.call("run", &[Val::I32(100)]).unwrap();
+    // Nothing overlaps an empty range
+    runner.call("r3_unwatch_all", &[]).unwrap();
+    runner
+        .call("r3_watch", &[Val::I32(104), Val::I32(104)])
+        .unwrap();
+    runner.call("run", &[Val::I32(100)]).unwrap();
+    let accesses: Vec<String> = events(&original, runner.trace())
+        .into_iter()
+        .filter(|e| e.contains("load") || e.contains("store"))
+        .collect();
+    assert_eq!(
+        accesses,
+        [
+            "i32.store 0x6c <- i32 1",
+            "i64.load 0x68 -> i64 4294967296",
+            "i32.store8 0x64 <- i32 2",
+        ]
+    );
+}
+
+#[test]
+fn sites() {
+    let original = wat::parse_str(MEMORY).unwrap();
+    let sites = Sites::from_buffer(&original).unwrap();
+    for watch in [vec![], vec![16..17, 4096..4100]] {
+        let options = Options {
+            sites: true,
+            watch,
+            ..Options::default()
+        };
+        let (original, instrumented) = instrument(MEMORY, &options);
+        let runner = run_both(
+            &original,
+            &instrumented,
+            &[("copy", &[Val::I32(16), Val::I32(32)])],
+        );
+        let events: Vec<String> = Decoder::from_buffer(&original)
+            .unwrap()
+            .decode_with_sites(runner.trace())
+            .unwrap()
+            .into_iter()
+            .map(|(event, site)| match site {
+                Some(id) => format!("{} at {}", event, sites.get(id).unwrap()),
+                None => event.to_string(),
+            })
+            .collect();
+        // The instructions are counted from the start of the body, the
+        // return appended to it is recorded at the final end
+        let expected = [
+            "export call 0 [i32 16, i32 32]",
+            "enter 0 [i32 16, i32 32]",
+            "i64.load8 0x10 -> i64 42 at function 0 instr 2 @0x",
+            "i64.store8 0x20 <- i64 42 at function 0 instr 3 @0x",
+            "i32.load 0x10 -> i32 42 at function 0 instr 6 @0x",
+            "i32.store 0x24 <- i32 42 at function 0 instr 7 @0x",
+            "return 0 [] at function 0 instr 8 @0x",
+            "export return 0 []",
+        ];
+        let expected: Vec<&str> = if options.watch.is_empty() {
+            expected.to_vec()
+        } else {
+            expected
+                .into_iter()
+                .filter(|e| !e.contains("store"))
+                .collect()
+        };
+        assert_eq!(events.len(), expected.len(), "{:?}", events);
+        for (event, expected) in events.iter().zip(expected) {
+            assert!(event.starts_with(expected), "{} is not {}", event, expected);
+        }
+    }
+}
+
+#[test]
+fn wasabi_dump() {
+    let (original, instrumented) = instrument(CALLS, &Options::default());
+    let runner = run_both(&original, &instrumented, &[("run", &[Val::I32(5)])]);
+    let events = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    assert_eq!(
+        wasabi::dump(&events),
+        [
+            "begin_function func[1] (i32:5)",
+            "global.get global[0] = i64:7",
+            "global.set global[0] = i64:8",
+            "call func[0] (i32:8, i32:42) -> i32:50",
+            "begin_function func[0] (i32:8, i32:42)",
+            "i32.store @0x8 = 42",
+            "return func[0] (i32:50)",
+            "call_indirect func[0] (i32:100, i32:5) -> i32:105",
+            "begin_function func[0] (i32:100, i32:5)",
+            "i32.store @0x64 = 5",
+            "return func[0] (i32:105)",
+            "return func[1] (i32:105)",
+        ]
+    );
+    let (original, instrumented) = instrument(MEMORY, &Options::default());
+    let runner = run_both(
+        &original,
+        &instrumented,
+        &[("store_same", &[Val::I32(128)])],
+    );
+    let events = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    assert_eq!(
+        wasabi::dump(&events)[1..3],
+        ["i32.store @0x80 = 128", "f64.store @0x40 = 1.5"]
+    );
+}
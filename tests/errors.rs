@@ -0,0 +1,79 @@
+//! Modules the tracer cannot instrument are reported, not panicked on.
+
+use r3_tracer::{instrument_wasm, instrument_wasm_with_options, InstrumentError, Options};
+
+fn instrument(wat: &str) -> Result<(), InstrumentError> {
+    instrument_wasm(&wat::parse_str(wat).unwrap()).map(|_| ())
+}
+
+#[test]
+fn simd_values_are_unsupported() {
+    for wat in [
+        r#"(module (func (param v128)))"#,
+        r#"(module (memory 1) (func (drop (v128.load (i32.const 0)))))"#,
+        r#"(module (global $g (mut v128) (v128.const i64x2 0 0))
+             (func (global.set $g (v128.const i64x2 1 1))))"#,
+        r#"(module (func $f (param v128)) (func (call $f (v128.const i64x2 0 0))))"#,
+    ] {
+        match instrument(wat) {
+            Err(
+                e @ InstrumentError::Unsupported {
+                    feature: "simd", ..
+                },
+            ) => {
+                assert!(e
+                    .to_string()
+                    .starts_with("unsupported feature simd in function "))
+            }
+            other => panic!("{}: {:?}", wat, other),
+        }
+    }
+}
+
+#[test]
+fn unknown_references_are_errors() {
+    for wat in [
+        r#"(module (func (call 5)))"#,
+        r#"(module (func (drop (global.get 1))))"#,
+        r#"(module (table 1 funcref) (func (call_indirect (type 3) (i32.const 0))))"#,
+        r#"(module (func (drop (table.get 2 (i32.const 0)))))"#,
+    ] {
+        // walrus checks the references when it reads the module
+        assert!(
+            matches!(instrument(wat), Err(InstrumentError::Parse(_))),
+            "{}",
+            wat
+        );
+    }
+}
+
+#[test]
+fn modules_that_used_to_panic() {
+    // Without functions
+    instrument(r#"(module (memory 1))"#).unwrap();
+    // A `call_indirect` type no function has
+    instrument(
+        r#"
+(module
+  (type $t (func (param i64) (result i64)))
+  (table 1 funcref)
+  (func (drop (call_indirect (type $t) (i64.const 1) (i32.const 0)))))
+"#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn too_many_watch_ranges() {
+    let wasm = wat::parse_str("(module)").unwrap();
+    let options = |count: u32| Options {
+        watch: (0..count).map(|i| i..i + 1).collect(),
+        ..Options::default()
+    };
+    instrument_wasm_with_options(&wasm, &options(8192)).unwrap();
+    let error = instrument_wasm_with_options(&wasm, &options(8193)).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "8193 watched ranges, at most 8192 are supported"
+    );
+}
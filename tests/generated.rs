@@ -0,0 +1,46 @@
+//! Instruments modules generated by `wasm-smith` from a fixed set of seeds.
+//! Run the cargo-fuzz target in `fuzz/` to search further.
+//!
+//! `R3_GENERATED_CASES` overrides the number of modules.
+
+mod common;
+
+use std::env;
+
+const CASES: u64 = 200;
+const INPUT_LEN: usize = 4096;
+
+#[test]
+fn generated_modules() {
+    let cases = env::var("R3_GENERATED_CASES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(CASES);
+    for seed in 0..cases {
+        let data = random_bytes(seed, INPUT_LEN);
+        let result = std::panic::catch_unwind(|| common::generated::check(&data));
+        if let Err(e) = result {
+            std::panic::resume_unwind(Box::new(format!(
+                "seed {}: {}",
+                seed,
+                e.downcast_ref::<String>()
+                    .map(String::as_str)
+                    .or_else(|| e.downcast_ref::<&str>().copied())
+                    .unwrap_or("panic")
+            )));
+        }
+    }
+}
+
+/// xorshift64*, so the cases are the same on every run.
+fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+        })
+        .collect()
+}
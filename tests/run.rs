@@ -0,0 +1,154 @@
+//! Records traces with the embedded wasmtime runner, needs the `run`
+//! feature.
+#![cfg(feature = "run")]
+
+use r3_tracer::{
+    decode::{Decoder, Value},
+    run::record,
+    Options,
+};
+
+const CALLS: &str = r#"
+(module
+  (import "env" "log" (func $log (param i32)))
+  (memory 1)
+  (func $add (param i32 i32) (result i32)
+    (i32.store (local.get 0) (local.get 1))
+    (i32.add (local.get 0) (local.get 1)))
+  (func (export "run") (param i32) (result i32)
+    (call $add (i32.const 8) (local.get 0)))
+  (func (export "log") (param i32)
+    (i32.store (i32.const 4) (local.get 0))
+    (call $log (local.get 0))))
+"#;
+
+fn events(trace: &[u8]) -> Vec<String> {
+    Decoder::from_buffer(CALLS.as_bytes())
+        .unwrap()
+        .decode(trace)
+        .unwrap()
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn records_the_call() {
+    let recording = record(
+        CALLS.as_bytes(),
+        &Options::default(),
+        "run",
+        &args(&["-2"]),
+        Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(recording.results.unwrap(), [Value::I32(6)]);
+    assert_eq!(
+        events(&recording.trace),
+        [
+            "export call 0 [i32 -2]",
+            "enter 2 [i32 -2]",
+            "call 1 [i32 8, i32 -2]",
+            "enter 1 [i32 8, i32 -2]",
+            "i32.store 0x8 <- i32 -2",
+            "return 1 [i32 6]",
+            "call_end (type 1) [i32 6]",
+            "return 2 [i32 6]",
+            "export return 0 [i32 6]",
+        ]
+    );
+}
+
+#[test]
+fn keeps_the_records_before_a_trap() {
+    let recording = record(
+        CALLS.as_bytes(),
+        &Options::default(),
+        "log",
+        &args(&["4294967295"]),
+        Vec::new(),
+    )
+    .unwrap();
+    assert!(recording.results.is_err());
+    assert_eq!(
+        events(&recording.trace),
+        [
+            "export call 1 [i32 -1]",
+            "enter 3 [i32 -1]",
+            "i32.store 0x4 <- i32 -1",
+            "call import 0 [i32 -1]",
+        ]
+    );
+}
+
+#[test]
+fn records_the_trap_info() {
+    let options = Options {
+        trap_info: true,
+        ..Options::default()
+    };
+    let recording = record(CALLS.as_bytes(), &options, "log", &args(&["7"]), Vec::new()).unwrap();
+    assert!(recording.results.is_err());
+    let events = events(&recording.trace);
+    assert_eq!(events[3], "call import 0 [i32 7]");
+    assert!(
+        events[4].starts_with("trap depth 1 [3 @0x"),
+        "{}",
+        events[4]
+    );
+    assert_eq!(events.len(), 5);
+}
+
+#[test]
+fn reassembles_the_ring_buffer() {
+    let options = Options {
+        ring_buffer: Some(64),
+        ..Options::default()
+    };
+    let recording = record(CALLS.as_bytes(), &options, "run", &args(&["1"]), Vec::new()).unwrap();
+    let events = events(&recording.trace);
+    assert_eq!(events.last().unwrap(), "export return 0 [i32 9]");
+}
+
+#[test]
+fn checks_the_arguments() {
+    let run = |export: &str, args: &[String]| {
+        record(
+            CALLS.as_bytes(),
+            &Options::default(),
+            export,
+            args,
+            Vec::new(),
+        )
+        .err()
+        .unwrap()
+        .to_string()
+    };
+    assert_eq!(run("run", &[]), "run takes 1 arguments, got 0");
+    assert_eq!(
+        run("missing", &[]),
+        "the module has no exported function \"missing\""
+    );
+    assert_eq!(run("run", &args(&["x"])), "argument \"x\"");
+}
+
+#[test]
+fn rejects_wasi_imports() {
+    let wasm = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "run")))
+"#;
+    let error = record(wasm.as_bytes(), &Options::default(), "run", &[], Vec::new())
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "the module imports wasi_snapshot_preview1.fd_write, WASI is not available in the runner"
+    );
+}
@@ -0,0 +1,119 @@
+//! Instruments every module in `tests/*.wat` and compares the result, printed
+//! as WAT, with the expectation in `tests/snapshots/`.
+//!
+//! Run with `UPDATE_SNAPSHOTS=1` to write the current output as the new
+//! expectation after a change to the instrumentation.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use r3_tracer::{instrument_wasm_with_options, text, Options, Sampling};
+
+const CONTEXT_LINES: usize = 3;
+
+#[test]
+fn corpus() {
+    let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&tests)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension().is_some_and(|e| e == "wat")
+                && !path.to_string_lossy().ends_with("-instrumented.wat")
+        })
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no modules in {}", tests.display());
+    let failures: Vec<String> = inputs
+        .iter()
+        .filter_map(|input| check(input, "", &Options::default()))
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn options() {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/call.wat");
+    let variants = [
+        (
+            "ring",
+            Options {
+                ring_buffer: Some(1024),
+                ..Options::default()
+            },
+        ),
+        (
+            "control",
+            Options {
+                runtime_control: true,
+                ..Options::default()
+            },
+        ),
+        (
+            "sampling",
+            Options {
+                sampling: Some(Sampling::EveryNth(4)),
+                ..Options::default()
+            },
+        ),
+    ];
+    let failures: Vec<String> = variants
+        .iter()
+        .filter_map(|(name, options)| check(&input, name, options))
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Compares the instrumented module with its snapshot, returns a description
+/// of the difference.
+fn check(input: &Path, variant: &str, options: &Options) -> Option<String> {
+    let stem = input.file_stem().unwrap().to_string_lossy();
+    let name = match variant {
+        "" => format!("{}.wat", stem),
+        variant => format!("{}.{}.wat", stem, variant),
+    };
+    let snapshot = input.with_file_name("snapshots").join(name);
+    let mut module = match instrument_wasm_with_options(&fs::read(input).unwrap(), options) {
+        Ok(module) => module,
+        Err(e) => return Some(format!("{}: {}", snapshot.display(), e)),
+    };
+    let actual = text::emit_wat(&mut module).unwrap();
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(snapshot.parent().unwrap()).unwrap();
+        fs::write(&snapshot, &actual).unwrap();
+        return None;
+    }
+    let expected = match fs::read_to_string(&snapshot) {
+        Ok(expected) => expected,
+        Err(e) => {
+            return Some(format!(
+                "{}: {}, run with UPDATE_SNAPSHOTS=1 to create it",
+                snapshot.display(),
+                e
+            ))
+        }
+    };
+    (expected != actual).then(|| format!("{}\n{}", snapshot.display(), diff(&expected, &actual)))
+}
+
+/// The lines around the first difference.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let first = expected
+        .iter()
+        .zip(&actual)
+        .position(|(e, a)| e != a)
+        .unwrap_or(expected.len().min(actual.len()));
+    let start = first.saturating_sub(CONTEXT_LINES);
+    let mut out = format!("first difference at line {}:\n", first + 1);
+    for line in &expected[start..(first + CONTEXT_LINES).min(expected.len())] {
+        out.push_str(&format!("- {}\n", line));
+    }
+    for line in &actual[start..(first + CONTEXT_LINES).min(actual.len())] {
+        out.push_str(&format!("+ {}\n", line));
+    }
+    out
+}
@@ -0,0 +1,151 @@
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32)))
+  (type (;2;) (func (param i32 i32)))
+  (type (;3;) (func (param i32 i32) (result f64)))
+  (func (;0;) (type 2) (param i32 i32)
+    (local i32 i32 f64)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    i32.store offset=9  ;; r3
+    i32.const 13  ;; r3
+    call 2  ;; r3
+    block ;; label = @1  ;; r3
+      local.get 0
+      local.get 1
+      global.get 0  ;; r3
+      i32.const 16  ;; r3
+      i32.store8  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store offset=1  ;; r3
+      local.set 2  ;; r3
+      global.get 0  ;; r3
+      local.get 2  ;; r3
+      i32.store offset=9  ;; r3
+      local.set 3  ;; r3
+      global.get 0  ;; r3
+      local.get 3  ;; r3
+      i32.store offset=5  ;; r3
+      local.get 3  ;; r3
+      local.get 2  ;; r3
+      i32.const 13  ;; r3
+      call 2  ;; r3
+      call $foo
+      global.get 0  ;; r3
+      i32.const 11  ;; r3
+      i32.store8  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store offset=1  ;; r3
+      local.set 4  ;; r3
+      global.get 0  ;; r3
+      local.get 4  ;; r3
+      f64.store offset=5  ;; r3
+      local.get 4  ;; r3
+      i32.const 13  ;; r3
+      call 2  ;; r3
+      drop
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store offset=1  ;; r3
+    i32.const 5  ;; r3
+    call 2  ;; r3
+    return  ;; r3
+  )
+  (func $foo (;1;) (type 3) (param i32 i32) (result f64)
+    (local f64)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    i32.store offset=9  ;; r3
+    i32.const 13  ;; r3
+    call 2  ;; r3
+    block (result f64) ;; label = @1  ;; r3
+      f64.const 0x0p+0 (;=0;)
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store offset=1  ;; r3
+    local.set 2  ;; r3
+    global.get 0  ;; r3
+    local.get 2  ;; r3
+    f64.store offset=5  ;; r3
+    local.get 2  ;; r3
+    i32.const 13  ;; r3
+    call 2  ;; r3
+    return  ;; r3
+  )
+  (func (;2;) (type 1) (param i32)
+    (local i32)
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.add  ;; r3
+    local.get 0  ;; r3
+    i32.store16  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.add  ;; r3
+    i32.const 2  ;; r3
+    i32.add  ;; r3
+    local.tee 1  ;; r3
+    global.get 3  ;; r3
+    i32.gt_u  ;; r3
+    if ;; label = @1  ;; r3
+      local.get 1  ;; r3
+      global.set 2  ;; r3
+      global.get 1  ;; r3
+      i32.const 1  ;; r3
+      i32.add  ;; r3
+      global.set 1  ;; r3
+      i32.const 0  ;; r3
+      global.set 5  ;; r3
+      i32.const 0  ;; r3
+      local.set 1  ;; r3
+    else
+    end
+    local.get 1  ;; r3
+    global.set 0  ;; r3
+  )
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) i32.const 0)
+  (global (;1;) (mut i32) i32.const 0)
+  (global (;2;) (mut i32) i32.const 0)
+  (global (;3;) i32 i32.const 1009)
+  (global (;4;) i32 i32.const 1024)
+  (global (;5;) (mut i32) i32.const 0)
+  (export "trace" (memory 0))
+  (export "trace_byte_length" (global 0))
+  (export "trace_wrap_count" (global 1))
+  (export "trace_ring_end" (global 2))
+  (export "trace_ring_limit" (global 3))
+  (export "trace_ring_size" (global 4))
+  (export "trace_ring_written" (global 5))
+  (@producers
+    (processed-by "walrus" "0.20.3")
+  )
+)
\ No newline at end of file
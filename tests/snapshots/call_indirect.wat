@@ -0,0 +1,132 @@
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32 f32) (result f64)))
+  (import "r3" "check_mem" (func (;0;) (type 0)))
+  (func (;1;) (type 0)
+    (local i32 i32 f32 f64)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 0  ;; r3
+    i32.const 5  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    block ;; label = @1  ;; r3
+      i32.const 0
+      f32.const 0x0p+0 (;=0;)
+      i32.const 0
+      global.get 0  ;; r3
+      i32.const 17  ;; r3
+      i32.store8  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store offset=1  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store offset=5  ;; r3
+      local.set 0  ;; r3
+      global.get 0  ;; r3
+      local.get 0  ;; r3
+      i32.store offset=17  ;; r3
+      local.set 2  ;; r3
+      global.get 0  ;; r3
+      local.get 2  ;; r3
+      f32.store offset=13  ;; r3
+      local.set 1  ;; r3
+      global.get 0  ;; r3
+      local.get 1  ;; r3
+      i32.store offset=9  ;; r3
+      local.get 1  ;; r3
+      local.get 2  ;; r3
+      local.get 0  ;; r3
+      global.get 0  ;; r3
+      i32.const 21  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      call_indirect (type 1)
+      global.get 0  ;; r3
+      i32.const 11  ;; r3
+      i32.store8  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store offset=1  ;; r3
+      local.set 3  ;; r3
+      global.get 0  ;; r3
+      local.get 3  ;; r3
+      f64.store offset=5  ;; r3
+      local.get 3  ;; r3
+      global.get 0  ;; r3
+      i32.const 13  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      drop
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 0  ;; r3
+    i32.const 5  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    call 0  ;; r3
+    i32.const 0  ;; r3
+    global.set 0  ;; r3
+    return  ;; r3
+  )
+  (func $foo (;2;) (type 1) (param i32 f32) (result f64)
+    (local f64)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    f32.store offset=9  ;; r3
+    global.get 0  ;; r3
+    i32.const 13  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    block (result f64) ;; label = @1  ;; r3
+      f64.const 0x1p+0 (;=1;)
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store offset=1  ;; r3
+    local.set 2  ;; r3
+    global.get 0  ;; r3
+    local.get 2  ;; r3
+    f64.store offset=5  ;; r3
+    local.get 2  ;; r3
+    global.get 0  ;; r3
+    i32.const 13  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    call 0  ;; r3
+    i32.const 0  ;; r3
+    global.set 0  ;; r3
+    return  ;; r3
+  )
+  (table (;0;) 1 funcref)
+  (memory (;0;) 30000)
+  (global (;0;) (mut i32) i32.const 0)
+  (export "trace" (memory 0))
+  (export "trace_byte_length" (global 0))
+  (elem (;0;) (i32.const 0) func $foo)
+  (@producers
+    (processed-by "walrus" "0.20.3")
+  )
+)
\ No newline at end of file
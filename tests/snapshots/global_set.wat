@@ -0,0 +1,58 @@
+(module
+  (type (;0;) (func))
+  (import "r3" "check_mem" (func (;0;) (type 0)))
+  (func (;1;) (type 0)
+    (local i32)
+    global.get 1  ;; r3
+    i32.const 2  ;; r3
+    i32.store8  ;; r3
+    global.get 1  ;; r3
+    i32.const 0  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 1  ;; r3
+    i32.const 5  ;; r3
+    i32.add  ;; r3
+    global.set 1  ;; r3
+    block ;; label = @1  ;; r3
+      i32.const 1
+      global.get 1  ;; r3
+      i32.const 36  ;; r3
+      i32.store8  ;; r3
+      global.get 1  ;; r3
+      i32.const 0  ;; r3
+      i32.store offset=1  ;; r3
+      local.set 0  ;; r3
+      global.get 1  ;; r3
+      local.get 0  ;; r3
+      i32.store offset=5  ;; r3
+      local.get 0  ;; r3
+      global.set 0
+      global.get 1  ;; r3
+      i32.const 9  ;; r3
+      i32.add  ;; r3
+      global.set 1  ;; r3
+    end
+    global.get 1  ;; r3
+    i32.const 15  ;; r3
+    i32.store8  ;; r3
+    global.get 1  ;; r3
+    i32.const 0  ;; r3
+    i32.store offset=1  ;; r3
+    global.get 1  ;; r3
+    i32.const 5  ;; r3
+    i32.add  ;; r3
+    global.set 1  ;; r3
+    call 0  ;; r3
+    i32.const 0  ;; r3
+    global.set 1  ;; r3
+    return  ;; r3
+  )
+  (memory (;0;) 30000)
+  (global (;0;) (mut i32) i32.const 0)
+  (global (;1;) (mut i32) i32.const 0)
+  (export "trace" (memory 0))
+  (export "trace_byte_length" (global 1))
+  (@producers
+    (processed-by "walrus" "0.20.3")
+  )
+)
\ No newline at end of file
@@ -0,0 +1,1564 @@
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32 i32)))
+  (type (;2;) (func (param i32 i32 i32) (result i32)))
+  (import "env" "memory" (memory (;0;) 64 64))
+  (import "r3" "check_mem" (func (;0;) (type 0)))
+  (func $_gol (;1;) (type 2) (param i32 i32 i32) (result i32)
+    (local i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store 1 offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    i32.store 1 offset=9  ;; r3
+    global.get 0  ;; r3
+    local.get 2  ;; r3
+    i32.store 1 offset=13  ;; r3
+    global.get 0  ;; r3
+    i32.const 17  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    block (result i32) ;; label = @1  ;; r3
+      i32.const 1024
+      i32.const 0
+      global.get 0  ;; r3
+      i32.const 54  ;; r3
+      i32.store8 1  ;; r3
+      local.set 11  ;; r3
+      global.get 0  ;; r3
+      local.get 11  ;; r3
+      i32.store 1 offset=5  ;; r3
+      local.set 12  ;; r3
+      global.get 0  ;; r3
+      local.get 12  ;; r3
+      i32.store 1 offset=1  ;; r3
+      local.get 12  ;; r3
+      local.get 11  ;; r3
+      i32.store
+      global.get 0  ;; r3
+      i32.const 9  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      i32.const 1028
+      local.get 1
+      global.get 0  ;; r3
+      i32.const 54  ;; r3
+      i32.store8 1  ;; r3
+      local.set 13  ;; r3
+      global.get 0  ;; r3
+      local.get 13  ;; r3
+      i32.store 1 offset=5  ;; r3
+      local.set 10  ;; r3
+      global.get 0  ;; r3
+      local.get 10  ;; r3
+      i32.store 1 offset=1  ;; r3
+      local.get 10  ;; r3
+      local.get 13  ;; r3
+      i32.store
+      global.get 0  ;; r3
+      i32.const 9  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      block (result i32) ;; label = @2
+        block ;; label = @3
+          local.get 2
+          i32.const 0
+          i32.gt_s
+          local.tee 5
+          i32.eqz
+          br_if 0 (;@3;)
+          i32.const 0
+          local.set 1
+          loop ;; label = @4
+            local.get 1
+            i32.const 3
+            i32.shl
+            local.get 0
+            i32.add
+            local.tee 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            i32.load
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const -1
+            i32.add
+            local.get 1
+            i32.const 3
+            i32.shl
+            local.get 0
+            i32.add
+            local.tee 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const -1
+            i32.add
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            i32.load
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const -1
+            i32.add
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            i32.load
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const 1
+            i32.add
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const -1
+            i32.add
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            i32.load
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const -1
+            i32.add
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            i32.load
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const 1
+            i32.add
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            i32.load
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const -1
+            i32.add
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const 1
+            i32.add
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            i32.load
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const 1
+            i32.add
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 3
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 13  ;; r3
+            i32.load
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const 1
+            i32.add
+            local.get 4
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            i32.const 1
+            i32.add
+            global.get 0  ;; r3
+            i32.const 16  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=9  ;; r3
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 10  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            i32.const 13  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            call $func0
+            global.get 0  ;; r3
+            i32.const 11  ;; r3
+            i32.store8 1  ;; r3
+            global.get 0  ;; r3
+            i32.const 0  ;; r3
+            i32.store 1 offset=1  ;; r3
+            global.get 0  ;; r3
+            i32.const 5  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 1
+            i32.const 1
+            i32.add
+            local.tee 1
+            local.get 2
+            i32.ne
+            br_if 0 (;@4;)
+          end
+          local.get 5
+          i32.eqz
+          br_if 0 (;@3;)
+          i32.const 1024
+          global.get 0  ;; r3
+          i32.const 40  ;; r3
+          i32.store8 1  ;; r3
+          local.set 11  ;; r3
+          global.get 0  ;; r3
+          local.get 11  ;; r3
+          i32.store 1 offset=1  ;; r3
+          local.get 11  ;; r3
+          i32.load
+          local.set 12  ;; r3
+          global.get 0  ;; r3
+          local.get 12  ;; r3
+          i32.store 1 offset=5  ;; r3
+          local.get 12  ;; r3
+          global.get 0  ;; r3
+          i32.const 9  ;; r3
+          i32.add  ;; r3
+          global.set 0  ;; r3
+          local.tee 4
+          i32.const 0
+          i32.gt_s
+          local.set 6
+          i32.const 1028
+          global.get 0  ;; r3
+          i32.const 40  ;; r3
+          i32.store8 1  ;; r3
+          local.set 13  ;; r3
+          global.get 0  ;; r3
+          local.get 13  ;; r3
+          i32.store 1 offset=1  ;; r3
+          local.get 13  ;; r3
+          i32.load
+          local.set 10  ;; r3
+          global.get 0  ;; r3
+          local.get 10  ;; r3
+          i32.store 1 offset=5  ;; r3
+          local.get 10  ;; r3
+          global.get 0  ;; r3
+          i32.const 9  ;; r3
+          i32.add  ;; r3
+          global.set 0  ;; r3
+          local.set 5
+          i32.const 0
+          local.set 3
+          loop (result i32) ;; label = @4
+            local.get 6
+            if ;; label = @5
+              local.get 3
+              i32.const 3
+              i32.shl
+              local.get 0
+              i32.add
+              global.get 0  ;; r3
+              i32.const 40  ;; r3
+              i32.store8 1  ;; r3
+              local.set 11  ;; r3
+              global.get 0  ;; r3
+              local.get 11  ;; r3
+              i32.store 1 offset=1  ;; r3
+              local.get 11  ;; r3
+              i32.load
+              local.set 12  ;; r3
+              global.get 0  ;; r3
+              local.get 12  ;; r3
+              i32.store 1 offset=5  ;; r3
+              local.get 12  ;; r3
+              global.get 0  ;; r3
+              i32.const 9  ;; r3
+              i32.add  ;; r3
+              global.set 0  ;; r3
+              local.set 7
+              local.get 3
+              i32.const 3
+              i32.shl
+              local.get 0
+              i32.add
+              local.set 8
+              i32.const 0
+              local.set 1
+              loop ;; label = @6
+                local.get 7
+                local.get 1
+                i32.const 12
+                i32.mul
+                local.get 5
+                i32.add
+                global.get 0  ;; r3
+                i32.const 40  ;; r3
+                i32.store8 1  ;; r3
+                local.set 13  ;; r3
+                global.get 0  ;; r3
+                local.get 13  ;; r3
+                i32.store 1 offset=1  ;; r3
+                local.get 13  ;; r3
+                i32.load
+                local.set 10  ;; r3
+                global.get 0  ;; r3
+                local.get 10  ;; r3
+                i32.store 1 offset=5  ;; r3
+                local.get 10  ;; r3
+                global.get 0  ;; r3
+                i32.const 9  ;; r3
+                i32.add  ;; r3
+                global.set 0  ;; r3
+                i32.eq
+                if ;; label = @7
+                  local.get 1
+                  i32.const 12
+                  i32.mul
+                  local.get 5
+                  i32.add
+                  global.get 0  ;; r3
+                  i32.const 40  ;; r3
+                  i32.store8 1  ;; r3
+                  local.set 11  ;; r3
+                  global.get 0  ;; r3
+                  local.get 11  ;; r3
+                  i32.store 1 offset=1  ;; r3
+                  local.get 11  ;; r3
+                  global.get 0  ;; r3
+                  local.get 11  ;; r3
+                  i32.const 4  ;; r3
+                  i32.add  ;; r3
+                  i32.store 1 offset=1  ;; r3
+                  i32.load offset=4
+                  local.set 12  ;; r3
+                  global.get 0  ;; r3
+                  local.get 12  ;; r3
+                  i32.store 1 offset=5  ;; r3
+                  local.get 12  ;; r3
+                  global.get 0  ;; r3
+                  i32.const 9  ;; r3
+                  i32.add  ;; r3
+                  global.set 0  ;; r3
+                  local.get 8
+                  global.get 0  ;; r3
+                  i32.const 40  ;; r3
+                  i32.store8 1  ;; r3
+                  local.set 13  ;; r3
+                  global.get 0  ;; r3
+                  local.get 13  ;; r3
+                  i32.store 1 offset=1  ;; r3
+                  local.get 13  ;; r3
+                  global.get 0  ;; r3
+                  local.get 13  ;; r3
+                  i32.const 4  ;; r3
+                  i32.add  ;; r3
+                  i32.store 1 offset=1  ;; r3
+                  i32.load offset=4
+                  local.set 10  ;; r3
+                  global.get 0  ;; r3
+                  local.get 10  ;; r3
+                  i32.store 1 offset=5  ;; r3
+                  local.get 10  ;; r3
+                  global.get 0  ;; r3
+                  i32.const 9  ;; r3
+                  i32.add  ;; r3
+                  global.set 0  ;; r3
+                  i32.eq
+                  if ;; label = @8
+                    local.get 1
+                    i32.const 12
+                    i32.mul
+                    local.get 5
+                    i32.add
+                    local.tee 9
+                    global.get 0  ;; r3
+                    i32.const 40  ;; r3
+                    i32.store8 1  ;; r3
+                    local.set 11  ;; r3
+                    global.get 0  ;; r3
+                    local.get 11  ;; r3
+                    i32.store 1 offset=1  ;; r3
+                    local.get 11  ;; r3
+                    global.get 0  ;; r3
+                    local.get 11  ;; r3
+                    i32.const 8  ;; r3
+                    i32.add  ;; r3
+                    i32.store 1 offset=1  ;; r3
+                    i32.load offset=8
+                    local.set 12  ;; r3
+                    global.get 0  ;; r3
+                    local.get 12  ;; r3
+                    i32.store 1 offset=5  ;; r3
+                    local.get 12  ;; r3
+                    global.get 0  ;; r3
+                    i32.const 9  ;; r3
+                    i32.add  ;; r3
+                    global.set 0  ;; r3
+                    i32.const 2
+                    i32.eq
+                    if ;; label = @9
+                      local.get 9
+                      i32.const 3
+                      global.get 0  ;; r3
+                      i32.const 54  ;; r3
+                      i32.store8 1  ;; r3
+                      local.set 13  ;; r3
+                      global.get 0  ;; r3
+                      local.get 13  ;; r3
+                      i32.store 1 offset=5  ;; r3
+                      local.set 10  ;; r3
+                      global.get 0  ;; r3
+                      local.get 10  ;; r3
+                      i32.store 1 offset=1  ;; r3
+                      local.get 10  ;; r3
+                      local.get 13  ;; r3
+                      global.get 0  ;; r3
+                      local.get 10  ;; r3
+                      i32.const 8  ;; r3
+                      i32.add  ;; r3
+                      i32.store 1 offset=1  ;; r3
+                      i32.store offset=8
+                      global.get 0  ;; r3
+                      i32.const 9  ;; r3
+                      i32.add  ;; r3
+                      global.set 0  ;; r3
+                    else
+                    end
+                  else
+                  end
+                else
+                end
+                local.get 1
+                i32.const 1
+                i32.add
+                local.tee 1
+                local.get 4
+                i32.ne
+                br_if 0 (;@6;)
+              end
+            else
+            end
+            local.get 3
+            i32.const 1
+            i32.add
+            local.tee 3
+            local.get 2
+            i32.ne
+            br_if 0 (;@4;)
+            local.get 4
+          end
+          br 1 (;@2;)
+        end
+        i32.const 1024
+        global.get 0  ;; r3
+        i32.const 40  ;; r3
+        i32.store8 1  ;; r3
+        local.set 13  ;; r3
+        global.get 0  ;; r3
+        local.get 13  ;; r3
+        i32.store 1 offset=1  ;; r3
+        local.get 13  ;; r3
+        i32.load
+        local.set 10  ;; r3
+        global.get 0  ;; r3
+        local.get 10  ;; r3
+        i32.store 1 offset=5  ;; r3
+        local.get 10  ;; r3
+        global.get 0  ;; r3
+        i32.const 9  ;; r3
+        i32.add  ;; r3
+        global.set 0  ;; r3
+      end
+      local.tee 2
+      i32.const 0
+      i32.gt_s
+      if ;; label = @2
+        i32.const 1028
+        global.get 0  ;; r3
+        i32.const 40  ;; r3
+        i32.store8 1  ;; r3
+        local.set 11  ;; r3
+        global.get 0  ;; r3
+        local.get 11  ;; r3
+        i32.store 1 offset=1  ;; r3
+        local.get 11  ;; r3
+        i32.load
+        local.set 12  ;; r3
+        global.get 0  ;; r3
+        local.get 12  ;; r3
+        i32.store 1 offset=5  ;; r3
+        local.get 12  ;; r3
+        global.get 0  ;; r3
+        i32.const 9  ;; r3
+        i32.add  ;; r3
+        global.set 0  ;; r3
+        local.set 4
+        i32.const 0
+        local.set 1
+        i32.const 0
+        local.set 3
+        loop ;; label = @3
+          local.get 3
+          i32.const 12
+          i32.mul
+          local.get 4
+          i32.add
+          global.get 0  ;; r3
+          i32.const 40  ;; r3
+          i32.store8 1  ;; r3
+          local.set 13  ;; r3
+          global.get 0  ;; r3
+          local.get 13  ;; r3
+          i32.store 1 offset=1  ;; r3
+          local.get 13  ;; r3
+          global.get 0  ;; r3
+          local.get 13  ;; r3
+          i32.const 8  ;; r3
+          i32.add  ;; r3
+          i32.store 1 offset=1  ;; r3
+          i32.load offset=8
+          local.set 10  ;; r3
+          global.get 0  ;; r3
+          local.get 10  ;; r3
+          i32.store 1 offset=5  ;; r3
+          local.get 10  ;; r3
+          global.get 0  ;; r3
+          i32.const 9  ;; r3
+          i32.add  ;; r3
+          global.set 0  ;; r3
+          i32.const 3
+          i32.eq
+          if ;; label = @4
+            local.get 1
+            i32.const 3
+            i32.shl
+            local.get 0
+            i32.add
+            local.get 3
+            i32.const 12
+            i32.mul
+            local.get 4
+            i32.add
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            i32.load
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            global.get 0  ;; r3
+            i32.const 54  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 10  ;; r3
+            local.get 13  ;; r3
+            i32.store
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 1
+            i32.const 3
+            i32.shl
+            local.get 0
+            i32.add
+            local.get 3
+            i32.const 12
+            i32.mul
+            local.get 4
+            i32.add
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.load offset=4
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            global.get 0  ;; r3
+            i32.const 54  ;; r3
+            i32.store8 1  ;; r3
+            local.set 13  ;; r3
+            global.get 0  ;; r3
+            local.get 13  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.set 10  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 10  ;; r3
+            local.get 13  ;; r3
+            global.get 0  ;; r3
+            local.get 10  ;; r3
+            i32.const 4  ;; r3
+            i32.add  ;; r3
+            i32.store 1 offset=1  ;; r3
+            i32.store offset=4
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.get 1
+            i32.const 1
+            i32.add
+            local.set 1
+            i32.const 1024
+            global.get 0  ;; r3
+            i32.const 40  ;; r3
+            i32.store8 1  ;; r3
+            local.set 11  ;; r3
+            global.get 0  ;; r3
+            local.get 11  ;; r3
+            i32.store 1 offset=1  ;; r3
+            local.get 11  ;; r3
+            i32.load
+            local.set 12  ;; r3
+            global.get 0  ;; r3
+            local.get 12  ;; r3
+            i32.store 1 offset=5  ;; r3
+            local.get 12  ;; r3
+            global.get 0  ;; r3
+            i32.const 9  ;; r3
+            i32.add  ;; r3
+            global.set 0  ;; r3
+            local.set 2
+          else
+          end
+          local.get 3
+          i32.const 1
+          i32.add
+          local.tee 3
+          local.get 2
+          i32.lt_s
+          br_if 0 (;@3;)
+        end
+      else
+        i32.const 0
+        local.set 1
+      end
+      local.get 1
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store 1 offset=1  ;; r3
+    local.set 10  ;; r3
+    global.get 0  ;; r3
+    local.get 10  ;; r3
+    i32.store 1 offset=5  ;; r3
+    local.get 10  ;; r3
+    global.get 0  ;; r3
+    i32.const 9  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    call 0  ;; r3
+    i32.const 0  ;; r3
+    global.set 0  ;; r3
+    return  ;; r3
+  )
+  (func $func0 (;2;) (type 1) (param i32 i32)
+    (local i32 i32 i32 i32 i32 i32 i32)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store 1 offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    i32.store 1 offset=9  ;; r3
+    global.get 0  ;; r3
+    i32.const 13  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    block ;; label = @1  ;; r3
+      i32.const 1028
+      global.get 0  ;; r3
+      i32.const 40  ;; r3
+      i32.store8 1  ;; r3
+      local.set 5  ;; r3
+      global.get 0  ;; r3
+      local.get 5  ;; r3
+      i32.store 1 offset=1  ;; r3
+      local.get 5  ;; r3
+      i32.load
+      local.set 6  ;; r3
+      global.get 0  ;; r3
+      local.get 6  ;; r3
+      i32.store 1 offset=5  ;; r3
+      local.get 6  ;; r3
+      global.get 0  ;; r3
+      i32.const 9  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      local.set 2
+      block ;; label = @2
+        block ;; label = @3
+          i32.const 1024
+          global.get 0  ;; r3
+          i32.const 40  ;; r3
+          i32.store8 1  ;; r3
+          local.set 5  ;; r3
+          global.get 0  ;; r3
+          local.get 5  ;; r3
+          i32.store 1 offset=1  ;; r3
+          local.get 5  ;; r3
+          i32.load
+          local.set 6  ;; r3
+          global.get 0  ;; r3
+          local.get 6  ;; r3
+          i32.store 1 offset=5  ;; r3
+          local.get 6  ;; r3
+          global.get 0  ;; r3
+          i32.const 9  ;; r3
+          i32.add  ;; r3
+          global.set 0  ;; r3
+          local.tee 4
+          i32.const 0
+          i32.le_s
+          br_if 0 (;@3;)
+          loop ;; label = @4
+            block ;; label = @5
+              local.get 0
+              local.get 3
+              i32.const 12
+              i32.mul
+              local.get 2
+              i32.add
+              global.get 0  ;; r3
+              i32.const 40  ;; r3
+              i32.store8 1  ;; r3
+              local.set 5  ;; r3
+              global.get 0  ;; r3
+              local.get 5  ;; r3
+              i32.store 1 offset=1  ;; r3
+              local.get 5  ;; r3
+              i32.load
+              local.set 6  ;; r3
+              global.get 0  ;; r3
+              local.get 6  ;; r3
+              i32.store 1 offset=5  ;; r3
+              local.get 6  ;; r3
+              global.get 0  ;; r3
+              i32.const 9  ;; r3
+              i32.add  ;; r3
+              global.set 0  ;; r3
+              i32.eq
+              if ;; label = @6
+                local.get 1
+                local.get 3
+                i32.const 12
+                i32.mul
+                local.get 2
+                i32.add
+                global.get 0  ;; r3
+                i32.const 40  ;; r3
+                i32.store8 1  ;; r3
+                local.set 7  ;; r3
+                global.get 0  ;; r3
+                local.get 7  ;; r3
+                i32.store 1 offset=1  ;; r3
+                local.get 7  ;; r3
+                global.get 0  ;; r3
+                local.get 7  ;; r3
+                i32.const 4  ;; r3
+                i32.add  ;; r3
+                i32.store 1 offset=1  ;; r3
+                i32.load offset=4
+                local.set 8  ;; r3
+                global.get 0  ;; r3
+                local.get 8  ;; r3
+                i32.store 1 offset=5  ;; r3
+                local.get 8  ;; r3
+                global.get 0  ;; r3
+                i32.const 9  ;; r3
+                i32.add  ;; r3
+                global.set 0  ;; r3
+                i32.eq
+                br_if 1 (;@5;)
+              else
+              end
+              local.get 3
+              i32.const 1
+              i32.add
+              local.tee 3
+              local.get 4
+              i32.lt_s
+              br_if 1 (;@4;)
+              br 2 (;@3;)
+            end
+          end
+          local.get 3
+          i32.const 12
+          i32.mul
+          local.get 2
+          i32.add
+          i32.const 8
+          i32.add
+          local.tee 0
+          local.set 1
+          local.get 0
+          global.get 0  ;; r3
+          i32.const 40  ;; r3
+          i32.store8 1  ;; r3
+          local.set 7  ;; r3
+          global.get 0  ;; r3
+          local.get 7  ;; r3
+          i32.store 1 offset=1  ;; r3
+          local.get 7  ;; r3
+          i32.load
+          local.set 8  ;; r3
+          global.get 0  ;; r3
+          local.get 8  ;; r3
+          i32.store 1 offset=5  ;; r3
+          local.get 8  ;; r3
+          global.get 0  ;; r3
+          i32.const 9  ;; r3
+          i32.add  ;; r3
+          global.set 0  ;; r3
+          local.set 0
+          br 1 (;@2;)
+        end
+        local.get 4
+        i32.const 12
+        i32.mul
+        local.get 2
+        i32.add
+        local.get 0
+        global.get 0  ;; r3
+        i32.const 54  ;; r3
+        i32.store8 1  ;; r3
+        local.set 5  ;; r3
+        global.get 0  ;; r3
+        local.get 5  ;; r3
+        i32.store 1 offset=5  ;; r3
+        local.set 6  ;; r3
+        global.get 0  ;; r3
+        local.get 6  ;; r3
+        i32.store 1 offset=1  ;; r3
+        local.get 6  ;; r3
+        local.get 5  ;; r3
+        i32.store
+        global.get 0  ;; r3
+        i32.const 9  ;; r3
+        i32.add  ;; r3
+        global.set 0  ;; r3
+        i32.const 1024
+        global.get 0  ;; r3
+        i32.const 40  ;; r3
+        i32.store8 1  ;; r3
+        local.set 7  ;; r3
+        global.get 0  ;; r3
+        local.get 7  ;; r3
+        i32.store 1 offset=1  ;; r3
+        local.get 7  ;; r3
+        i32.load
+        local.set 8  ;; r3
+        global.get 0  ;; r3
+        local.get 8  ;; r3
+        i32.store 1 offset=5  ;; r3
+        local.get 8  ;; r3
+        global.get 0  ;; r3
+        i32.const 9  ;; r3
+        i32.add  ;; r3
+        global.set 0  ;; r3
+        local.tee 0
+        i32.const 12
+        i32.mul
+        local.get 2
+        i32.add
+        local.get 1
+        global.get 0  ;; r3
+        i32.const 54  ;; r3
+        i32.store8 1  ;; r3
+        local.set 5  ;; r3
+        global.get 0  ;; r3
+        local.get 5  ;; r3
+        i32.store 1 offset=5  ;; r3
+        local.set 6  ;; r3
+        global.get 0  ;; r3
+        local.get 6  ;; r3
+        i32.store 1 offset=1  ;; r3
+        local.get 6  ;; r3
+        local.get 5  ;; r3
+        global.get 0  ;; r3
+        local.get 6  ;; r3
+        i32.const 4  ;; r3
+        i32.add  ;; r3
+        i32.store 1 offset=1  ;; r3
+        i32.store offset=4
+        global.get 0  ;; r3
+        i32.const 9  ;; r3
+        i32.add  ;; r3
+        global.set 0  ;; r3
+        local.get 0
+        i32.const 12
+        i32.mul
+        local.get 2
+        i32.add
+        i32.const 1
+        global.get 0  ;; r3
+        i32.const 54  ;; r3
+        i32.store8 1  ;; r3
+        local.set 7  ;; r3
+        global.get 0  ;; r3
+        local.get 7  ;; r3
+        i32.store 1 offset=5  ;; r3
+        local.set 8  ;; r3
+        global.get 0  ;; r3
+        local.get 8  ;; r3
+        i32.store 1 offset=1  ;; r3
+        local.get 8  ;; r3
+        local.get 7  ;; r3
+        global.get 0  ;; r3
+        local.get 8  ;; r3
+        i32.const 8  ;; r3
+        i32.add  ;; r3
+        i32.store 1 offset=1  ;; r3
+        i32.store offset=8
+        global.get 0  ;; r3
+        i32.const 9  ;; r3
+        i32.add  ;; r3
+        global.set 0  ;; r3
+        i32.const 1024
+        local.set 1
+      end
+      local.get 1
+      local.get 0
+      i32.const 1
+      i32.add
+      global.get 0  ;; r3
+      i32.const 54  ;; r3
+      i32.store8 1  ;; r3
+      local.set 7  ;; r3
+      global.get 0  ;; r3
+      local.get 7  ;; r3
+      i32.store 1 offset=5  ;; r3
+      local.set 8  ;; r3
+      global.get 0  ;; r3
+      local.get 8  ;; r3
+      i32.store 1 offset=1  ;; r3
+      local.get 8  ;; r3
+      local.get 7  ;; r3
+      i32.store
+      global.get 0  ;; r3
+      i32.const 9  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    i32.const 5  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    call 0  ;; r3
+    i32.const 0  ;; r3
+    global.set 0  ;; r3
+    return  ;; r3
+  )
+  (func (;3;) (type 2) (param i32 i32 i32) (result i32)
+    (local i32)
+    global.get 0  ;; r3
+    i32.const 20  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store 1 offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    i32.store 1 offset=9  ;; r3
+    global.get 0  ;; r3
+    local.get 2  ;; r3
+    i32.store 1 offset=13  ;; r3
+    i32.const 17  ;; r3
+    global.get 0  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    local.get 0  ;; r3
+    local.get 1  ;; r3
+    local.get 2  ;; r3
+    call $_gol  ;; r3
+    local.set 3  ;; r3
+    global.get 0  ;; r3
+    i32.const 21  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 3  ;; r3
+    i32.store 1 offset=5  ;; r3
+    i32.const 9  ;; r3
+    global.get 0  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    call 0  ;; r3
+    i32.const 0  ;; r3
+    global.set 0  ;; r3
+    local.get 3  ;; r3
+  )
+  (memory (;1;) 30000)
+  (global (;0;) (mut i32) i32.const 0)
+  (export "_gol" (func 3))
+  (export "trace" (memory 1))
+  (export "trace_byte_length" (global 0))
+  (@producers
+    (processed-by "walrus" "0.20.3")
+  )
+)
\ No newline at end of file
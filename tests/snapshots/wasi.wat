@@ -0,0 +1,239 @@
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32) (result i32)))
+  (type (;2;) (func (param i32 i32 i32 i32) (result i32)))
+  (type (;3;) (func (param i32 i32 i32 i32 i32)))
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (;0;) (type 2)))
+  (import "r3" "check_mem" (func (;1;) (type 0)))
+  (func (;2;) (type 3) (param i32 i32 i32 i32 i32)
+    (local i32 i32 i32 i32 i32 i32 i32)
+    local.get 4  ;; r3
+    i32.eqz  ;; r3
+    local.set 5  ;; r3
+    global.get 0  ;; r3
+    i32.const 13  ;; r3
+    i32.add  ;; r3
+    local.set 6  ;; r3
+    i32.const 0  ;; r3
+    local.set 7  ;; r3
+    local.get 5  ;; r3
+    if ;; label = @1  ;; r3
+      local.get 3  ;; r3
+      local.set 8  ;; r3
+      i32.const 4  ;; r3
+      local.set 9  ;; r3
+      local.get 6  ;; r3
+      local.get 8  ;; r3
+      i32.store 1  ;; r3
+      local.get 6  ;; r3
+      local.get 9  ;; r3
+      i32.store 1 offset=4  ;; r3
+      local.get 6  ;; r3
+      i32.const 8  ;; r3
+      i32.add  ;; r3
+      local.get 8  ;; r3
+      local.get 9  ;; r3
+      memory.copy 1 0  ;; r3
+      local.get 6  ;; r3
+      local.get 9  ;; r3
+      i32.add  ;; r3
+      i32.const 8  ;; r3
+      i32.add  ;; r3
+      local.set 6  ;; r3
+      local.get 7  ;; r3
+      i32.const 1  ;; r3
+      i32.add  ;; r3
+      local.set 7  ;; r3
+      local.get 3  ;; r3
+      i32.load  ;; r3
+      local.set 11  ;; r3
+      i32.const 0  ;; r3
+      local.set 10  ;; r3
+      block ;; label = @2  ;; r3
+        loop ;; label = @3  ;; r3
+          local.get 10  ;; r3
+          local.get 2  ;; r3
+          i32.ge_u  ;; r3
+          local.get 11  ;; r3
+          i32.eqz  ;; r3
+          i32.or  ;; r3
+          br_if 1 (;@2;)  ;; r3
+          local.get 1  ;; r3
+          local.get 10  ;; r3
+          i32.const 8  ;; r3
+          i32.mul  ;; r3
+          i32.add  ;; r3
+          local.tee 8  ;; r3
+          i32.load offset=4  ;; r3
+          local.set 9  ;; r3
+          local.get 8  ;; r3
+          i32.load  ;; r3
+          local.set 8  ;; r3
+          local.get 9  ;; r3
+          local.get 11  ;; r3
+          local.get 9  ;; r3
+          local.get 11  ;; r3
+          i32.lt_u  ;; r3
+          select  ;; r3
+          local.set 9  ;; r3
+          local.get 6  ;; r3
+          local.get 8  ;; r3
+          i32.store 1  ;; r3
+          local.get 6  ;; r3
+          local.get 9  ;; r3
+          i32.store 1 offset=4  ;; r3
+          local.get 6  ;; r3
+          i32.const 8  ;; r3
+          i32.add  ;; r3
+          local.get 8  ;; r3
+          local.get 9  ;; r3
+          memory.copy 1 0  ;; r3
+          local.get 6  ;; r3
+          local.get 9  ;; r3
+          i32.add  ;; r3
+          i32.const 8  ;; r3
+          i32.add  ;; r3
+          local.set 6  ;; r3
+          local.get 7  ;; r3
+          i32.const 1  ;; r3
+          i32.add  ;; r3
+          local.set 7  ;; r3
+          local.get 11  ;; r3
+          local.get 9  ;; r3
+          i32.sub  ;; r3
+          local.set 11  ;; r3
+          local.get 10  ;; r3
+          i32.const 1  ;; r3
+          i32.add  ;; r3
+          local.set 10  ;; r3
+          br 0 (;@3;)  ;; r3
+        end
+      end
+    else
+    end
+    global.get 0  ;; r3
+    i32.const 18  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 0  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 4  ;; r3
+    i32.store 1 offset=5  ;; r3
+    global.get 0  ;; r3
+    local.get 7  ;; r3
+    i32.store 1 offset=9  ;; r3
+    local.get 6  ;; r3
+    global.get 0  ;; r3
+    i32.sub  ;; r3
+    global.get 0  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+  )
+  (func (;3;) (type 1) (param i32) (result i32)
+    (local i32 i32 i32 i32 i32)
+    global.get 0  ;; r3
+    i32.const 2  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store 1 offset=1  ;; r3
+    global.get 0  ;; r3
+    local.get 0  ;; r3
+    i32.store 1 offset=5  ;; r3
+    global.get 0  ;; r3
+    i32.const 9  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    block (result i32) ;; label = @1  ;; r3
+      i32.const 0
+      local.get 0
+      i32.const 1
+      i32.const 8
+      global.get 0  ;; r3
+      i32.const 19  ;; r3
+      i32.store8 1  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store 1 offset=1  ;; r3
+      local.set 2  ;; r3
+      global.get 0  ;; r3
+      local.get 2  ;; r3
+      i32.store 1 offset=17  ;; r3
+      local.set 3  ;; r3
+      global.get 0  ;; r3
+      local.get 3  ;; r3
+      i32.store 1 offset=13  ;; r3
+      local.set 4  ;; r3
+      global.get 0  ;; r3
+      local.get 4  ;; r3
+      i32.store 1 offset=9  ;; r3
+      local.set 5  ;; r3
+      global.get 0  ;; r3
+      local.get 5  ;; r3
+      i32.store 1 offset=5  ;; r3
+      local.get 5  ;; r3
+      local.get 4  ;; r3
+      local.get 3  ;; r3
+      local.get 2  ;; r3
+      global.get 0  ;; r3
+      i32.const 21  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      call $fd_read
+      global.get 0  ;; r3
+      i32.const 11  ;; r3
+      i32.store8 1  ;; r3
+      global.get 0  ;; r3
+      i32.const 0  ;; r3
+      i32.store 1 offset=1  ;; r3
+      local.set 1  ;; r3
+      global.get 0  ;; r3
+      local.get 1  ;; r3
+      i32.store 1 offset=5  ;; r3
+      local.get 1  ;; r3
+      global.get 0  ;; r3
+      i32.const 9  ;; r3
+      i32.add  ;; r3
+      global.set 0  ;; r3
+      local.get 5  ;; r3
+      local.get 4  ;; r3
+      local.get 3  ;; r3
+      local.get 2  ;; r3
+      local.get 1  ;; r3
+      call 2  ;; r3
+    end
+    global.get 0  ;; r3
+    i32.const 15  ;; r3
+    i32.store8 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 1  ;; r3
+    i32.store 1 offset=1  ;; r3
+    local.set 1  ;; r3
+    global.get 0  ;; r3
+    local.get 1  ;; r3
+    i32.store 1 offset=5  ;; r3
+    local.get 1  ;; r3
+    global.get 0  ;; r3
+    i32.const 9  ;; r3
+    i32.add  ;; r3
+    global.set 0  ;; r3
+    call 1  ;; r3
+    i32.const 0  ;; r3
+    global.set 0  ;; r3
+    return  ;; r3
+  )
+  (memory (;0;) 1)
+  (memory (;1;) 30000)
+  (global (;0;) (mut i32) i32.const 0)
+  (global (;1;) (mut i32) i32.const 0)
+  (global (;2;) (mut i32) i32.const 0)
+  (global (;3;) (mut i32) i32.const 0)
+  (global (;4;) (mut i32) i32.const 0)
+  (export "memory" (memory 0))
+  (export "trace" (memory 1))
+  (export "trace_byte_length" (global 0))
+  (@producers
+    (processed-by "walrus" "0.20.3")
+  )
+)
\ No newline at end of file
@@ -0,0 +1,152 @@
+//! Reconstructs the state at events of a recorded trace.
+
+mod common;
+
+use common::run_both;
+use r3_tracer::{
+    decode::{Decoder, Event, Value},
+    instrument_wasm_with_options,
+    state::{last_write, StateAt},
+    Options,
+};
+use wasmi::Val;
+
+const WAT: &str = r#"
+(module
+  (global $g (mut i64) (i64.const 1))
+  (table 2 funcref)
+  (memory (export "memory") 1)
+  (data (i32.const 64) "\07")
+  (func $f)
+  (elem declare func $f)
+  (func (export "write") (param i32 i32)
+    (i32.store (local.get 0) (local.get 1))
+    (global.set $g (i64.extend_i32_u (local.get 1)))
+    (table.set (i32.const 1) (ref.func $f))))
+"#;
+
+fn record(options: &Options, calls: &[(&str, &[Val])]) -> Vec<Event> {
+    let original = wat::parse_str(WAT).unwrap();
+    let instrumented = instrument_wasm_with_options(&original, options)
+        .unwrap()
+        .emit_wasm();
+    let runner = run_both(&original, &instrumented, calls);
+    Decoder::new(&walrus::Module::from_buffer(&original).unwrap())
+        .decode(runner.trace())
+        .unwrap()
+}
+
+fn position(events: &[Event], f: impl Fn(&Event) -> bool) -> usize {
+    events.iter().position(f).unwrap()
+}
+
+#[test]
+fn state_before_and_after_a_store() {
+    let options = Options {
+        snapshot_memory: true,
+        ..Options::default()
+    };
+    let events = record(&options, &[("write", &[Val::I32(64), Val::I32(9)])]);
+    let store = position(&events, |e| matches!(e, Event::Store { .. }));
+    let before = StateAt::at(&events, store);
+    assert_eq!(before.snapshot, Some(0));
+    assert_eq!(before.read(64, 4), Some(&[7, 0, 0, 0][..]));
+    assert_eq!(before.globals[&0], Value::I64(1));
+    assert_eq!(before.tables[0][1], Value::Ref { is_null: true });
+    let after = StateAt::at(&events, events.len());
+    assert_eq!(after.read(64, 4), Some(&[9, 0, 0, 0][..]));
+    assert_eq!(after.globals[&0], Value::I64(9));
+    assert_eq!(after.tables[0][1], Value::Ref { is_null: false });
+    assert_eq!(after.memory.len(), 64 * 1024);
+}
+
+#[test]
+fn finds_the_last_write() {
+    let options = Options {
+        snapshot_memory: true,
+        checkpoint_every: Some(3),
+        ..Options::default()
+    };
+    let calls: Vec<[Val; 2]> = [(64, 1), (100, 2), (66, 3), (200, 4)]
+        .iter()
+        .map(|(addr, value)| [Val::I32(*addr), Val::I32(*value)])
+        .collect();
+    let calls: Vec<(&str, &[Val])> = calls.iter().map(|args| ("write", &args[..])).collect();
+    let events = record(&options, &calls);
+    let stores: Vec<usize> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| matches!(e, Event::Store { .. }))
+        .map(|(i, _)| i)
+        .collect();
+    // The store to 66 overwrote the upper bytes of the one to 64
+    assert_eq!(last_write(&events, 64, events.len()), Some(stores[0]));
+    assert_eq!(last_write(&events, 66, events.len()), Some(stores[2]));
+    assert_eq!(last_write(&events, 66, stores[2]), Some(stores[0]));
+    assert_eq!(last_write(&events, 68, events.len()), Some(stores[2]));
+    assert_eq!(last_write(&events, 70, events.len()), None);
+    assert_eq!(last_write(&events, 0, events.len()), None);
+    // From a checkpoint in the middle of the trace
+    let end = StateAt::at(&events, events.len());
+    assert!(matches!(
+        events[end.snapshot.unwrap()],
+        Event::Checkpoint { .. }
+    ));
+    assert_eq!(end.read(64, 6), Some(&[1, 0, 3, 0, 0, 0][..]));
+    assert_eq!(end.read(200, 4), Some(&[4, 0, 0, 0][..]));
+    assert_eq!(end.globals[&0], Value::I64(4));
+}
+
+#[test]
+fn bulk_instructions_update_the_state() {
+    let wat = r#"
+(module
+  (memory (export "memory") 1 2)
+  (data $d "hello")
+  (table $t 2 4 funcref)
+  (elem $e func $f)
+  (func $f)
+  (func (export "run")
+    (memory.fill (i32.const 16) (i32.const 0xab) (i32.const 8))
+    (memory.copy (i32.const 20) (i32.const 16) (i32.const 8))
+    (memory.init $d (i32.const 100) (i32.const 0) (i32.const 5))
+    (drop (memory.grow (i32.const 1)))
+    (i32.store (i32.const 70000) (i32.const 1))
+    (table.init $t $e (i32.const 1) (i32.const 0) (i32.const 1))
+    (drop (table.grow (ref.null func) (i32.const 1)))))
+"#;
+    let original = wat::parse_str(wat).unwrap();
+    let options = Options {
+        snapshot_memory: true,
+        ..Options::default()
+    };
+    let instrumented = instrument_wasm_with_options(&original, &options)
+        .unwrap()
+        .emit_wasm();
+    let runner = run_both(&original, &instrumented, &[("run", &[])]);
+    let events = Decoder::from_buffer(&original)
+        .unwrap()
+        .decode(runner.trace())
+        .unwrap();
+    let end = StateAt::at(&events, events.len());
+    assert!(!end.incomplete);
+    assert_eq!(Some(end.memory), runner.memory("memory"));
+    let null = |is_null| Value::Ref { is_null };
+    assert_eq!(end.tables, [vec![null(true), null(false), null(true)]]);
+    let copy = position(&events, |e| e.to_string().starts_with("memory.copy"));
+    assert_eq!(last_write(&events, 27, events.len()), Some(copy));
+}
+
+#[test]
+fn unrecorded_ranges_make_the_state_incomplete() {
+    let events = [Event::BulkMemory {
+        opcode: 0x0B,
+        addr: 16,
+        len: 1000,
+        bytes: None,
+    }];
+    let state = StateAt::at(&events, 1);
+    assert!(state.incomplete);
+    assert_eq!(last_write(&events, 1015, 1), Some(0));
+    assert_eq!(last_write(&events, 1016, 1), None);
+}
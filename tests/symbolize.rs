@@ -0,0 +1,154 @@
+//! Names in the events, frames and sites of hand written traces.
+
+use r3_tracer::{
+    call_tree::CallTree,
+    decode::{Event, TrapFrame, Value},
+    sites::Sites,
+    symbolize::{demangle, Symbols},
+};
+
+const WAT: &str = r#"
+(module
+  (import "env" "log" (func (param i32)))
+  (global $counter (mut i32) (i32.const 0))
+  (global i64 (i64.const 1))
+  (func $_ZN4core3fmt5write17h0123456789abcdefE (param $out i32) (param i32) (result i32)
+    (global.set $counter (local.get $out))
+    (local.get 1))
+  (func $_Z3addii (param i32 i32) (result i32)
+    (i32.add (local.get 0) (local.get 1)))
+  (func (export "main") (result i32)
+    (call 0 (i32.const 1))
+    (call $_ZN4core3fmt5write17h0123456789abcdefE (i32.const 2) (i32.const 3))))
+"#;
+
+fn symbols() -> Symbols {
+    Symbols::from_buffer(WAT.as_bytes()).unwrap()
+}
+
+#[test]
+fn demangles_rust_and_cpp_names() {
+    assert_eq!(
+        demangle("_ZN4core3fmt5write17h0123456789abcdefE"),
+        "core::fmt::write"
+    );
+    assert_eq!(demangle("_Z3addii"), "add(int, int)");
+    assert_eq!(demangle("main"), "main");
+    let symbols = symbols();
+    assert_eq!(symbols.function(0), Some("env.log"));
+    assert_eq!(symbols.function(1), Some("core::fmt::write"));
+    assert_eq!(symbols.function(2), Some("add(int, int)"));
+    assert_eq!(symbols.function(3), Some("main"));
+    assert_eq!(symbols.function_index("core::fmt::write"), Some(1));
+    assert_eq!(symbols.function_name(4), "function 4");
+}
+
+#[test]
+fn names_events() {
+    let symbols = symbols();
+    let events = [
+        Event::ExportCall {
+            export: 0,
+            args: vec![],
+        },
+        Event::FunctionEntry {
+            func: 3,
+            params: vec![],
+        },
+        Event::ImportCall {
+            func: 0,
+            args: vec![Value::I32(1)],
+        },
+        Event::Call {
+            func: 1,
+            args: vec![Value::I32(2), Value::I32(3)],
+        },
+        Event::GlobalSet {
+            global: 0,
+            value: Value::I32(2),
+        },
+        Event::GlobalGet {
+            global: 1,
+            value: Value::I64(1),
+        },
+        Event::Return {
+            func: 1,
+            results: vec![Value::I32(3)],
+        },
+        Event::Store {
+            opcode: 0x36,
+            addr: 16,
+            value: Value::I32(5),
+        },
+        Event::Trap {
+            depth: 1,
+            frames: vec![TrapFrame {
+                func: 2,
+                offset: 0x40,
+            }],
+        },
+        Event::Checkpoint {
+            globals: vec![(0, Value::I32(2)), (1, Value::I64(1))],
+            tables: vec![],
+            memory: None,
+        },
+    ];
+    let lines: Vec<String> = events
+        .iter()
+        .map(|e| symbols.event(e).to_string())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "export call main []",
+            "enter main []",
+            "call import env.log [i32 1]",
+            "call core::fmt::write [out: i32 2, i32 3]",
+            "global.set counter <- i32 2",
+            "global.get 1 -> i64 1",
+            "return core::fmt::write [i32 3]",
+            "i32.store 0x10 <- i32 5",
+            "trap depth 1 [add(int, int) @0x40]",
+            "checkpoint globals [counter: i32 2, 1: i64 1] tables []",
+        ]
+    );
+    // Without names the events print as they are
+    let unnamed = Symbols::from_buffer(b"(module (func (param i32)))").unwrap();
+    assert_eq!(unnamed.event(&events[3]).to_string(), events[3].to_string());
+}
+
+#[test]
+fn names_frames_and_sites() {
+    let symbols = symbols();
+    let events = [
+        Event::ExportCall {
+            export: 0,
+            args: vec![],
+        },
+        Event::FunctionEntry {
+            func: 3,
+            params: vec![],
+        },
+        Event::ImportCall {
+            func: 0,
+            args: vec![Value::I32(1)],
+        },
+    ];
+    let tree = CallTree::new(&events);
+    assert_eq!(
+        symbols.call_tree(&tree).to_string(),
+        "export main @0 (unterminated)\n\
+         \x20 function main @1 (unterminated)\n\
+         \x20   host import env.log @2 (unterminated)\n\
+         issue: host import env.log entered at event 2 has not returned when the trace ends\n\
+         issue: function main entered at event 1 has not returned when the trace ends\n\
+         issue: export main entered at event 0 has not returned when the trace ends\n"
+    );
+    let wasm = wat::parse_str(WAT).unwrap();
+    let sites = Sites::new(&wasm).unwrap();
+    let add = sites.sites.iter().find(|s| s.func == 2).unwrap();
+    assert_eq!(
+        symbols.site(add),
+        format!("add(int, int) instr 0 @{:#x}", add.offset)
+    );
+}
@@ -6,5 +6,5 @@
         drop
     )
     (table 1 funcref)
-    (elem 0 $foo)
+    (elem (i32.const 0) $foo)
 )
\ No newline at end of file
@@ -0,0 +1,87 @@
+//! Reports of instrumented modules that do not validate.
+
+use std::collections::HashMap;
+
+use r3_tracer::{instrument_wasm, validate, InvalidInstr};
+use walrus::{ir::Drop, FunctionId, Module};
+
+const WAT: &str = r#"
+(module
+  (import "env" "g" (func $g))
+  (func $f (export "f") (param i32) (result i32)
+    (call $g)
+    (i32.add (local.get 0) (i32.const 1))))
+"#;
+
+/// Instruments the module and inserts a `drop` on the empty stack at the
+/// start of `func`, returning the original function indices.
+fn break_function(
+    module: &mut Module,
+    func: impl Fn(&Module, &HashMap<FunctionId, u32>) -> FunctionId,
+) -> HashMap<FunctionId, u32> {
+    let g = module.funcs.by_name("g").unwrap();
+    let f = module.funcs.by_name("f").unwrap();
+    let func_indices = HashMap::from([(g, 0), (f, 1)]);
+    let id = func(module, &func_indices);
+    module
+        .funcs
+        .get_mut(id)
+        .kind
+        .unwrap_local_mut()
+        .builder_mut()
+        .func_body()
+        .instr_at(0, Drop {});
+    func_indices
+}
+
+#[test]
+fn instrumented_module_validates() {
+    let mut module = instrument_wasm(&wat::parse_str(WAT).unwrap()).unwrap();
+    let g = module.funcs.by_name("g").unwrap();
+    let f = module.funcs.by_name("f").unwrap();
+    validate(&mut module, &HashMap::from([(g, 0), (f, 1)])).unwrap();
+}
+
+#[test]
+fn reports_the_original_function() {
+    let mut module = instrument_wasm(&wat::parse_str(WAT).unwrap()).unwrap();
+    let func_indices = break_function(&mut module, |module, _| module.funcs.by_name("f").unwrap());
+    let error = validate(&mut module, &func_indices).unwrap_err();
+    // The imported `r3.check_mem` is emitted behind `env.g`
+    assert_eq!(
+        error.instr,
+        Some(InvalidInstr {
+            func_index: 2,
+            original_func_index: Some(1),
+            instr_index: 0,
+            instr: "Drop".to_string(),
+            inserted: true,
+        })
+    );
+    let message = error.to_string();
+    assert!(message.starts_with("instrumented module is invalid: "));
+    assert!(message.ends_with(
+        " in function 2 (original function 1), instruction 0 `Drop` inserted by the tracer"
+    ));
+}
+
+#[test]
+fn reports_functions_added_by_the_tracer() {
+    let mut module = instrument_wasm(&wat::parse_str(WAT).unwrap()).unwrap();
+    let func_indices = break_function(&mut module, |module, func_indices| {
+        module
+            .funcs
+            .iter_local()
+            .map(|(id, _)| id)
+            .find(|id| !func_indices.contains_key(id))
+            .unwrap()
+    });
+    let error = validate(&mut module, &func_indices).unwrap_err();
+    let instr = error.instr.as_ref().unwrap();
+    assert!(instr.func_index > 2);
+    assert_eq!(instr.original_func_index, None);
+    assert_eq!(instr.instr_index, 0);
+    assert!(error
+        .to_string()
+        .contains(" (added by the tracer), instruction 0 `Drop`"));
+}
@@ -0,0 +1,11 @@
+(module
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (memory (export "memory") 1)
+    (func (param i32) (result i32)
+        i32.const 0
+        local.get 0
+        i32.const 1
+        i32.const 8
+        call $fd_read
+    )
+)